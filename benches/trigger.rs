@@ -0,0 +1,92 @@
+//! Compares triggering events on a `Machine` with its compiled jump table.
+//!
+//! Run with `cargo bench`.
+
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use nanomachine::Machine;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum State {
+    Idle,
+    Header,
+    Length,
+    Payload,
+    Checksum,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Event {
+    Start,
+    Byte,
+    End,
+    Reset,
+}
+
+/// A small framing parser: a header, a length, a payload and a checksum.
+fn parser() -> Machine<State, Event> {
+    use Event::{Byte, End, Reset, Start};
+    use State::{Checksum, Done, Header, Idle, Length, Payload};
+
+    let mut nano = Machine::new(Idle);
+    nano.when(Start, Idle, Header);
+    nano.when(Byte, Header, Length);
+    nano.when(Byte, Length, Payload);
+    nano.when_internal(Byte, Payload);
+    nano.when(End, Payload, Checksum);
+    nano.when(Byte, Checksum, Done);
+    nano.when_iter(
+        Reset,
+        [Idle, Header, Length, Payload, Checksum, Done].map(|s| (s, Idle)),
+    );
+    nano
+}
+
+/// The events of one frame with `len` payload bytes, ending back in `Idle`.
+fn frame(len: usize) -> Vec<Event> {
+    let mut events = vec![Event::Start, Event::Byte, Event::Byte];
+    events.extend(std::iter::repeat_n(Event::Byte, len));
+    events.extend([Event::End, Event::Byte, Event::Reset]);
+    events
+}
+
+fn trigger(c: &mut Criterion) {
+    let events = frame(64);
+    let mut group = c.benchmark_group("trigger");
+
+    let mut nano = parser();
+    group.bench_function("machine", |b| {
+        b.iter(|| {
+            for event in &events {
+                nano.trigger(black_box(event)).unwrap();
+            }
+        });
+    });
+
+    let mut compiled = parser().compile();
+    group.bench_function("compiled", |b| {
+        b.iter(|| {
+            for event in &events {
+                compiled.trigger(black_box(event)).unwrap();
+            }
+        });
+    });
+
+    let mut compiled = parser().compile();
+    let ids: Vec<_> =
+        events.iter().map(|e| compiled.event_id(e).unwrap()).collect();
+    group.bench_function("compiled by id", |b| {
+        b.iter(|| {
+            for &id in &ids {
+                compiled.trigger_id(black_box(id)).unwrap();
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, trigger);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+//! [`arbitrary`] support generating event sequences.
+//!
+//! Available with the `arbitrary` feature.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use ::arbitrary::{Result, Unstructured};
+
+use crate::{Machine, walk::Sequences};
+
+/// Generate a sequence of events that is valid for `machine`, starting from
+/// its current state, from the fuzzer's input.
+///
+/// Sequences only follow the transitions of the machine: guards are not
+/// evaluated, and nothing runs on `machine` itself. A sequence stops early if
+/// it reaches a state without transitions.
+///
+/// Every input yields a valid sequence, so a fuzzer minimising its input also
+/// minimises the trace.
+///
+/// # Errors
+///
+/// Returns an error if `u` cannot provide the data, see [`Unstructured`].
+///
+/// # Examples
+///
+/// ```rust
+/// use arbitrary::Unstructured;
+/// use nanomachine::Machine;
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+/// nano.when("turn knob", "unlocked", "locked");
+///
+/// let mut u = Unstructured::new(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+/// let events = nanomachine::arbitrary::sequence(&nano, &mut u).unwrap();
+///
+/// for event in &events {
+///     nano.trigger(event).unwrap();
+/// }
+/// ```
+pub fn sequence<S, E, C, P>(
+    machine: &Machine<S, E, C, P>,
+    u: &mut Unstructured<'_>,
+) -> Result<Vec<E>>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone + Ord,
+    P: ?Sized,
+{
+    let len = u.arbitrary_len::<u64>()?;
+    let choices =
+        (0..len).map(|_| u.arbitrary()).collect::<Result<Vec<u64>>>()?;
+    Ok(Sequences::new(machine).replay(choices))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use ::arbitrary::Unstructured;
+
+    use super::sequence;
+    use crate::{Rng, testing::turnstile};
+
+    #[test]
+    fn sequences_are_valid() {
+        let nano = turnstile();
+        let mut rng = Rng::new(0);
+
+        for len in 0..64 {
+            let data: Vec<u8> =
+                (0..len * 8).map(|_| rng.next_u64().to_le_bytes()[0]).collect();
+            let events =
+                sequence(&nano, &mut Unstructured::new(&data)).unwrap();
+            let mut nano = nano.clone();
+            assert!(events.iter().all(|event| nano.trigger(event).is_ok()));
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        let events =
+            sequence(&turnstile(), &mut Unstructured::new(&[])).unwrap();
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn zeroed_input_picks_the_smallest_events() {
+        let mut data = [0; 64];
+        // The length of the sequence is read from the end of the input.
+        data[63] = 0xff;
+        let events =
+            sequence(&turnstile(), &mut Unstructured::new(&data)).unwrap();
+
+        assert!(!events.is_empty());
+        for (i, event) in events.iter().enumerate() {
+            let expected = if i % 2 == 0 { "insert coin" } else { "turn knob" };
+            assert_eq!(*event, expected);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use alloc::{
+    boxed::Box,
+    rc::{Rc, Weak},
+};
+use core::{any::Any, error::Error};
+
+/// Any `Fn` that takes an event, a payload and the machine's context as input.
+///
+/// Infallible callbacks are stored the same way and always return `Ok(())`.
+type CallbackFn<E, C, P> =
+    dyn Fn(E, &P, &mut C) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+/// A callback shared by the clones of a machine.
+pub(crate) type Callback<E, C, P> = Rc<CallbackFn<E, C, P>>;
+
+/// A handle identifying a callback registered on a [`Machine`].
+///
+/// Every registration function returns one, and it can later be passed to
+/// [`Machine::remove_callback`] or [`Machine::guard_callback`].
+///
+/// [`Machine`]: crate::Machine
+/// [`Machine::remove_callback`]: crate::Machine::remove_callback
+/// [`Machine::guard_callback`]: crate::Machine::guard_callback
+#[derive(Debug, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CallbackId(pub(crate) u64);
+
+/// A guard that owns a callback and unregisters it when dropped.
+///
+/// Obtained from [`Machine::guard_callback`]. The machine only keeps a weak
+/// reference to a guarded callback, so dropping the guard drops the callback
+/// and everything it captured, and the callback stops firing right away.
+///
+/// [`Machine::guard_callback`]: crate::Machine::guard_callback
+#[derive(Debug)]
+#[must_use = "the callback is unregistered as soon as the guard is dropped"]
+pub struct CallbackGuard {
+    id: CallbackId,
+    _callback: Box<dyn Any>,
+}
+
+impl CallbackGuard {
+    pub(crate) fn new(id: CallbackId, callback: Box<dyn Any>) -> Self {
+        CallbackGuard { id, _callback: callback }
+    }
+
+    /// The id of the guarded callback.
+    #[inline]
+    #[must_use]
+    pub fn id(&self) -> CallbackId {
+        self.id
+    }
+}
+
+/// A callback stored in a machine along with its bookkeeping.
+pub(crate) struct Registered<E, C, P: ?Sized> {
+    pub(crate) id: CallbackId,
+    callback: Held<E, C, P>,
+}
+
+/// How a machine holds on to a callback.
+enum Held<E, C, P: ?Sized> {
+    Owned(Callback<E, C, P>),
+    /// Owned by a [`CallbackGuard`].
+    Guarded(Weak<CallbackFn<E, C, P>>),
+}
+
+impl<E, C, P: ?Sized> Clone for Registered<E, C, P> {
+    fn clone(&self) -> Self {
+        let callback = match &self.callback {
+            Held::Owned(callback) => Held::Owned(callback.clone()),
+            Held::Guarded(callback) => Held::Guarded(callback.clone()),
+        };
+        Registered { id: self.id, callback }
+    }
+}
+
+impl<E, C, P: ?Sized> Registered<E, C, P> {
+    pub(crate) fn new(id: CallbackId, callback: Callback<E, C, P>) -> Self {
+        Registered { id, callback: Held::Owned(callback) }
+    }
+
+    /// The callback, unless its guard is gone.
+    pub(crate) fn callback(&self) -> Option<Callback<E, C, P>> {
+        match &self.callback {
+            Held::Owned(callback) => Some(callback.clone()),
+            Held::Guarded(callback) => callback.upgrade(),
+        }
+    }
+
+    /// Whether the callback should still fire.
+    pub(crate) fn is_live(&self) -> bool {
+        match &self.callback {
+            Held::Owned(_) => true,
+            Held::Guarded(callback) => callback.strong_count() > 0,
+        }
+    }
+
+    /// Hand the callback over to a guard, keeping only a weak reference to
+    /// it. Returns `None` if it is already guarded.
+    pub(crate) fn guard(&mut self) -> Option<CallbackGuard>
+    where
+        E: 'static,
+        C: 'static,
+        P: 'static,
+    {
+        let Held::Owned(callback) = &self.callback else {
+            return None;
+        };
+        // Wrap the callback so the guard holds the only strong reference,
+        // even when clones of the machine made before share the callback.
+        let callback = callback.clone();
+        let callback: Callback<E, C, P> =
+            Rc::new(move |event, payload, context| {
+                callback(event, payload, context)
+            });
+        self.callback = Held::Guarded(Rc::downgrade(&callback));
+        Some(CallbackGuard::new(self.id, Box::new(callback)))
+    }
+}
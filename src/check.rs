@@ -0,0 +1,815 @@
+use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use core::{
+    fmt::{Debug, Display, Formatter},
+    hash::Hash,
+};
+
+use hashbrown::HashMap;
+
+use crate::Machine;
+
+/// A property of a machine's states and of the paths between them, to be
+/// checked with [`Machine::check`].
+///
+/// Formulas are built from predicates on states with the usual boolean
+/// connectives and the path operators of CTL, where `E` means "along some
+/// path" and `A` "along every path":
+///
+/// - [`Formula::ex`] and [`Formula::ax`]: after some or every next event.
+/// - [`Formula::ef`] and [`Formula::af`]: eventually.
+/// - [`Formula::eg`] and [`Formula::ag`]: always.
+/// - [`Formula::eu`] and [`Formula::au`]: one holds until the other does.
+///
+/// LTL-style properties, which speak about every path, are written with the
+/// `A` operators, e.g. `G(p -> F q)` becomes `AG(p -> AF q)`.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Formula, Machine};
+///
+/// let mut order = Machine::new("created");
+/// order.when("pay", "created", "paid");
+/// order.when("ship", "paid", "shipped");
+/// order.when("deliver", "shipped", "delivered");
+/// order.when("cancel", "created", "cancelled");
+/// order.set_accepting("delivered");
+/// order.set_accepting("cancelled");
+///
+/// // Delivered is never reached without passing through Paid.
+/// let paid = Formula::state(|s: &&str| *s == "paid");
+/// let delivered = Formula::state(|s: &&str| *s == "delivered");
+/// let safety = Formula::eu(paid.not(), delivered).not();
+/// assert!(order.check(&safety, 10).is_ok());
+///
+/// // Every reachable state can eventually reach a final state.
+/// let liveness = Formula::ag(Formula::ef(Formula::accepting()));
+/// assert!(order.check(&liveness, 10).is_ok());
+/// ```
+pub struct Formula<S> {
+    node: Node<S>,
+}
+
+/// A predicate on states.
+type Predicate<S> = Rc<dyn Fn(&S) -> bool>;
+
+enum Node<S> {
+    True,
+    State(Predicate<S>),
+    Accepting,
+    Not(Box<Formula<S>>),
+    And(Box<Formula<S>>, Box<Formula<S>>),
+    Or(Box<Formula<S>>, Box<Formula<S>>),
+    Ex(Box<Formula<S>>),
+    Eu(Box<Formula<S>>, Box<Formula<S>>),
+    Au(Box<Formula<S>>, Box<Formula<S>>),
+    Eg(Box<Formula<S>>),
+}
+
+impl<S> Formula<S> {
+    fn new(node: Node<S>) -> Self {
+        Formula { node }
+    }
+
+    /// Holds in every state.
+    #[must_use]
+    pub fn truth() -> Self {
+        Formula::new(Node::True)
+    }
+
+    /// Holds in the states for which `predicate` returns `true`.
+    pub fn state<F>(predicate: F) -> Self
+    where
+        F: Fn(&S) -> bool + 'static,
+    {
+        Formula::new(Node::State(Rc::new(predicate)))
+    }
+
+    /// Holds in the machine's [accepting](Machine::set_accepting) states.
+    #[must_use]
+    pub fn accepting() -> Self {
+        Formula::new(Node::Accepting)
+    }
+
+    /// Holds where `self` doesn't.
+    #[must_use]
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Formula::new(Node::Not(Box::new(self)))
+    }
+
+    /// Holds where both `self` and `other` hold.
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        Formula::new(Node::And(Box::new(self), Box::new(other)))
+    }
+
+    /// Holds where `self`, `other` or both hold.
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        Formula::new(Node::Or(Box::new(self), Box::new(other)))
+    }
+
+    /// Holds where `other` holds whenever `self` does.
+    #[must_use]
+    pub fn implies(self, other: Self) -> Self {
+        self.not().or(other)
+    }
+
+    /// Holds in the states where some event leads to a state where `f`
+    /// holds.
+    #[must_use]
+    pub fn ex(f: Self) -> Self {
+        Formula::new(Node::Ex(Box::new(f)))
+    }
+
+    /// Holds in the states where every event leads to a state where `f`
+    /// holds. Also holds in states without transitions.
+    #[must_use]
+    pub fn ax(f: Self) -> Self {
+        Formula::ex(f.not()).not()
+    }
+
+    /// Holds in the states from which some path reaches a state where `f`
+    /// holds.
+    #[must_use]
+    pub fn ef(f: Self) -> Self {
+        Formula::eu(Formula::truth(), f)
+    }
+
+    /// Holds in the states from which every path reaches a state where `f`
+    /// holds.
+    #[must_use]
+    pub fn af(f: Self) -> Self {
+        Formula::au(Formula::truth(), f)
+    }
+
+    /// Holds in the states from which some path stays in states where `f`
+    /// holds.
+    #[must_use]
+    pub fn eg(f: Self) -> Self {
+        Formula::new(Node::Eg(Box::new(f)))
+    }
+
+    /// Holds in the states from which every path stays in states where `f`
+    /// holds.
+    #[must_use]
+    pub fn ag(f: Self) -> Self {
+        Formula::ef(f.not()).not()
+    }
+
+    /// Holds in the states from which some path reaches a state where `b`
+    /// holds, going only through states where `a` holds.
+    #[must_use]
+    pub fn eu(a: Self, b: Self) -> Self {
+        Formula::new(Node::Eu(Box::new(a), Box::new(b)))
+    }
+
+    /// Holds in the states from which every path reaches a state where `b`
+    /// holds, going only through states where `a` holds.
+    #[must_use]
+    pub fn au(a: Self, b: Self) -> Self {
+        Formula::new(Node::Au(Box::new(a), Box::new(b)))
+    }
+
+    /// A key identifying this formula during a check.
+    fn key(&self) -> usize {
+        core::ptr::from_ref(self) as usize
+    }
+}
+
+impl<S> Debug for Formula<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match &self.node {
+            Node::True => write!(f, "true"),
+            Node::State(_) => write!(f, "state(..)"),
+            Node::Accepting => write!(f, "accepting"),
+            Node::Not(a) => write!(f, "!{a:?}"),
+            Node::And(a, b) => write!(f, "({a:?} & {b:?})"),
+            Node::Or(a, b) => write!(f, "({a:?} | {b:?})"),
+            Node::Ex(a) => write!(f, "EX {a:?}"),
+            Node::Eu(a, b) => write!(f, "E[{a:?} U {b:?}]"),
+            Node::Au(a, b) => write!(f, "A[{a:?} U {b:?}]"),
+            Node::Eg(a) => write!(f, "EG {a:?}"),
+        }
+    }
+}
+
+/// A path showing why a property checked with [`Machine::check`] fails.
+///
+/// It starts at the machine's current state and ends in the state where the
+/// failure shows, e.g. a reachable state where an [`ag`](Formula::ag)
+/// property is broken. When the failure shows in the current state, the path
+/// has no steps.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Violation<S, E> {
+    start: S,
+    steps: Vec<(E, S)>,
+}
+
+impl<S, E> Violation<S, E> {
+    /// The events along the path.
+    pub fn events(&self) -> impl Iterator<Item = &E> {
+        self.steps.iter().map(|(event, _)| event)
+    }
+
+    /// The states along the path, starting with the machine's current state.
+    pub fn states(&self) -> impl Iterator<Item = &S> {
+        core::iter::once(&self.start).chain(self.steps.iter().map(|(_, s)| s))
+    }
+
+    /// The state the path ends in.
+    #[must_use]
+    pub fn state(&self) -> &S {
+        self.steps.last().map_or(&self.start, |(_, state)| state)
+    }
+}
+
+impl<S: Debug, E: Debug> Display for Violation<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:?}", self.start)?;
+        for (event, state) in &self.steps {
+            write!(f, " --{event:?}--> {state:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Check that `formula` holds in the current state, with each path
+    /// operator looking at most `depth` events ahead.
+    ///
+    /// Paths are cut after `depth` events, so [`Formula::ef`] only finds
+    /// states reachable within `depth` events, and [`Formula::af`] requires
+    /// its property to hold within `depth` events. When `depth` is at least
+    /// the number of states, [`Formula::ag`], [`Formula::ef`] and the
+    /// `until` operators give the same results as without a bound.
+    ///
+    /// Like the other operations treating machines as automata, this only
+    /// looks at transitions and accepting states: guards are not evaluated
+    /// and no callbacks run.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Violation`] if the formula doesn't hold, whose path leads
+    /// to where the failure shows, as far as it can be shown by a single path.
+    pub fn check(
+        &self,
+        formula: &Formula<S>,
+        depth: usize,
+    ) -> Result<(), Violation<S, E>> {
+        let mut checker = Checker::new(self, depth);
+        if checker.holds(formula, 0, depth) {
+            return Ok(());
+        }
+        let mut steps = Vec::new();
+        checker.explain(formula, 0, depth, &mut steps);
+        let steps = steps
+            .into_iter()
+            .map(|(e, s)| {
+                (checker.events[e].clone(), checker.states[s].clone())
+            })
+            .collect();
+        Err(Violation { start: self.state.clone(), steps })
+    }
+}
+
+/// The reachable states of a machine, numbered from 0 for the current state,
+/// along with the truth values computed so far.
+struct Checker<'m, S, E, C, P: ?Sized> {
+    machine: &'m Machine<S, E, C, P>,
+    /// How many events each path operator looks ahead.
+    depth: usize,
+    states: Vec<&'m S>,
+    events: Vec<&'m E>,
+    /// The `(event, target)` pairs leaving each state.
+    outgoing: Vec<Vec<(usize, usize)>>,
+    /// The truth value of each formula checked so far in every state, for
+    /// each number of events looked ahead. Levels past the last are the same
+    /// as the last one.
+    labels: HashMap<usize, Vec<Vec<bool>>>,
+}
+
+impl<'m, S, E, C, P> Checker<'m, S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    fn new(machine: &'m Machine<S, E, C, P>, depth: usize) -> Self {
+        let events: Vec<&E> = machine.events().collect();
+        let mut states = vec![&machine.state];
+        let mut index = HashMap::new();
+        index.insert(&machine.state, 0);
+        let mut outgoing = Vec::new();
+
+        let mut next = 0;
+        while let Some(&state) = states.get(next) {
+            let mut edges = Vec::new();
+            for (event, &e) in events.iter().enumerate() {
+                if let Some(target) = machine.target(e, state) {
+                    let to = *index.entry(target).or_insert_with(|| {
+                        states.push(target);
+                        states.len() - 1
+                    });
+                    edges.push((event, to));
+                }
+            }
+            outgoing.push(edges);
+            next += 1;
+        }
+
+        Checker {
+            machine,
+            depth,
+            states,
+            events,
+            outgoing,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Whether `formula` holds in `state` when looking `ahead` events ahead.
+    fn holds(
+        &mut self,
+        formula: &Formula<S>,
+        state: usize,
+        ahead: usize,
+    ) -> bool {
+        let levels = self.label(formula);
+        levels[ahead.min(levels.len() - 1)][state]
+    }
+
+    /// The truth values of `formula` in every state, looking `depth` events
+    /// ahead.
+    fn top(&mut self, formula: &Formula<S>) -> Vec<bool> {
+        let levels = self.label(formula);
+        levels[levels.len() - 1].clone()
+    }
+
+    /// Compute the truth values of `formula` in every state, one level per
+    /// number of events looked ahead.
+    ///
+    /// The path operators are computed one level at a time from the one
+    /// before, stopping early once a level repeats, since then every level
+    /// after it is the same. This happens after at most as many levels as
+    /// there are states, because each level only adds states to the previous
+    /// one, or only removes them.
+    fn label(&mut self, formula: &Formula<S>) -> &[Vec<bool>] {
+        let key = formula.key();
+        if !self.labels.contains_key(&key) {
+            let levels = self.compute(formula);
+            self.labels.insert(key, levels);
+        }
+        &self.labels[&key]
+    }
+
+    fn compute(&mut self, formula: &Formula<S>) -> Vec<Vec<bool>> {
+        let states = 0..self.states.len();
+        let level = match &formula.node {
+            Node::True => vec![true; self.states.len()],
+            Node::State(predicate) => {
+                states.map(|s| predicate(self.states[s])).collect()
+            }
+            Node::Accepting => states
+                .map(|s| self.machine.is_accepting(self.states[s]))
+                .collect(),
+            Node::Not(inner) => {
+                self.top(inner).into_iter().map(|value| !value).collect()
+            }
+            Node::And(left, right) => {
+                let left = self.top(left);
+                let right = self.top(right);
+                states.map(|s| left[s] && right[s]).collect()
+            }
+            Node::Or(left, right) => {
+                let left = self.top(left);
+                let right = self.top(right);
+                states.map(|s| left[s] || right[s]).collect()
+            }
+            Node::Ex(inner) => {
+                let inner = self.top(inner);
+                states
+                    .map(|s| {
+                        self.depth > 0
+                            && self.outgoing[s].iter().any(|&(_, to)| inner[to])
+                    })
+                    .collect()
+            }
+            Node::Eu(until, reached) => {
+                let until = self.top(until);
+                let reached = self.top(reached);
+                return self.iterate(reached.clone(), |outgoing, before| {
+                    (0..before.len())
+                        .map(|s| {
+                            reached[s]
+                                || until[s]
+                                    && outgoing[s]
+                                        .iter()
+                                        .any(|&(_, to)| before[to])
+                        })
+                        .collect()
+                });
+            }
+            Node::Au(until, reached) => {
+                let until = self.top(until);
+                let reached = self.top(reached);
+                return self.iterate(reached.clone(), |outgoing, before| {
+                    (0..before.len())
+                        .map(|s| {
+                            reached[s]
+                                || until[s]
+                                    && !outgoing[s].is_empty()
+                                    && outgoing[s]
+                                        .iter()
+                                        .all(|&(_, to)| before[to])
+                        })
+                        .collect()
+                });
+            }
+            Node::Eg(inner) => {
+                let inner = self.top(inner);
+                return self.iterate(inner.clone(), |outgoing, before| {
+                    (0..before.len())
+                        .map(|s| {
+                            inner[s]
+                                && (outgoing[s].is_empty()
+                                    || outgoing[s]
+                                        .iter()
+                                        .any(|&(_, to)| before[to]))
+                        })
+                        .collect()
+                });
+            }
+        };
+        vec![level]
+    }
+
+    /// Compute up to `depth` levels after `first`, each from the one before
+    /// with `step`, until a level repeats.
+    fn iterate<F>(&self, first: Vec<bool>, step: F) -> Vec<Vec<bool>>
+    where
+        F: Fn(&[Vec<(usize, usize)>], &[bool]) -> Vec<bool>,
+    {
+        let mut levels = vec![first];
+        for _ in 0..self.depth {
+            let next = step(&self.outgoing, &levels[levels.len() - 1]);
+            if next == levels[levels.len() - 1] {
+                break;
+            }
+            levels.push(next);
+        }
+        levels
+    }
+
+    /// The first `(event, target)` step out of `state` whose target passes
+    /// `test` with one event less to look ahead, if any.
+    fn next<F>(
+        &mut self,
+        state: usize,
+        ahead: usize,
+        mut test: F,
+    ) -> Option<(usize, usize)>
+    where
+        F: FnMut(&mut Self, usize, usize) -> bool,
+    {
+        if ahead == 0 {
+            return None;
+        }
+        for i in 0..self.outgoing[state].len() {
+            let (event, to) = self.outgoing[state][i];
+            if test(self, to, ahead - 1) {
+                return Some((event, to));
+            }
+        }
+        None
+    }
+
+    /// Append to `steps` a path from `state` showing why `formula` has the
+    /// truth value it has there, when a single path can show it.
+    fn explain(
+        &mut self,
+        formula: &Formula<S>,
+        mut state: usize,
+        mut ahead: usize,
+        steps: &mut Vec<(usize, usize)>,
+    ) {
+        let value = self.holds(formula, state, ahead);
+        match &formula.node {
+            Node::True | Node::State(_) | Node::Accepting => {}
+            Node::Not(inner) => self.explain(inner, state, self.depth, steps),
+            Node::And(left, right) | Node::Or(left, right) => {
+                // Explain a part that has the same value as the whole,
+                // preferring one that needs a path to be explained.
+                let before = steps.len();
+                for part in [left, right] {
+                    if self.holds(part, state, self.depth) == value {
+                        self.explain(part, state, self.depth, steps);
+                        if steps.len() > before {
+                            return;
+                        }
+                    }
+                }
+            }
+            Node::Ex(inner) => {
+                // Only a successor where `inner` holds can be shown, not
+                // that there is none.
+                if !value {
+                    return;
+                }
+                if let Some((event, to)) =
+                    self.next(state, ahead, |checker, to, _| {
+                        checker.holds(inner, to, checker.depth)
+                    })
+                {
+                    steps.push((event, to));
+                    self.explain(inner, to, self.depth, steps);
+                }
+            }
+            Node::Eu(_, reached) => {
+                if !value {
+                    return;
+                }
+                // Follow a path through `until` states to a `reached` state,
+                // looking no further ahead than needed so it doesn't go round
+                // a loop.
+                ahead = ahead.min(self.label(formula).len() - 1);
+                while !self.holds(reached, state, self.depth) {
+                    let Some((event, to)) =
+                        self.next(state, ahead, |checker, to, ahead| {
+                            checker.holds(formula, to, ahead)
+                        })
+                    else {
+                        return;
+                    };
+                    steps.push((event, to));
+                    state = to;
+                    ahead -= 1;
+                }
+                self.explain(reached, state, self.depth, steps);
+            }
+            Node::Au(until, _) => {
+                if value {
+                    return;
+                }
+                // Follow a path that never gets to `reached`, until it leaves
+                // the `until` states or ends.
+                while self.holds(until, state, self.depth) {
+                    let Some((event, to)) =
+                        self.next(state, ahead, |checker, to, ahead| {
+                            !checker.holds(formula, to, ahead)
+                        })
+                    else {
+                        return;
+                    };
+                    steps.push((event, to));
+                    state = to;
+                    ahead -= 1;
+                }
+                self.explain(until, state, self.depth, steps);
+            }
+            Node::Eg(inner) => {
+                if value {
+                    return;
+                }
+                // Every path leaves the `inner` states, follow any of them
+                // until it does.
+                while self.holds(inner, state, self.depth) {
+                    let Some((event, to)) =
+                        self.next(state, ahead, |_, _, _| true)
+                    else {
+                        return;
+                    };
+                    steps.push((event, to));
+                    state = to;
+                    ahead -= 1;
+                }
+                self.explain(inner, state, self.depth, steps);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec::Vec};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum Order {
+        Created,
+        Paid,
+        Shipped,
+        Delivered,
+        Cancelled,
+    }
+
+    use Order::{Cancelled, Created, Delivered, Paid, Shipped};
+
+    fn order() -> Machine<Order, &'static str> {
+        let mut nano = Machine::new(Created);
+        nano.when("pay", Created, Paid);
+        nano.when("ship", Paid, Shipped);
+        nano.when("deliver", Shipped, Delivered);
+        nano.when_iter("cancel", [(Created, Cancelled), (Paid, Cancelled)]);
+        nano.set_accepting(Delivered);
+        nano.set_accepting(Cancelled);
+        nano
+    }
+
+    fn is(state: Order) -> Formula<Order> {
+        Formula::state(move |s| *s == state)
+    }
+
+    /// Delivered is never reached without passing through Paid.
+    fn paid_before_delivered() -> Formula<Order> {
+        Formula::eu(is(Paid).not(), is(Delivered)).not()
+    }
+
+    fn path(violation: &Violation<Order, &'static str>) -> Vec<&'static str> {
+        violation.events().copied().collect()
+    }
+
+    #[test]
+    fn safety_holds() {
+        assert!(order().check(&paid_before_delivered(), 10).is_ok());
+    }
+
+    #[test]
+    fn safety_counterexample() {
+        let mut nano = order();
+        nano.when("ship", Created, Shipped);
+
+        let violation = nano.check(&paid_before_delivered(), 10).unwrap_err();
+        assert_eq!(path(&violation), ["ship", "deliver"]);
+        assert_eq!(*violation.state(), Delivered);
+        assert_eq!(
+            violation.states().copied().collect::<Vec<_>>(),
+            [Created, Shipped, Delivered]
+        );
+    }
+
+    #[test]
+    fn liveness_holds() {
+        let live = Formula::ag(Formula::ef(Formula::accepting()));
+        assert!(order().check(&live, 10).is_ok());
+    }
+
+    #[test]
+    fn liveness_counterexample() {
+        let mut nano = order();
+        nano.when("wait", Paid, Paid);
+
+        // Every path eventually reaches a final state.
+        let eventually = Formula::af(Formula::accepting());
+        let violation = nano.check(&eventually, 10).unwrap_err();
+        assert!(violation.states().all(|s| !nano.is_accepting(s)));
+        assert_eq!(violation.events().count(), 10);
+    }
+
+    #[test]
+    fn nested_operators_look_ahead_from_their_own_state() {
+        let mut nano = order();
+        nano.when("return", Shipped, Created);
+
+        // Every reachable state can reach a final state, even after going
+        // round the loop more than `depth` events.
+        let live = Formula::ag(Formula::ef(Formula::accepting()));
+        assert!(nano.check(&live, 4).is_ok());
+    }
+
+    #[test]
+    fn fails_in_current_state() {
+        let mut nano = Machine::new(Created);
+        nano.when("pay", Created, Paid);
+
+        let live = Formula::ag(Formula::ef(Formula::accepting()));
+        let violation = nano.check(&live, 10).unwrap_err();
+        assert_eq!(violation.events().count(), 0);
+        assert_eq!(*violation.state(), Created);
+        assert_eq!(violation.to_string(), "Created");
+    }
+
+    #[test]
+    fn unreachable_final_state() {
+        let mut nano = Machine::new(Created);
+        nano.when("pay", Created, Paid);
+        nano.when("ship", Paid, Shipped);
+        nano.when("deliver", Shipped, Delivered);
+        nano.when("refund", Paid, Cancelled);
+        nano.when("retry", Cancelled, Cancelled);
+        nano.set_accepting(Delivered);
+
+        let live = Formula::ag(Formula::ef(Formula::accepting()));
+        let violation = nano.check(&live, 10).unwrap_err();
+        assert_eq!(path(&violation), ["pay", "refund"]);
+        assert_eq!(*violation.state(), Cancelled);
+    }
+
+    #[test]
+    fn next() {
+        let nano = order();
+
+        assert!(nano.check(&Formula::ex(is(Paid)), 1).is_ok());
+        assert!(nano.check(&Formula::ax(is(Paid)), 1).is_err());
+        assert!(nano.check(&Formula::ex(is(Paid)), 0).is_err());
+
+        let violation = nano.check(&Formula::ax(is(Paid)), 1).unwrap_err();
+        assert_eq!(path(&violation), ["cancel"]);
+    }
+
+    #[test]
+    fn bounded_eventually() {
+        let nano = order();
+        let delivered = Formula::ef(is(Delivered));
+
+        assert!(nano.check(&delivered, 3).is_ok());
+        assert!(nano.check(&delivered, 2).is_err());
+    }
+
+    #[test]
+    fn always_globally() {
+        let nano = order();
+        let violation =
+            nano.check(&Formula::ag(is(Delivered).not()), 10).unwrap_err();
+
+        assert_eq!(path(&violation), ["pay", "ship", "deliver"]);
+    }
+
+    #[test]
+    fn exists_globally() {
+        let mut nano = order();
+        nano.when("wait", Paid, Paid);
+
+        let stays_unfinished = Formula::eg(Formula::accepting().not());
+        assert!(nano.check(&stays_unfinished, 10).is_ok());
+        assert!(order().check(&stays_unfinished, 10).is_err());
+        assert!(order().check(&stays_unfinished, 2).is_ok());
+    }
+
+    #[test]
+    fn until() {
+        let nano = order();
+        let unpaid = is(Created);
+        let settled = is(Paid).or(is(Cancelled));
+
+        assert!(nano.check(&Formula::au(unpaid, settled), 10).is_ok());
+
+        let violation =
+            nano.check(&Formula::au(is(Created), is(Paid)), 10).unwrap_err();
+        assert_eq!(path(&violation), ["cancel"]);
+    }
+
+    #[test]
+    fn implications() {
+        let nano = order();
+        let paid_then_delivered =
+            Formula::ag(is(Paid).implies(Formula::af(is(Delivered))));
+
+        let violation = nano.check(&paid_then_delivered, 10).unwrap_err();
+        assert_eq!(path(&violation), ["pay", "cancel"]);
+    }
+
+    #[test]
+    fn large_depth() {
+        let mut nano = Machine::new(0u8);
+        nano.when("tick", 0, 0);
+
+        let reaches_one = Formula::ef(Formula::state(|s: &u8| *s == 1));
+        assert!(nano.check(&reaches_one, 1_000_000).is_err());
+
+        let ticks_forever = Formula::eg(Formula::state(|s: &u8| *s == 0));
+        assert!(nano.check(&ticks_forever, 1_000_000).is_ok());
+    }
+
+    #[test]
+    fn witness_skips_loops() {
+        let mut nano = order();
+        nano.when("wait", Created, Created);
+
+        let violation = nano.check(&Formula::ef(is(Shipped)).not(), 100);
+        assert_eq!(path(&violation.unwrap_err()), ["pay", "ship"]);
+    }
+
+    #[test]
+    fn display() {
+        let mut nano = order();
+        nano.when("ship", Created, Shipped);
+
+        let violation = nano.check(&paid_before_delivered(), 10).unwrap_err();
+        assert_eq!(
+            violation.to_string(),
+            "Created --\"ship\"--> Shipped --\"deliver\"--> Delivered"
+        );
+        assert_eq!(
+            alloc::format!("{:?}", paid_before_delivered()),
+            "!E[!state(..) U state(..)]"
+        );
+    }
+}
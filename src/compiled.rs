@@ -0,0 +1,621 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::{any::Any, fmt::Debug, hash::Hash};
+
+use hashbrown::HashMap;
+
+#[cfg(any(feature = "tracing", feature = "log"))]
+use crate::trace::Tracer;
+use crate::{
+    Definition, Guard, Instance, Machine, MachineError, Payload,
+    TransitionKind, Trigger, TriggerError,
+    callback::{Callback, Registered},
+    observer::{self, Observer},
+};
+
+/// Marks a missing transition in the jump table.
+const NONE: u32 = u32::MAX;
+
+/// The index of an event in a [`Compiled`] machine.
+///
+/// Obtained from [`Compiled::event_id`], and only meaningful for the machine
+/// that handed it out.
+#[derive(Debug, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct EventId(u32);
+
+/// A transition of a [`Compiled`] machine, with its target as an index.
+struct Edge<E, C, P: ?Sized> {
+    target: u32,
+    kind: TransitionKind,
+    guard: Option<Guard<C>>,
+    action: Option<Callback<E, C, P>>,
+}
+
+/// A machine compiled to a dense jump table, for hot loops.
+///
+/// States and events are numbered with `u32` indices, and the transitions
+/// live in a single `Vec` indexed by state and event. Triggering an event by
+/// [`EventId`] does no hashing and clones no state, and triggering it by value
+/// hashes the event once.
+///
+/// A compiled machine is a snapshot: it runs the transitions, guards, actions
+/// and callbacks its [`Machine`] or [`Definition`] had when it was compiled,
+/// notifies the observers that are still alive, reports to `tracing` and
+/// `log` like its machine, and can't be changed afterwards. It doesn't record
+/// [coverage](Machine::record_coverage) or
+/// [metrics](Machine::record_metrics).
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::Machine;
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+/// nano.when("turn knob", "unlocked", "locked");
+///
+/// let mut compiled = nano.compile();
+/// let coin = compiled.event_id(&"insert coin").unwrap();
+///
+/// compiled.trigger_id(coin).unwrap();
+/// assert_eq!(*compiled.state(), "unlocked");
+///
+/// compiled.trigger(&"turn knob").unwrap();
+/// assert_eq!(*compiled.state(), "locked");
+/// ```
+pub struct Compiled<S, E, C = (), P: ?Sized = dyn Any> {
+    states: Vec<S>,
+    events: Vec<E>,
+    event_ids: HashMap<E, u32>,
+    /// The index in `edges` of the transition for each state and event, at
+    /// `state * events.len() + event`, or [`NONE`].
+    table: Vec<u32>,
+    edges: Vec<Edge<E, C, P>>,
+    /// The states each event has a transition from, handed out when the
+    /// event is rejected.
+    valid_from: Vec<Arc<[S]>>,
+    enter: Vec<Vec<Registered<E, C, P>>>,
+    exit: Vec<Vec<Registered<E, C, P>>>,
+    any: Vec<Registered<E, C, P>>,
+    observers: Vec<Observer<S, E>>,
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    tracer: Tracer<S, E>,
+    state: u32,
+    context: C,
+}
+
+impl<S, E, C, P> Definition<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Compile the definition to a dense jump table, starting from
+    /// `instance`.
+    ///
+    /// See [`Compiled`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are `u32::MAX` states, events or transitions or more.
+    pub fn compile(&self, instance: Instance<S, C>) -> Compiled<S, E, C, P> {
+        let (state, context) = instance.into_parts();
+        let mut states = Vec::new();
+        let mut state_ids = HashMap::new();
+        for s in core::iter::once(&state).chain(self.states()) {
+            state_ids.entry(s).or_insert_with(|| {
+                states.push(s.clone());
+                index(states.len() - 1)
+            });
+        }
+        let events: Vec<E> = self.events().cloned().collect();
+        let event_ids: HashMap<E, u32> = events
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.clone(), index(i)))
+            .collect();
+
+        let mut table = Vec::new();
+        table.resize(states.len() * events.len(), NONE);
+        let mut edges = Vec::new();
+        for (event, state_map) in &self.transitions {
+            let e = event_ids[event] as usize;
+            for (from, transition) in state_map {
+                let s = state_ids[from] as usize;
+                table[s * events.len() + e] = index(edges.len());
+                edges.push(Edge {
+                    target: state_ids[&transition.target],
+                    kind: transition.kind,
+                    guard: transition.guard.clone(),
+                    action: transition.action.clone(),
+                });
+            }
+        }
+
+        let mut enter = Vec::new();
+        enter.resize_with(states.len(), Vec::new);
+        let mut exit = Vec::new();
+        exit.resize_with(states.len(), Vec::new);
+        let mut any = Vec::new();
+        for (trigger, registered) in &self.callbacks {
+            let live = registered.iter().filter(|r| r.is_live()).cloned();
+            match trigger {
+                Trigger::Enter(s) => {
+                    if let Some(&s) = state_ids.get(s) {
+                        enter[s as usize].extend(live);
+                    }
+                }
+                Trigger::Exit(s) => {
+                    if let Some(&s) = state_ids.get(s) {
+                        exit[s as usize].extend(live);
+                    }
+                }
+                Trigger::AnyState => any.extend(live),
+            }
+        }
+
+        let valid_from = (0..events.len())
+            .map(|e| {
+                (0..states.len())
+                    .filter(|s| table[s * events.len() + e] != NONE)
+                    .map(|s| states[s].clone())
+                    .collect()
+            })
+            .collect();
+
+        Compiled {
+            states,
+            events,
+            event_ids,
+            table,
+            edges,
+            valid_from,
+            enter,
+            exit,
+            any,
+            observers: self.observers.clone(),
+            #[cfg(any(feature = "tracing", feature = "log"))]
+            tracer: self.tracer,
+            state: 0,
+            context,
+        }
+    }
+}
+
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    C: Clone,
+    P: ?Sized,
+{
+    /// Compile the machine to a dense jump table, starting from its current
+    /// state and context.
+    ///
+    /// See [`Compiled`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are `u32::MAX` states, events or transitions or more.
+    pub fn compile(&self) -> Compiled<S, E, C, P> {
+        let instance =
+            Instance::with_context(self.state.clone(), self.context.clone());
+        self.definition.compile(instance)
+    }
+}
+
+/// Convert a position to a `u32` index.
+fn index(i: usize) -> u32 {
+    u32::try_from(i).ok().filter(|&i| i != NONE).expect("too many entries")
+}
+
+impl<S, E, C, P: ?Sized> Compiled<S, E, C, P> {
+    /// A reference to the current state of the machine.
+    #[inline]
+    pub fn state(&self) -> &S {
+        &self.states[self.state as usize]
+    }
+
+    /// A reference to the machine's context.
+    #[inline]
+    pub fn context(&self) -> &C {
+        &self.context
+    }
+
+    /// A mutable reference to the machine's context.
+    #[inline]
+    pub fn context_mut(&mut self) -> &mut C {
+        &mut self.context
+    }
+
+    /// The event identified by `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` was handed out by another machine with fewer events.
+    #[inline]
+    pub fn event(&self, id: EventId) -> &E {
+        &self.events[id.0 as usize]
+    }
+}
+
+impl<S, E, C, P> Compiled<S, E, C, P>
+where
+    S: Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// The id of `event`, if the machine reacts to it.
+    #[inline]
+    pub fn event_id(&self, event: &E) -> Option<EventId> {
+        self.event_ids.get(event).copied().map(EventId)
+    }
+
+    /// Trigger the given `event` without any payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger`].
+    #[inline]
+    pub fn trigger(&mut self, event: &E) -> Result<(), TriggerError<S, E>>
+    where
+        P: Payload<()>,
+    {
+        self.trigger_with(event, &())
+    }
+
+    /// Trigger the given `event` with an associated payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger_with`].
+    pub fn trigger_with<T>(
+        &mut self,
+        event: &E,
+        payload: &T,
+    ) -> Result<(), TriggerError<S, E>>
+    where
+        T: 'static,
+        P: Payload<T>,
+    {
+        let Some(id) = self.event_id(event) else {
+            let state = self.state().clone();
+            let error = MachineError::EventInvalid;
+            let err = TriggerError::new(error, event.clone(), state, None);
+            return Err(self.rejected(err));
+        };
+        self.fire(id, P::wrap(payload))
+    }
+
+    /// Trigger the event identified by `id` without any payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger`], except for
+    /// [`MachineError::EventInvalid`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` was handed out by another machine with fewer events.
+    #[inline]
+    pub fn trigger_id(&mut self, id: EventId) -> Result<(), TriggerError<S, E>>
+    where
+        P: Payload<()>,
+    {
+        self.trigger_id_with(id, &())
+    }
+
+    /// Trigger the event identified by `id` with an associated payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger_with`], except for
+    /// [`MachineError::EventInvalid`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` was handed out by another machine with fewer events.
+    #[inline]
+    pub fn trigger_id_with<T>(
+        &mut self,
+        id: EventId,
+        payload: &T,
+    ) -> Result<(), TriggerError<S, E>>
+    where
+        T: 'static,
+        P: Payload<T>,
+    {
+        self.fire(id, P::wrap(payload))
+    }
+
+    /// Perform the transition for the event `id` and run the matching
+    /// callbacks and observers, reporting it to `tracing` and `log`.
+    fn fire(
+        &mut self,
+        id: EventId,
+        payload: &P,
+    ) -> Result<(), TriggerError<S, E>> {
+        #[cfg(any(feature = "tracing", feature = "log"))]
+        {
+            let (from, event) = (self.state, &self.events[id.0 as usize]);
+            let span = self.tracer.span(&self.states[from as usize], event);
+            let result = span.in_scope(|| self.apply_notified(id, payload));
+            let error = result.as_ref().err().map(TriggerError::error);
+            let (from, to) = (&self.states[from as usize], self.state());
+            let event = &self.events[id.0 as usize];
+            self.tracer.record(from, to, event, error);
+            result
+        }
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        self.apply_notified(id, payload)
+    }
+
+    /// [`Compiled::apply`], reporting rejections to the observers.
+    fn apply_notified(
+        &mut self,
+        id: EventId,
+        payload: &P,
+    ) -> Result<(), TriggerError<S, E>> {
+        self.apply(id, payload).map_err(|err| self.notify_rejected(err))
+    }
+
+    /// Report `err` to the observers and, since nothing ran, to `tracing` and
+    /// `log`, and hand it back.
+    fn rejected(&self, err: TriggerError<S, E>) -> TriggerError<S, E> {
+        #[cfg(any(feature = "tracing", feature = "log"))]
+        self.tracer.reject(err.event(), &err);
+        self.notify_rejected(err)
+    }
+
+    /// Report `err` to the observers and hand it back.
+    fn notify_rejected(&self, err: TriggerError<S, E>) -> TriggerError<S, E> {
+        observer::notify(&self.observers, |o| {
+            o.on_rejected(err.state(), err.event(), err.error());
+        });
+        err
+    }
+
+    /// [`Compiled::fire`], without reporting rejections to observers.
+    fn apply(
+        &mut self,
+        id: EventId,
+        payload: &P,
+    ) -> Result<(), TriggerError<S, E>> {
+        let event = &self.events[id.0 as usize];
+        let width = self.events.len();
+        let from = self.state as usize;
+        let fail = |error, valid_from| {
+            let state = self.states[from].clone();
+            TriggerError::new(error, event.clone(), state, valid_from)
+        };
+
+        let edge = self.table[from * width + id.0 as usize];
+        if edge == NONE {
+            let valid_from = self.valid_from[id.0 as usize].clone();
+            return Err(fail(MachineError::StateInvalid, Some(valid_from)));
+        }
+        let edge = &self.edges[edge as usize];
+        if let Some(guard) = &edge.guard
+            && !guard(&self.context)
+        {
+            return Err(fail(MachineError::GuardRejected, None));
+        }
+
+        let callback_failed =
+            |err: alloc::boxed::Box<dyn core::error::Error + Send + Sync>| {
+                fail(MachineError::CallbackFailed(err.into()), None)
+            };
+        let external = edge.kind == TransitionKind::External;
+        if external {
+            let state = &self.states[from];
+            observer::dispatch(
+                &self.exit[from],
+                &self.observers,
+                event,
+                payload,
+                &mut self.context,
+                |o| o.on_exit(state, event),
+            )
+            .map_err(callback_failed)?;
+        }
+        if let Some(action) = &edge.action {
+            action(event.clone(), payload, &mut self.context)
+                .map_err(callback_failed)?;
+        }
+        if external {
+            self.state = edge.target;
+            let state = &self.states[edge.target as usize];
+            observer::dispatch(
+                &self.enter[edge.target as usize],
+                &self.observers,
+                event,
+                payload,
+                &mut self.context,
+                |o| o.on_enter(state, event),
+            )
+            .map_err(callback_failed)?;
+        }
+        let (from, to) =
+            (&self.states[from], &self.states[edge.target as usize]);
+        observer::dispatch(
+            &self.any,
+            &self.observers,
+            event,
+            payload,
+            &mut self.context,
+            |o| o.on_transition(from, to, event),
+        )
+        .map_err(callback_failed)?;
+        Ok(())
+    }
+}
+
+impl<S, E, C, P> Debug for Compiled<S, E, C, P>
+where
+    S: Debug,
+    E: Debug,
+    C: Debug,
+    P: ?Sized,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Compiled")
+            .field("state", &self.states[self.state as usize])
+            .field("context", &self.context)
+            .field("events", &self.events)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{Machine, testing::turnstile_with_context};
+
+    /// A turnstile that breaks when kicked, and can only be repaired if it was
+    /// kicked less than twice, as counted by its context.
+    fn turnstile() -> Machine<&'static str, &'static str, u32> {
+        let mut nano = turnstile_with_context(0);
+        nano.when_internal("insert coin", "unlocked");
+        nano.when("kick", "locked", "broken");
+        nano.when_guarded("repair", "broken", "locked", |kicks| *kicks < 2);
+        nano
+    }
+
+    #[test]
+    fn follows_the_machine() {
+        let events = [
+            "insert coin",
+            "insert coin",
+            "turn knob",
+            "kick",
+            "repair",
+            "turn knob",
+            "insert coin",
+            "kick",
+            "repair",
+        ];
+        let mut nano = turnstile();
+        let mut compiled = nano.compile();
+
+        for event in events {
+            let expected = nano.trigger(&event);
+            assert_eq!(compiled.trigger(&event), expected, "{event}");
+            assert_eq!(compiled.state(), nano.state());
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut nano = turnstile();
+        nano.when("repair", "unlocked", "unlocked");
+        let mut compiled = nano.compile();
+
+        let err = compiled.trigger(&"turn knob").unwrap_err();
+        assert_eq!(*err.error(), MachineError::StateInvalid);
+        assert_eq!(err.valid_from(), ["unlocked"]);
+        let again = compiled.trigger(&"turn knob").unwrap_err();
+        assert!(core::ptr::eq(err.valid_from(), again.valid_from()));
+
+        let mut valid_from =
+            compiled.trigger(&"repair").unwrap_err().valid_from().to_vec();
+        valid_from.sort_unstable();
+        assert_eq!(valid_from, ["broken", "unlocked"]);
+
+        let mut empty: Machine<&str, &str> = Machine::new("locked");
+        let err = empty.compile().trigger(&"insert coin").unwrap_err();
+        assert_eq!(*err.error(), MachineError::EventInvalid);
+        assert_eq!(*err.state(), "locked");
+        assert!(empty.trigger(&"insert coin").is_err());
+    }
+
+    #[test]
+    fn guards_see_the_context() {
+        let mut compiled = turnstile().compile();
+        *compiled.context_mut() = 2;
+
+        compiled.trigger(&"kick").unwrap();
+        let err = compiled.trigger(&"repair").unwrap_err();
+        assert_eq!(*err.error(), MachineError::GuardRejected);
+        assert_eq!(*compiled.state(), "broken");
+    }
+
+    #[test]
+    fn callbacks_run_in_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut nano = turnstile();
+        let l = log.clone();
+        nano.on_exit("locked", move |e| l.borrow_mut().push(("exit", e)));
+        let l = log.clone();
+        nano.on_enter("unlocked", move |e| l.borrow_mut().push(("enter", e)));
+        let l = log.clone();
+        nano.on_transition(move |e| l.borrow_mut().push(("any", e)));
+        nano.on_enter_ctx("unlocked", |_, (): &(), coins: &mut u32| {
+            *coins += 1;
+        });
+
+        let mut compiled = nano.compile();
+        compiled.trigger(&"insert coin").unwrap();
+        compiled.trigger(&"insert coin").unwrap();
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                ("exit", "insert coin"),
+                ("enter", "insert coin"),
+                ("any", "insert coin"),
+                ("any", "insert coin"),
+            ]
+        );
+        assert_eq!(*compiled.context(), 1);
+    }
+
+    #[test]
+    fn actions_and_failing_callbacks() {
+        let mut nano: Machine<&str, &str, u32> =
+            Machine::with_context("locked", 0);
+        nano.when_then(
+            "insert coin",
+            "locked",
+            "unlocked",
+            |_, amount: &u32, total: &mut u32| {
+                *total += amount;
+            },
+        );
+        nano.try_on_enter_with("unlocked", |_, _: &u32| {
+            Err(Box::from("jammed"))
+        });
+
+        let mut compiled = nano.compile();
+        let err = compiled.trigger_with(&"insert coin", &25u32).unwrap_err();
+        assert!(matches!(err.error(), MachineError::CallbackFailed(_)));
+        assert_eq!(*compiled.state(), "unlocked");
+        assert_eq!(*compiled.context(), 25);
+    }
+
+    #[test]
+    fn event_ids() {
+        let mut compiled = turnstile().compile();
+        let coin = compiled.event_id(&"insert coin").unwrap();
+        let knob = compiled.event_id(&"turn knob").unwrap();
+
+        assert_eq!(*compiled.event(coin), "insert coin");
+        compiled.trigger_id(coin).unwrap();
+        compiled.trigger_id(knob).unwrap();
+        assert_eq!(*compiled.state(), "locked");
+
+        let empty: Machine<&str, &str> = Machine::new("locked");
+        assert!(empty.compile().event_id(&"insert coin").is_none());
+    }
+
+    #[test]
+    fn compiled_from_a_definition() {
+        let mut definition = Definition::new();
+        definition.when("insert coin", "locked", "unlocked");
+        let mut compiled = definition.compile(Instance::new("locked"));
+
+        compiled.trigger(&"insert coin").unwrap();
+        assert_eq!(*compiled.state(), "unlocked");
+    }
+}
@@ -0,0 +1,323 @@
+use core::hash::Hash;
+
+use hashbrown::HashMap;
+
+use crate::Machine;
+
+/// Counts of the transitions a machine took and the states it entered.
+///
+/// Recording is opt-in: call [`Machine::record_coverage`] and read the counts
+/// back with [`Machine::coverage`]. Counts from several machines, e.g. one per
+/// test, can be combined with [`Coverage::merge`] and compared against a
+/// machine's transition table with [`Coverage::unexercised`].
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Coverage, Machine};
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+/// nano.when("turn knob", "unlocked", "locked");
+/// nano.record_coverage();
+///
+/// nano.trigger(&"insert coin").unwrap();
+///
+/// let coverage = nano.coverage().unwrap();
+/// assert_eq!(coverage.edge_count(&"insert coin", &"locked", &"unlocked"), 1);
+/// assert_eq!(coverage.entered_count(&"unlocked"), 1);
+///
+/// let missing: Vec<_> = coverage.unexercised(&nano).collect();
+/// assert_eq!(missing, [(&"turn knob", &"unlocked", &"locked")]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Coverage<S, E> {
+    edges: HashMap<(E, S, S), u64>,
+    entered: HashMap<S, u64>,
+}
+
+impl<S, E> Default for Coverage<S, E> {
+    fn default() -> Self {
+        Coverage { edges: HashMap::new(), entered: HashMap::new() }
+    }
+}
+
+impl<S: Eq + Hash, E: Eq + Hash> PartialEq for Coverage<S, E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.edges == other.edges && self.entered == other.entered
+    }
+}
+
+impl<S: Eq + Hash, E: Eq + Hash> Eq for Coverage<S, E> {}
+
+impl<S, E> Coverage<S, E>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+{
+    /// Create empty coverage counts.
+    #[must_use]
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    /// Record that `event` moved the machine from `from` to `to`.
+    ///
+    /// `to` is only counted as entered if `entered` is set, which is not the
+    /// case for [internal](crate::TransitionKind::Internal) transitions.
+    pub(crate) fn record(
+        &mut self,
+        event: &E,
+        from: &S,
+        to: &S,
+        entered: bool,
+    ) {
+        let edge = (event.clone(), from.clone(), to.clone());
+        *self.edges.entry(edge).or_default() += 1;
+        if entered {
+            *self.entered.entry(to.clone()).or_default() += 1;
+        }
+    }
+
+    /// How many times `event` moved the machine from `from` to `to`.
+    #[must_use]
+    pub fn edge_count(&self, event: &E, from: &S, to: &S) -> u64 {
+        let edge = (event.clone(), from.clone(), to.clone());
+        self.edges.get(&edge).copied().unwrap_or_default()
+    }
+
+    /// How many times `state` was entered through a transition.
+    ///
+    /// The state a machine starts in only counts once it is entered again.
+    #[must_use]
+    pub fn entered_count(&self, state: &S) -> u64 {
+        self.entered.get(state).copied().unwrap_or_default()
+    }
+
+    /// Returns an iterator over every `(event, from, to)` transition taken at
+    /// least once, along with how many times it was taken.
+    pub fn edges(&self) -> impl Iterator<Item = ((&E, &S, &S), u64)> {
+        self.edges.iter().map(|((e, from, to), count)| ((e, from, to), *count))
+    }
+
+    /// Returns an iterator over every state entered at least once, along with
+    /// how many times it was entered.
+    pub fn entered_states(&self) -> impl Iterator<Item = (&S, u64)> {
+        self.entered.iter().map(|(state, count)| (state, *count))
+    }
+
+    /// Returns an iterator over the transitions of `machine` that were never
+    /// taken, as `(event, from, to)` triples.
+    pub fn unexercised<'m, C, P>(
+        &self,
+        machine: &'m Machine<S, E, C, P>,
+    ) -> impl Iterator<Item = (&'m E, &'m S, &'m S)>
+    where
+        P: ?Sized,
+    {
+        machine
+            .transitions()
+            .filter(|(e, from, to)| self.edge_count(e, from, to) == 0)
+    }
+
+    /// Returns an iterator over the states of `machine` that were never
+    /// entered.
+    pub fn unentered<'m, C, P>(
+        &self,
+        machine: &'m Machine<S, E, C, P>,
+    ) -> impl Iterator<Item = &'m S>
+    where
+        P: ?Sized,
+    {
+        machine.states().filter(|state| self.entered_count(state) == 0)
+    }
+
+    /// Add the counts of `other` to these.
+    pub fn merge(&mut self, other: &Coverage<S, E>) {
+        for (edge, count) in &other.edges {
+            *self.edges.entry(edge.clone()).or_default() += count;
+        }
+        for (state, count) in &other.entered {
+            *self.entered.entry(state.clone()).or_default() += count;
+        }
+    }
+}
+
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Start counting the transitions the machine takes and the states it
+    /// enters.
+    ///
+    /// Does nothing if coverage is already being recorded.
+    pub fn record_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+
+    /// The coverage recorded so far, if [recording](Machine::record_coverage).
+    #[inline]
+    pub fn coverage(&self) -> Option<&Coverage<S, E>> {
+        self.coverage.as_ref()
+    }
+
+    /// Stop recording coverage and return what was recorded.
+    #[inline]
+    pub fn take_coverage(&mut self) -> Option<Coverage<S, E>> {
+        self.coverage.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::testing;
+
+    /// A [`testing::turnstile`] that breaks when kicked, recording coverage.
+    fn turnstile() -> Machine<&'static str, &'static str> {
+        let mut nano = testing::turnstile();
+        nano.when("kick", "locked", "broken");
+        nano.when_internal("insert coin", "unlocked");
+        nano.record_coverage();
+        nano
+    }
+
+    #[test]
+    fn opt_in() {
+        let mut nano = Machine::new("locked");
+        nano.when("insert coin", "locked", "unlocked");
+        nano.trigger(&"insert coin").unwrap();
+
+        assert!(nano.coverage().is_none());
+
+        nano.record_coverage();
+        assert_eq!(nano.coverage(), Some(&Coverage::new()));
+    }
+
+    #[test]
+    fn counts_edges_and_entered_states() {
+        let mut nano = turnstile();
+        nano.trigger(&"insert coin").unwrap();
+        nano.trigger(&"turn knob").unwrap();
+        nano.trigger(&"insert coin").unwrap();
+        nano.trigger(&"insert coin").unwrap();
+
+        let coverage = nano.coverage().unwrap();
+        assert_eq!(
+            coverage.edge_count(&"insert coin", &"locked", &"unlocked"),
+            2
+        );
+        assert_eq!(
+            coverage.edge_count(&"turn knob", &"unlocked", &"locked"),
+            1
+        );
+        assert_eq!(
+            coverage.edge_count(&"insert coin", &"unlocked", &"unlocked"),
+            1
+        );
+        assert_eq!(coverage.entered_count(&"unlocked"), 2);
+        assert_eq!(coverage.entered_count(&"locked"), 1);
+        assert_eq!(coverage.edges().count(), 3);
+        assert_eq!(coverage.entered_states().count(), 2);
+    }
+
+    #[test]
+    fn rejected_events_are_not_counted() {
+        let mut nano = turnstile();
+        assert!(nano.trigger(&"turn knob").is_err());
+
+        assert_eq!(nano.coverage().unwrap().edges().count(), 0);
+    }
+
+    #[test]
+    fn rolled_back_transitions_are_not_counted() {
+        let mut nano = turnstile();
+        nano.try_on_enter_with("locked", |_, (): &()| Err("jammed".into()));
+
+        assert!(nano.trigger_all(&["insert coin", "turn knob"]).is_err());
+        assert_eq!(nano.coverage(), Some(&Coverage::new()));
+
+        nano.trigger(&"insert coin").unwrap();
+        assert!(nano.try_trigger(&"turn knob").is_err());
+
+        let coverage = nano.coverage().unwrap();
+        assert_eq!(coverage.edges().count(), 1);
+        assert_eq!(coverage.entered_states().count(), 1);
+    }
+
+    #[test]
+    fn failed_transitions_that_entered_are_counted() {
+        let mut nano = turnstile();
+        nano.try_on_enter_with("unlocked", |_, (): &()| Err("jammed".into()));
+
+        assert!(nano.trigger(&"insert coin").is_err());
+
+        let coverage = nano.coverage().unwrap();
+        assert_eq!(
+            coverage.edge_count(&"insert coin", &"locked", &"unlocked"),
+            1
+        );
+        assert_eq!(coverage.entered_count(&"unlocked"), 1);
+    }
+
+    #[test]
+    fn unexercised() {
+        let mut nano = turnstile();
+        nano.trigger(&"insert coin").unwrap();
+        nano.trigger(&"turn knob").unwrap();
+
+        let coverage = nano.coverage().unwrap();
+        let mut missing: Vec<_> = coverage.unexercised(&nano).collect();
+        missing.sort_unstable();
+        assert_eq!(
+            missing,
+            [
+                (&"insert coin", &"unlocked", &"unlocked"),
+                (&"kick", &"locked", &"broken"),
+            ]
+        );
+        assert_eq!(coverage.unentered(&nano).collect::<Vec<_>>(), [&"broken"]);
+    }
+
+    #[test]
+    fn merge() {
+        let mut first = turnstile();
+        first.trigger(&"insert coin").unwrap();
+        let mut second = turnstile();
+        second.trigger(&"insert coin").unwrap();
+        second.trigger(&"turn knob").unwrap();
+        let mut third = turnstile();
+        third.trigger(&"kick").unwrap();
+
+        let mut coverage = first.take_coverage().unwrap();
+        coverage.merge(second.coverage().unwrap());
+        coverage.merge(third.coverage().unwrap());
+
+        assert_eq!(
+            coverage.edge_count(&"insert coin", &"locked", &"unlocked"),
+            2
+        );
+        assert_eq!(coverage.entered_count(&"locked"), 1);
+        assert_eq!(coverage.entered_count(&"broken"), 1);
+        assert_eq!(
+            coverage.unexercised(&first).collect::<Vec<_>>(),
+            [(&"insert coin", &"unlocked", &"unlocked")]
+        );
+        assert!(first.coverage().is_none());
+    }
+
+    #[test]
+    fn clones_keep_their_own_coverage() {
+        let mut nano = turnstile();
+        nano.trigger(&"insert coin").unwrap();
+        let mut clone = nano.clone();
+        clone.trigger(&"turn knob").unwrap();
+
+        assert_eq!(nano.coverage().unwrap().edges().count(), 1);
+        assert_eq!(clone.coverage().unwrap().edges().count(), 2);
+    }
+}
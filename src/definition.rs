@@ -0,0 +1,962 @@
+use alloc::{
+    boxed::Box,
+    rc::{Rc, Weak},
+    vec::Vec,
+};
+use core::{any::Any, error::Error, fmt::Debug, hash::Hash};
+
+use hashbrown::{HashMap, HashSet};
+
+#[cfg(any(feature = "tracing", feature = "log"))]
+use crate::trace::Tracer;
+use crate::{
+    CallbackGuard, CallbackId, MachineError, MachineObserver, Payload,
+    Transition, TransitionKind, Transitions, Trigger, TriggerError,
+    callback::{Callback, Registered},
+    observer::{self, Observer},
+};
+
+/// The transitions and callbacks of a state machine, without any state.
+///
+/// A definition is meant to be built once, put behind an [`Rc`] and shared by
+/// many [`Instance`]s, each holding nothing but a current state and a context.
+/// Events are triggered with [`Definition::trigger`], which moves an instance
+/// and runs the callbacks as a [`Machine`](crate::Machine) would.
+///
+/// A [`Machine`](crate::Machine) is a definition bundled with a single
+/// instance. Clones of a machine share their definition until one of them
+/// changes it, see [`Machine::definition`](crate::Machine::definition).
+///
+/// # Examples
+///
+/// ```rust
+/// use std::rc::Rc;
+///
+/// use nanomachine::{Definition, Instance};
+///
+/// let mut definition = Definition::new();
+/// definition.when("pay", "created", "paid");
+/// definition.when("ship", "paid", "shipped");
+/// let definition = Rc::new(definition);
+///
+/// let mut orders: Vec<_> = (0..1000).map(|_| Instance::new("created")).collect();
+/// for order in &mut orders[..10] {
+///     definition.trigger(order, &"pay").unwrap();
+/// }
+///
+/// assert_eq!(*orders[0].state(), "paid");
+/// assert_eq!(*orders[10].state(), "created");
+/// ```
+pub struct Definition<S, E, C = (), P: ?Sized = dyn Any> {
+    pub(crate) transitions: Transitions<S, E, C, P>,
+    pub(crate) accepting: HashSet<S>,
+    pub(crate) callbacks: HashMap<Trigger<S>, Vec<Registered<E, C, P>>>,
+    pub(crate) observers: Vec<Observer<S, E>>,
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    pub(crate) tracer: Tracer<S, E>,
+    next_callback_id: u64,
+}
+
+/// A transition being defined, to give it a guard, an action or make it
+/// internal.
+///
+/// Obtained from [`Machine::transition`](crate::Machine::transition) or
+/// [`Definition::transition`]. The transition is already defined, and each
+/// method changes it in place.
+pub struct TransitionBuilder<'d, S, E, C, P: ?Sized> {
+    state: S,
+    transition: &'d mut Transition<S, E, C, P>,
+}
+
+impl<S, E, C, P> TransitionBuilder<'_, S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Only take the transition if `guard` allows it, see
+    /// [`Machine::when_guarded`](crate::Machine::when_guarded).
+    pub fn guard<G>(&mut self, guard: G) -> &mut Self
+    where
+        G: Fn(&C) -> bool + 'static,
+    {
+        self.transition.guard = Some(Rc::new(guard));
+        self
+    }
+
+    /// Run `action` when the transition is taken, see
+    /// [`Machine::when_then`](crate::Machine::when_then).
+    pub fn then<T, F>(&mut self, action: F) -> &mut Self
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        let action = Definition::<S, E, C, P>::wrap_with_context(action);
+        self.transition.action = Some(action);
+        self
+    }
+
+    /// Make the transition [internal](TransitionKind::Internal), staying in
+    /// the state it starts from without exiting it, whatever its target was.
+    pub fn internal(&mut self) -> &mut Self {
+        self.transition.kind = TransitionKind::Internal;
+        self.transition.target = self.state.clone();
+        self
+    }
+}
+
+/// The current state and context of one machine built from a shared
+/// [`Definition`].
+#[derive(Debug, Clone, Default, Eq, Hash, PartialEq)]
+pub struct Instance<S, C = ()> {
+    state: S,
+    context: C,
+}
+
+impl<S> Instance<S> {
+    /// Create an instance in `initial_state`.
+    pub fn new(initial_state: S) -> Self {
+        Instance { state: initial_state, context: () }
+    }
+}
+
+impl<S, C> Instance<S, C> {
+    /// Create an instance in `initial_state` with the given context.
+    pub fn with_context(initial_state: S, context: C) -> Self {
+        Instance { state: initial_state, context }
+    }
+
+    /// A reference to the current state of the instance.
+    #[inline]
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// A reference to the instance's context.
+    #[inline]
+    pub fn context(&self) -> &C {
+        &self.context
+    }
+
+    /// A mutable reference to the instance's context.
+    #[inline]
+    pub fn context_mut(&mut self) -> &mut C {
+        &mut self.context
+    }
+
+    /// The state and context of the instance.
+    #[inline]
+    pub fn into_parts(self) -> (S, C) {
+        (self.state, self.context)
+    }
+}
+
+impl<S, E> Definition<S, E> {
+    /// Create an empty definition, whose callbacks accept payloads of any
+    /// type.
+    ///
+    /// Use [`Definition::default`] to pick another context or payload type.
+    #[must_use]
+    pub fn new() -> Self {
+        Definition::default()
+    }
+}
+
+impl<S, E, C, P: ?Sized> Default for Definition<S, E, C, P> {
+    fn default() -> Self {
+        Definition {
+            transitions: HashMap::new(),
+            accepting: HashSet::new(),
+            callbacks: HashMap::new(),
+            observers: Vec::new(),
+            #[cfg(any(feature = "tracing", feature = "log"))]
+            tracer: Tracer::default(),
+            next_callback_id: 0,
+        }
+    }
+}
+
+impl<S, E, C, P> Definition<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Returns an iterator over all states known to the definition, see
+    /// [`Machine::states`](crate::Machine::states).
+    pub fn states(&self) -> impl Iterator<Item = &S> {
+        let mut used: HashSet<&S> = self.accepting.iter().collect();
+        for state_map in self.transitions.values() {
+            for (from, transition) in state_map {
+                used.insert(from);
+                used.insert(&transition.target);
+            }
+        }
+        used.into_iter()
+    }
+
+    /// Returns an iterator over all events the definition can react to.
+    #[inline]
+    pub fn events(&self) -> impl Iterator<Item = &E> {
+        self.transitions.keys()
+    }
+
+    /// Returns an iterator over events with a transition defined from `state`.
+    pub fn triggerable_events(&self, state: &S) -> impl Iterator<Item = &E> {
+        self.transitions
+            .iter()
+            .filter(|(_, mp)| mp.contains_key(state))
+            .map(|(e, _)| e)
+    }
+
+    /// Returns an iterator over all transitions as `(event, from, to)`
+    /// triples, see [`Machine::transitions`](crate::Machine::transitions).
+    pub fn transitions(&self) -> impl Iterator<Item = (&E, &S, &S)> {
+        self.transitions.iter().flat_map(|(event, state_map)| {
+            state_map.iter().map(move |(from, t)| (event, from, &t.target))
+        })
+    }
+
+    /// When `event` occurs in `state`, move to `new_state`, see
+    /// [`Machine::when`](crate::Machine::when).
+    pub fn when(&mut self, event: E, state: S, new_state: S) {
+        self.transitions
+            .entry(event)
+            .or_default()
+            .insert(state, Transition::external(new_state));
+    }
+
+    /// Define multiple transitions for a single event, see
+    /// [`Machine::when_iter`](crate::Machine::when_iter).
+    pub fn when_iter<I>(&mut self, event: E, mapping: I)
+    where
+        I: IntoIterator<Item = (S, S)>,
+    {
+        self.transitions.entry(event).or_default().extend(
+            mapping
+                .into_iter()
+                .map(|(from, to)| (from, Transition::external(to))),
+        );
+    }
+
+    /// When `event` occurs in `state`, exit and re-enter `state`, see
+    /// [`Machine::when_self`](crate::Machine::when_self).
+    pub fn when_self(&mut self, event: E, state: S) {
+        self.when(event, state.clone(), state);
+    }
+
+    /// When `event` occurs in `state`, stay in `state` without exiting it, see
+    /// [`Machine::when_internal`](crate::Machine::when_internal).
+    pub fn when_internal(&mut self, event: E, state: S) {
+        self.transition(event, state.clone(), state).internal();
+    }
+
+    /// When `event` occurs in `state`, move to `new_state` if `guard` allows
+    /// it, see [`Machine::when_guarded`](crate::Machine::when_guarded).
+    pub fn when_guarded<G>(
+        &mut self,
+        event: E,
+        state: S,
+        new_state: S,
+        guard: G,
+    ) where
+        G: Fn(&C) -> bool + 'static,
+    {
+        self.transition(event, state, new_state).guard(guard);
+    }
+
+    /// When `event` occurs in `state`, move to `new_state` and run `action`,
+    /// see [`Machine::when_then`](crate::Machine::when_then).
+    pub fn when_then<T, F>(
+        &mut self,
+        event: E,
+        state: S,
+        new_state: S,
+        action: F,
+    ) where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        self.transition(event, state, new_state).then(action);
+    }
+
+    /// When `event` occurs in `state`, move to `new_state`, returning a
+    /// builder to give the transition a guard, an action or make it internal,
+    /// see [`Machine::transition`](crate::Machine::transition).
+    pub fn transition(
+        &mut self,
+        event: E,
+        state: S,
+        new_state: S,
+    ) -> TransitionBuilder<'_, S, E, C, P> {
+        let state_map = self.transitions.entry(event).or_default();
+        let transition = state_map
+            .entry(state.clone())
+            .insert(Transition::external(new_state))
+            .into_mut();
+        TransitionBuilder { state, transition }
+    }
+
+    /// The kind of the transition defined for `event` in `state`, if any.
+    pub fn transition_kind(
+        &self,
+        event: &E,
+        state: &S,
+    ) -> Option<TransitionKind> {
+        self.transitions.get(event)?.get(state).map(|t| t.kind)
+    }
+
+    /// The state `event` leads to from `state`, if a transition is defined.
+    pub(crate) fn target(&self, event: &E, state: &S) -> Option<&S> {
+        self.transitions.get(event)?.get(state).map(|t| &t.target)
+    }
+
+    /// Designate `state` as an accepting, or final, state, see
+    /// [`Machine::set_accepting`](crate::Machine::set_accepting).
+    pub fn set_accepting(&mut self, state: S) {
+        self.accepting.insert(state);
+    }
+
+    /// Whether `state` is an accepting state.
+    #[inline]
+    pub fn is_accepting(&self, state: &S) -> bool {
+        self.accepting.contains(state)
+    }
+
+    /// Returns an iterator over the accepting states.
+    #[inline]
+    pub fn accepting_states(&self) -> impl Iterator<Item = &S> {
+        self.accepting.iter()
+    }
+
+    /// Internal helper to wrap a callback that expects a specific payload type
+    /// `T`.
+    #[doc(hidden)]
+    fn wrap_callback<T, F>(callback: F) -> Callback<E, C, P>
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static,
+    {
+        Self::wrap_fallible(move |evt, p: &T| {
+            callback(evt, p);
+            Ok(())
+        })
+    }
+
+    /// Internal helper to wrap a fallible callback that expects a specific
+    /// payload type `T`.
+    #[doc(hidden)]
+    fn wrap_fallible<T, F>(callback: F) -> Callback<E, C, P>
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        Rc::new(move |evt, payload: &P, _context: &mut C| {
+            match payload.cast() {
+                Some(p) => callback(evt, p),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Internal helper to wrap a callback that expects a specific payload type
+    /// `T` and the machine's context.
+    #[doc(hidden)]
+    fn wrap_with_context<T, F>(callback: F) -> Callback<E, C, P>
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        Rc::new(move |evt, payload: &P, context: &mut C| {
+            if let Some(p) = payload.cast() {
+                callback(evt, p, context);
+            }
+            Ok(())
+        })
+    }
+
+    /// Internal helper to wrap a callback that only cares about the event.
+    #[doc(hidden)]
+    fn wrap_event<F>(callback: F) -> Callback<E, C, P>
+    where
+        F: Fn(E) + 'static,
+    {
+        Rc::new(move |evt, _payload, _context| {
+            callback(evt);
+            Ok(())
+        })
+    }
+
+    /// Store `callback` under `trigger` and hand out a fresh id for it.
+    ///
+    /// Callbacks under `trigger` whose guard is gone are dropped on the way.
+    fn register(
+        &mut self,
+        trigger: Trigger<S>,
+        callback: Callback<E, C, P>,
+    ) -> CallbackId {
+        let id = self.next_id();
+        let registered = self.callbacks.entry(trigger).or_default();
+        registered.retain(Registered::is_live);
+        registered.push(Registered::new(id, callback));
+        id
+    }
+
+    /// Hand out a fresh callback id.
+    fn next_id(&mut self) -> CallbackId {
+        let id = CallbackId(self.next_callback_id);
+        self.next_callback_id += 1;
+        id
+    }
+
+    /// Register `observer` by weak reference, see
+    /// [`Machine::observe`](crate::Machine::observe).
+    pub fn observe<O>(&mut self, observer: &Rc<O>) -> CallbackId
+    where
+        O: MachineObserver<S, E> + 'static,
+    {
+        let id = self.next_id();
+        self.observers.retain(Observer::is_live);
+        let observer: Weak<O> = Rc::downgrade(observer);
+        self.observers.push(Observer { id, observer });
+        id
+    }
+
+    /// Format states and events with `Debug` when reporting to `tracing` and
+    /// `log`, see [`Machine::instrument`](crate::Machine::instrument).
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    pub fn instrument(&mut self)
+    where
+        S: Debug,
+        E: Debug,
+    {
+        self.tracer = Tracer::debug();
+    }
+
+    /// Unregister the callback or observer identified by `id`, see
+    /// [`Machine::remove_callback`](crate::Machine::remove_callback).
+    pub fn remove_callback(&mut self, id: CallbackId) -> bool {
+        self.observers.retain(Observer::is_live);
+        let len = self.observers.len();
+        self.observers.retain(|o| o.id != id);
+        let mut removed = self.observers.len() != len;
+        for registered in self.callbacks.values_mut() {
+            registered.retain(Registered::is_live);
+            let len = registered.len();
+            registered.retain(|r| r.id != id);
+            removed |= registered.len() != len;
+        }
+        self.callbacks.retain(|_, registered| !registered.is_empty());
+        removed
+    }
+
+    /// Unregister every enter and exit callback registered for `state`, see
+    /// [`Machine::clear_callbacks`](crate::Machine::clear_callbacks).
+    pub fn clear_callbacks(&mut self, state: &S) {
+        self.callbacks.retain(|trigger, _| match trigger {
+            Trigger::Enter(s) | Trigger::Exit(s) => s != state,
+            Trigger::AnyState => true,
+        });
+    }
+
+    /// Hand the callback identified by `id` over to the returned guard, see
+    /// [`Machine::guard_callback`](crate::Machine::guard_callback).
+    pub fn guard_callback(&mut self, id: CallbackId) -> Option<CallbackGuard>
+    where
+        E: 'static,
+        C: 'static,
+        P: 'static,
+    {
+        self.callbacks
+            .values_mut()
+            .flatten()
+            .find(|r| r.id == id && r.is_live())?
+            .guard()
+    }
+
+    /// Register a callback for entering `state`, see
+    /// [`Machine::on_enter`](crate::Machine::on_enter).
+    pub fn on_enter<F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        F: Fn(E) + 'static,
+    {
+        self.register(Trigger::Enter(state), Self::wrap_event(callback))
+    }
+
+    /// Register a callback for entering `state` that expects a payload of type
+    /// `T`, see [`Machine::on_enter_with`](crate::Machine::on_enter_with).
+    pub fn on_enter_with<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static,
+    {
+        let callback = Self::wrap_callback(callback);
+        self.register(Trigger::Enter(state), callback)
+    }
+
+    /// Register a callback for exiting `state`, see
+    /// [`Machine::on_exit`](crate::Machine::on_exit).
+    pub fn on_exit<F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        F: Fn(E) + 'static,
+    {
+        self.register(Trigger::Exit(state), Self::wrap_event(callback))
+    }
+
+    /// Register a callback for exiting `state` that expects a payload of type
+    /// `T`, see [`Machine::on_exit_with`](crate::Machine::on_exit_with).
+    pub fn on_exit_with<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static,
+    {
+        let callback = Self::wrap_callback(callback);
+        self.register(Trigger::Exit(state), callback)
+    }
+
+    /// Register a callback to fire on any state transition, see
+    /// [`Machine::on_transition`](crate::Machine::on_transition).
+    pub fn on_transition<F>(&mut self, callback: F) -> CallbackId
+    where
+        F: Fn(E) + 'static + Clone,
+    {
+        self.register(Trigger::AnyState, Self::wrap_event(callback))
+    }
+
+    /// Register a callback to fire on any state transition with a payload of
+    /// type `T`, see
+    /// [`Machine::on_transition_with`](crate::Machine::on_transition_with).
+    pub fn on_transition_with<T, F>(&mut self, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static + Clone,
+    {
+        let callback = Self::wrap_callback(callback);
+        self.register(Trigger::AnyState, callback)
+    }
+
+    /// Register a fallible callback for entering `state`, see
+    /// [`Machine::try_on_enter_with`](crate::Machine::try_on_enter_with).
+    pub fn try_on_enter_with<T, F>(
+        &mut self,
+        state: S,
+        callback: F,
+    ) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        let callback = Self::wrap_fallible(callback);
+        self.register(Trigger::Enter(state), callback)
+    }
+
+    /// Register a fallible callback for exiting `state`, see
+    /// [`Machine::try_on_exit_with`](crate::Machine::try_on_exit_with).
+    pub fn try_on_exit_with<T, F>(
+        &mut self,
+        state: S,
+        callback: F,
+    ) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        let callback = Self::wrap_fallible(callback);
+        self.register(Trigger::Exit(state), callback)
+    }
+
+    /// Register a fallible callback to fire on any state transition, see
+    /// [`Machine::try_on_transition_with`](crate::Machine::try_on_transition_with).
+    pub fn try_on_transition_with<T, F>(&mut self, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        let callback = Self::wrap_fallible(callback);
+        self.register(Trigger::AnyState, callback)
+    }
+
+    /// Register a callback for entering `state` that can update the context,
+    /// see [`Machine::on_enter_ctx`](crate::Machine::on_enter_ctx).
+    pub fn on_enter_ctx<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        let callback = Self::wrap_with_context(callback);
+        self.register(Trigger::Enter(state), callback)
+    }
+
+    /// Register a callback for exiting `state` that can update the context,
+    /// see [`Machine::on_exit_ctx`](crate::Machine::on_exit_ctx).
+    pub fn on_exit_ctx<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        let callback = Self::wrap_with_context(callback);
+        self.register(Trigger::Exit(state), callback)
+    }
+
+    /// Register a callback to fire on any state transition that can update the
+    /// context, see
+    /// [`Machine::on_transition_ctx`](crate::Machine::on_transition_ctx).
+    pub fn on_transition_ctx<T, F>(&mut self, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        let callback = Self::wrap_with_context(callback);
+        self.register(Trigger::AnyState, callback)
+    }
+
+    /// Trigger `event` on `instance` without any payload.
+    ///
+    /// Works like [`Machine::trigger`](crate::Machine::trigger), with the
+    /// callbacks receiving the instance's context.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as
+    /// [`Machine::trigger`](crate::Machine::trigger).
+    #[inline]
+    pub fn trigger(
+        &self,
+        instance: &mut Instance<S, C>,
+        event: &E,
+    ) -> Result<(), TriggerError<S, E>>
+    where
+        P: Payload<()>,
+    {
+        self.trigger_with(instance, event, &())
+    }
+
+    /// Trigger `event` on `instance` with an associated payload.
+    ///
+    /// Works like [`Machine::trigger_with`](crate::Machine::trigger_with), with
+    /// the callbacks receiving the instance's context.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as
+    /// [`Machine::trigger_with`](crate::Machine::trigger_with).
+    pub fn trigger_with<T>(
+        &self,
+        instance: &mut Instance<S, C>,
+        event: &E,
+        payload: &T,
+    ) -> Result<(), TriggerError<S, E>>
+    where
+        T: 'static,
+        P: Payload<T>,
+    {
+        let Instance { state, context } = instance;
+        self.fire(state, context, &mut false, event, P::wrap(payload))
+    }
+
+    /// The transition `event` would take from `state`, or the kind of
+    /// failure if the event would be rejected.
+    ///
+    /// Unlike [`Definition::resolve`], this doesn't allocate.
+    pub(crate) fn lookup(
+        &self,
+        state: &S,
+        context: &C,
+        event: &E,
+    ) -> Result<&Transition<S, E, C, P>, MachineError> {
+        let state_map =
+            self.transitions.get(event).ok_or(MachineError::EventInvalid)?;
+        let transition =
+            state_map.get(state).ok_or(MachineError::StateInvalid)?;
+        if !transition.allows(context) {
+            return Err(MachineError::GuardRejected);
+        }
+        Ok(transition)
+    }
+
+    /// The transition `event` would take from `state`, or why the event would
+    /// be rejected.
+    pub(crate) fn resolve(
+        &self,
+        state: &S,
+        context: &C,
+        event: &E,
+    ) -> Result<&Transition<S, E, C, P>, TriggerError<S, E>> {
+        self.lookup(state, context, event).map_err(|error| {
+            let valid_from = match (&error, self.transitions.get(event)) {
+                (MachineError::StateInvalid, Some(state_map)) => {
+                    Some(state_map.keys().cloned().collect())
+                }
+                _ => None,
+            };
+            TriggerError::new(error, event.clone(), state.clone(), valid_from)
+        })
+    }
+
+    /// Perform the transition for `event` from `state` and run the matching
+    /// callbacks and observers.
+    ///
+    /// `exited` is set once the machine has left `state` for the target of an
+    /// [external](TransitionKind::External) transition, even if a callback
+    /// fails afterwards.
+    pub(crate) fn fire(
+        &self,
+        state: &mut S,
+        context: &mut C,
+        exited: &mut bool,
+        event: &E,
+        payload: &P,
+    ) -> Result<(), TriggerError<S, E>> {
+        let mut run = |state: &mut S| {
+            self.apply(state, context, exited, event, payload).inspect_err(
+                |err| {
+                    observer::notify(&self.observers, |o| {
+                        o.on_rejected(err.state(), event, err.error());
+                    });
+                },
+            )
+        };
+        #[cfg(any(feature = "tracing", feature = "log"))]
+        {
+            let from = state.clone();
+            let result =
+                self.tracer.span(&from, event).in_scope(|| run(&mut *state));
+            let error = result.as_ref().err().map(TriggerError::error);
+            self.tracer.record(&from, state, event, error);
+            result
+        }
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        run(state)
+    }
+
+    /// Report that triggering `event` failed with `err` to observers and to
+    /// `tracing` and `log`, without running anything.
+    pub(crate) fn reject(&self, event: &E, err: &TriggerError<S, E>) {
+        observer::notify(&self.observers, |o| {
+            o.on_rejected(err.state(), event, err.error());
+        });
+        #[cfg(any(feature = "tracing", feature = "log"))]
+        self.tracer.reject(event, err);
+    }
+
+    /// [`Definition::fire`], without reporting rejections to observers.
+    fn apply(
+        &self,
+        state: &mut S,
+        context: &mut C,
+        exited: &mut bool,
+        event: &E,
+        payload: &P,
+    ) -> Result<(), TriggerError<S, E>> {
+        let transition = self.resolve(state, context, event)?;
+        let from = state.clone();
+        let fail =
+            |error| TriggerError::new(error, event.clone(), from.clone(), None);
+
+        let external = transition.kind == TransitionKind::External;
+        if external {
+            let exit = Trigger::Exit(from.clone());
+            self.run_callbacks(&exit, event, payload, context, |o| {
+                o.on_exit(&from, event);
+            })
+            .map_err(fail)?;
+        }
+        if let Some(action) = &transition.action {
+            action(event.clone(), payload, context).map_err(|err| {
+                fail(MachineError::CallbackFailed(err.into()))
+            })?;
+        }
+        if external {
+            *state = transition.target.clone();
+            *exited = true;
+            let enter = Trigger::Enter(state.clone());
+            self.run_callbacks(&enter, event, payload, context, |o| {
+                o.on_enter(state, event);
+            })
+            .map_err(fail)?;
+        }
+        self.run_callbacks(&Trigger::AnyState, event, payload, context, |o| {
+            o.on_transition(&from, state, event);
+        })
+        .map_err(fail)?;
+        Ok(())
+    }
+
+    /// Invoke every live callback registered under `trigger`, and `hook` on
+    /// every live observer, in the order they were registered.
+    ///
+    /// Stops at the first callback that fails.
+    fn run_callbacks(
+        &self,
+        trigger: &Trigger<S>,
+        event: &E,
+        payload: &P,
+        context: &mut C,
+        hook: impl Fn(&dyn MachineObserver<S, E>),
+    ) -> Result<(), MachineError> {
+        let registered =
+            self.callbacks.get(trigger).map_or(&[][..], Vec::as_slice);
+        observer::dispatch(
+            registered,
+            &self.observers,
+            event,
+            payload,
+            context,
+            hook,
+        )
+        .map_err(|err| MachineError::CallbackFailed(err.into()))
+    }
+}
+
+impl<S, E, C, P> Clone for Definition<S, E, C, P>
+where
+    S: Clone,
+    E: Clone,
+    P: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Definition {
+            transitions: self.transitions.clone(),
+            accepting: self.accepting.clone(),
+            callbacks: self.callbacks.clone(),
+            observers: self.observers.clone(),
+            #[cfg(any(feature = "tracing", feature = "log"))]
+            tracer: self.tracer,
+            next_callback_id: self.next_callback_id,
+        }
+    }
+}
+
+impl<S, E, C, P> Debug for Definition<S, E, C, P>
+where
+    S: Debug + Eq + Hash + Clone,
+    E: Debug + Eq + Hash + Clone,
+    P: ?Sized,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Definition")
+            .field("events", &self.transitions.keys().collect::<Vec<_>>())
+            .field("accepting", &self.accepting)
+            .field("callbacks", &self.callbacks.len())
+            .field("observers", &self.observers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::Machine;
+
+    fn order() -> Definition<&'static str, &'static str> {
+        let mut definition = Definition::new();
+        definition.when("pay", "created", "paid");
+        definition.when("ship", "paid", "shipped");
+        definition.when_internal("remind", "paid");
+        definition
+    }
+
+    #[test]
+    fn instances_move_independently() {
+        let definition = Rc::new(order());
+        let mut first = Instance::new("created");
+        let mut second = Instance::new("created");
+
+        definition.trigger(&mut first, &"pay").unwrap();
+        definition.trigger(&mut first, &"ship").unwrap();
+        definition.trigger(&mut second, &"pay").unwrap();
+
+        assert_eq!(*first.state(), "shipped");
+        assert_eq!(*second.state(), "paid");
+    }
+
+    #[test]
+    fn rejected_events_leave_the_instance_untouched() {
+        let definition = order();
+        let mut instance = Instance::new("created");
+
+        let err = definition.trigger(&mut instance, &"ship").unwrap_err();
+        assert_eq!(*err.error(), MachineError::StateInvalid);
+        assert_eq!(err.valid_from(), ["paid"]);
+        assert_eq!(*instance.state(), "created");
+    }
+
+    #[test]
+    fn callbacks_receive_the_instance_context() {
+        let mut definition: Definition<_, _, u32> = Definition::default();
+        definition.when_guarded("pay", "created", "paid", |paid| *paid == 0);
+        definition
+            .on_enter_ctx("paid", |_, (): &(), paid: &mut u32| *paid += 1);
+        let mut instance = Instance::with_context("created", 0);
+
+        definition.trigger(&mut instance, &"pay").unwrap();
+        assert_eq!(*instance.context(), 1);
+
+        let mut paid = Instance::with_context("created", 1);
+        let err = definition.trigger(&mut paid, &"pay").unwrap_err();
+        assert_eq!(*err.error(), MachineError::GuardRejected);
+        assert_eq!(paid.into_parts(), ("created", 1));
+    }
+
+    #[test]
+    fn callbacks_fire_for_every_instance() {
+        let mut definition = order();
+        let entered = Rc::new(Cell::new(0));
+        let e = entered.clone();
+        definition.on_enter("paid", move |_| e.set(e.get() + 1));
+        let definition = Rc::new(definition);
+
+        let mut instances: Vec<_> =
+            (0..3).map(|_| Instance::new("created")).collect();
+        for instance in &mut instances {
+            definition.trigger(instance, &"pay").unwrap();
+        }
+
+        assert_eq!(entered.get(), 3);
+    }
+
+    #[test]
+    fn machines_share_definitions_until_changed() {
+        let mut nano = Machine::new("created");
+        nano.when("pay", "created", "paid");
+        let mut clone = nano.clone();
+        assert!(Rc::ptr_eq(nano.definition(), clone.definition()));
+
+        clone.trigger(&"pay").unwrap();
+        assert!(Rc::ptr_eq(nano.definition(), clone.definition()));
+
+        clone.when("ship", "paid", "shipped");
+        assert!(!Rc::ptr_eq(nano.definition(), clone.definition()));
+        assert_eq!(nano.events().count(), 1);
+        assert_eq!(clone.events().count(), 2);
+    }
+
+    #[test]
+    fn machines_from_shared_definitions() {
+        let definition = Rc::new(order());
+        let mut nano =
+            Machine::from_definition(definition.clone(), Instance::new("paid"));
+
+        nano.trigger(&"ship").unwrap();
+
+        assert!(Rc::ptr_eq(nano.definition(), &definition));
+        assert_eq!(*nano.into_instance().state(), "shipped");
+    }
+}
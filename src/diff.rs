@@ -0,0 +1,339 @@
+use alloc::vec::Vec;
+use core::{
+    fmt::{Debug, Display, Formatter},
+    hash::Hash,
+};
+
+use hashbrown::HashSet;
+
+use crate::Machine;
+
+/// A single difference between two machines, as reported by [`diff`].
+#[derive(Debug, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Change<S, E> {
+    /// A transition that only exists in the new machine.
+    AddedTransition {
+        /// The event triggering the transition.
+        event: E,
+        /// The state the transition leaves.
+        from: S,
+        /// The state the transition enters.
+        to: S,
+    },
+    /// A transition that only exists in the old machine.
+    RemovedTransition {
+        /// The event triggering the transition.
+        event: E,
+        /// The state the transition leaves.
+        from: S,
+        /// The state the transition enters.
+        to: S,
+    },
+    /// A transition that exists in both machines but enters a different
+    /// state.
+    RetargetedTransition {
+        /// The event triggering the transition.
+        event: E,
+        /// The state the transition leaves.
+        from: S,
+        /// The state the transition enters in the old machine.
+        old: S,
+        /// The state the transition enters in the new machine.
+        new: S,
+    },
+    /// A state only known to the new machine.
+    AddedState(S),
+    /// A state only known to the old machine.
+    RemovedState(S),
+    /// An event only defined in the new machine.
+    AddedEvent(E),
+    /// An event only defined in the old machine.
+    RemovedEvent(E),
+}
+
+impl<S: Debug, E: Debug> Display for Change<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Change::AddedTransition { event, from, to } => {
+                write!(f, "+ {event:?}: {from:?} -> {to:?}")
+            }
+            Change::RemovedTransition { event, from, to } => {
+                write!(f, "- {event:?}: {from:?} -> {to:?}")
+            }
+            Change::RetargetedTransition { event, from, old, new } => {
+                write!(f, "~ {event:?}: {from:?} -> {new:?} (was {old:?})")
+            }
+            Change::AddedState(state) => write!(f, "+ state {state:?}"),
+            Change::RemovedState(state) => write!(f, "- state {state:?}"),
+            Change::AddedEvent(event) => write!(f, "+ event {event:?}"),
+            Change::RemovedEvent(event) => write!(f, "- event {event:?}"),
+        }
+    }
+}
+
+/// The differences between the transition tables of two machines.
+///
+/// Created by [`diff`]. Displaying it prints one change per line, and the
+/// changes themselves can be inspected with [`Diff::changes`], e.g. to fail a
+/// CI check on removed transitions.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct Diff<S, E> {
+    changes: Vec<Change<S, E>>,
+}
+
+impl<S, E> Diff<S, E> {
+    /// The changes, grouped by kind in the order of the [`Change`] variants.
+    ///
+    /// The order within a group is unspecified, see [`Diff::sorted`].
+    #[inline]
+    #[must_use]
+    pub fn changes(&self) -> &[Change<S, E>] {
+        &self.changes
+    }
+
+    /// Whether both machines have the same transitions.
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Sort the changes, so that the diff is the same across runs.
+    #[must_use]
+    pub fn sorted(mut self) -> Self
+    where
+        S: Ord,
+        E: Ord,
+    {
+        self.changes.sort();
+        self
+    }
+}
+
+impl<S, E> IntoIterator for Diff<S, E> {
+    type IntoIter = alloc::vec::IntoIter<Change<S, E>>;
+    type Item = Change<S, E>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.changes.into_iter()
+    }
+}
+
+impl<S: Debug, E: Debug> Display for Diff<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the transition tables of `old` and `new`.
+///
+/// Transitions are compared by event and source state. Only their targets are
+/// compared, not their kind, guard or action. States and events are the ones
+/// reported by [`Machine::states`] and [`Machine::events`].
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Change, Machine, diff};
+///
+/// let mut old = Machine::new("locked");
+/// old.when("insert coin", "locked", "unlocked");
+/// old.when("turn knob", "unlocked", "locked");
+///
+/// let mut new = old.clone();
+/// new.when("turn knob", "unlocked", "broken");
+///
+/// let diff = diff(&old, &new).sorted();
+/// assert_eq!(
+///     diff.changes(),
+///     [
+///         Change::RetargetedTransition {
+///             event: "turn knob",
+///             from: "unlocked",
+///             old: "locked",
+///             new: "broken",
+///         },
+///         Change::AddedState("broken"),
+///     ]
+/// );
+/// assert_eq!(
+///     diff.to_string(),
+///     "~ \"turn knob\": \"unlocked\" -> \"broken\" (was \"locked\")\n\
+///      + state \"broken\"\n"
+/// );
+/// ```
+pub fn diff<S, E, C, P, C2, P2>(
+    old: &Machine<S, E, C, P>,
+    new: &Machine<S, E, C2, P2>,
+) -> Diff<S, E>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+    P2: ?Sized,
+{
+    let mut changes = Vec::new();
+
+    for (event, from, to) in new.transitions() {
+        if old.target(event, from).is_none() {
+            changes.push(Change::AddedTransition {
+                event: event.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            });
+        }
+    }
+    for (event, from, to) in old.transitions() {
+        if new.target(event, from).is_none() {
+            changes.push(Change::RemovedTransition {
+                event: event.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            });
+        }
+    }
+    for (event, from, to) in old.transitions() {
+        if let Some(target) = new.target(event, from)
+            && target != to
+        {
+            changes.push(Change::RetargetedTransition {
+                event: event.clone(),
+                from: from.clone(),
+                old: to.clone(),
+                new: target.clone(),
+            });
+        }
+    }
+
+    let old_states: HashSet<&S> = old.states().collect();
+    let new_states: HashSet<&S> = new.states().collect();
+    for &state in new_states.difference(&old_states) {
+        changes.push(Change::AddedState(state.clone()));
+    }
+    for &state in old_states.difference(&new_states) {
+        changes.push(Change::RemovedState(state.clone()));
+    }
+
+    for event in new.events() {
+        if !old.definition.transitions.contains_key(event) {
+            changes.push(Change::AddedEvent(event.clone()));
+        }
+    }
+    for event in old.events() {
+        if !new.definition.transitions.contains_key(event) {
+            changes.push(Change::RemovedEvent(event.clone()));
+        }
+    }
+
+    Diff { changes }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec};
+
+    use super::*;
+    use crate::testing::turnstile;
+
+    #[test]
+    fn identical_machines() {
+        let diff = diff(&turnstile(), &turnstile());
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "");
+    }
+
+    #[test]
+    fn added_and_removed_transitions() {
+        let old = turnstile();
+        let mut new = Machine::new("locked");
+        new.when("insert coin", "locked", "unlocked");
+        new.when("turn knob", "unlocked", "locked");
+        new.when("insert coin", "unlocked", "unlocked");
+
+        let diff = diff(&old, &new).sorted();
+        assert_eq!(
+            diff.changes(),
+            [
+                Change::AddedTransition {
+                    event: "insert coin",
+                    from: "unlocked",
+                    to: "unlocked",
+                },
+                Change::RemovedTransition {
+                    event: "kick",
+                    from: "locked",
+                    to: "locked",
+                },
+                Change::RemovedEvent("kick"),
+            ]
+        );
+    }
+
+    #[test]
+    fn retargeted_transitions_and_states() {
+        let old = turnstile();
+        let mut new = turnstile();
+        new.when("kick", "locked", "broken");
+        new.when("repair", "broken", "locked");
+
+        let diff = diff(&old, &new).sorted();
+        assert_eq!(
+            diff.changes(),
+            [
+                Change::AddedTransition {
+                    event: "repair",
+                    from: "broken",
+                    to: "locked",
+                },
+                Change::RetargetedTransition {
+                    event: "kick",
+                    from: "locked",
+                    old: "locked",
+                    new: "broken",
+                },
+                Change::AddedState("broken"),
+                Change::AddedEvent("repair"),
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_states() {
+        let diff = diff(&turnstile(), &Machine::<_, &str>::new("locked"));
+
+        let mut states: Vec<_> = diff
+            .into_iter()
+            .filter_map(|change| match change {
+                Change::RemovedState(state) => Some(state),
+                _ => None,
+            })
+            .collect();
+        states.sort_unstable();
+        assert_eq!(states, ["locked", "unlocked"]);
+    }
+
+    #[test]
+    fn display() {
+        let old = turnstile();
+        let mut new = turnstile();
+        new.when("kick", "locked", "broken");
+        new.when("repair", "broken", "locked");
+
+        assert_eq!(
+            diff(&old, &new).sorted().to_string(),
+            "+ \"repair\": \"broken\" -> \"locked\"\n\
+             ~ \"kick\": \"locked\" -> \"broken\" (was \"locked\")\n\
+             + state \"broken\"\n\
+             + event \"repair\"\n"
+        );
+
+        let change: Change<u8, char> = Change::RemovedState(1);
+        assert_eq!(change.to_string(), "- state 1");
+        let changes = vec![Change::<u8, char>::RemovedEvent('a')];
+        assert_eq!(Diff { changes }.to_string(), "- event 'a'\n");
+    }
+}
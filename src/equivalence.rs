@@ -0,0 +1,352 @@
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use hashbrown::HashSet;
+
+use crate::Machine;
+
+/// The outcome of comparing two machines with [`Machine::equivalent`] or
+/// [`Machine::bisimilar`].
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub enum Equivalence<E> {
+    /// The machines cannot be told apart.
+    Equivalent,
+    /// A shortest sequence of events that tells the machines apart.
+    Counterexample(Vec<E>),
+}
+
+impl<E> Equivalence<E> {
+    /// Whether the machines cannot be told apart.
+    #[inline]
+    #[must_use]
+    pub fn is_equivalent(&self) -> bool {
+        matches!(self, Equivalence::Equivalent)
+    }
+
+    /// The distinguishing sequence, if the machines can be told apart.
+    #[inline]
+    #[must_use]
+    pub fn counterexample(&self) -> Option<&[E]> {
+        match self {
+            Equivalence::Equivalent => None,
+            Equivalence::Counterexample(events) => Some(events),
+        }
+    }
+}
+
+/// Checks comparing two machines, starting from their current states.
+///
+/// Like the other operations treating machines as automata, they only look at
+/// transitions and accepting states: guards, actions and callbacks are
+/// ignored.
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Check whether `self` and `other` accept the same sequences of events.
+    ///
+    /// The counterexample, if any, is a shortest sequence accepted by one
+    /// machine and not the other, the least one in event order among those.
+    /// Rejected events don't matter beyond making a sequence rejected, see
+    /// [`Machine::bisimilar`] for a stricter check.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::{Equivalence, Machine};
+    ///
+    /// let mut old = Machine::new("locked");
+    /// old.when("insert coin", "locked", "unlocked");
+    /// old.when("turn knob", "unlocked", "locked");
+    /// old.set_accepting("locked");
+    ///
+    /// let mut new = old.clone();
+    /// new.when("insert coin", "unlocked", "unlocked");
+    ///
+    /// assert_eq!(
+    ///     old.equivalent(&new),
+    ///     Equivalence::Counterexample(vec!["insert coin", "insert coin", "turn knob"]),
+    /// );
+    /// ```
+    pub fn equivalent<T, C2, P2>(
+        &self,
+        other: &Machine<T, E, C2, P2>,
+    ) -> Equivalence<E>
+    where
+        T: Eq + Hash + Clone,
+        E: Ord,
+        P2: ?Sized,
+    {
+        let events = union_events(self, other);
+        shortest(
+            (Some(self.state.clone()), Some(other.state.clone())),
+            &events,
+            |(s, t), event| {
+                let s = s.as_ref().and_then(|s| self.target(event, s));
+                let t = t.as_ref().and_then(|t| other.target(event, t));
+                if s.is_none() && t.is_none() {
+                    return Ok(None);
+                }
+                Ok(Some((s.cloned(), t.cloned())))
+            },
+            |(s, t)| {
+                s.as_ref().is_some_and(|s| self.is_accepting(s))
+                    != t.as_ref().is_some_and(|t| other.is_accepting(t))
+            },
+        )
+    }
+
+    /// Check whether `self` and `other` are bisimilar: from every pair of
+    /// states reached by the same events, both machines accept the same
+    /// events next and agree on whether they are accepting.
+    ///
+    /// The counterexample, if any, is a shortest sequence after which the
+    /// machines disagree on whether they are accepting, or whose last event
+    /// is accepted by one machine and rejected by the other, the least one in
+    /// event order among those.
+    pub fn bisimilar<T, C2, P2>(
+        &self,
+        other: &Machine<T, E, C2, P2>,
+    ) -> Equivalence<E>
+    where
+        T: Eq + Hash + Clone,
+        E: Ord,
+        P2: ?Sized,
+    {
+        let events = union_events(self, other);
+        shortest(
+            (self.state.clone(), other.state.clone()),
+            &events,
+            |(s, t), event| match (
+                self.target(event, s),
+                other.target(event, t),
+            ) {
+                (Some(s), Some(t)) => Ok(Some((s.clone(), t.clone()))),
+                (None, None) => Ok(None),
+                _ => Err(()),
+            },
+            |(s, t)| self.is_accepting(s) != other.is_accepting(t),
+        )
+    }
+}
+
+/// The events of both machines, each listed once, in order.
+fn union_events<'a, S, T, E, C, C2, P, P2>(
+    left: &'a Machine<S, E, C, P>,
+    right: &'a Machine<T, E, C2, P2>,
+) -> Vec<&'a E>
+where
+    S: Eq + Hash + Clone,
+    T: Eq + Hash + Clone,
+    E: Eq + Hash + Clone + Ord,
+    P: ?Sized,
+    P2: ?Sized,
+{
+    let mut events: Vec<&E> = left.events().collect();
+    events.extend(
+        right
+            .events()
+            .filter(|e| !left.definition.transitions.contains_key(*e)),
+    );
+    events.sort_unstable();
+    events
+}
+
+/// Search breadth-first from `start` for a shortest path to a pair that
+/// `differs`, or whose `step` fails.
+///
+/// `step` returns `Ok(None)` when neither side has a transition. Pairs are
+/// checked as soon as they are reached, so that a pair that `differs` one
+/// event away isn't overtaken by a failing `step` from a pair expanded later.
+fn shortest<Q, E, F, D>(
+    start: Q,
+    events: &[&E],
+    step: F,
+    differs: D,
+) -> Equivalence<E>
+where
+    Q: Eq + Hash + Clone,
+    E: Clone,
+    F: Fn(&Q, &E) -> Result<Option<Q>, ()>,
+    D: Fn(&Q) -> bool,
+{
+    // Every visited pair along with the pair and event it was reached from.
+    let mut visited: Vec<(Q, Option<(usize, usize)>)> = Vec::new();
+    let mut seen = HashSet::new();
+    seen.insert(start.clone());
+    visited.push((start, None));
+
+    let path = |visited: &[(Q, Option<(usize, usize)>)], mut at: usize| {
+        let mut path = Vec::new();
+        while let Some((parent, event)) = visited[at].1 {
+            path.push(events[event].clone());
+            at = parent;
+        }
+        path.reverse();
+        path
+    };
+
+    if differs(&visited[0].0) {
+        return Equivalence::Counterexample(Vec::new());
+    }
+    let mut next = 0;
+    while next < visited.len() {
+        for (e, event) in events.iter().enumerate() {
+            match step(&visited[next].0, event) {
+                Ok(Some(pair)) => {
+                    if seen.insert(pair.clone()) {
+                        let differs = differs(&pair);
+                        visited.push((pair, Some((next, e))));
+                        if differs {
+                            let at = visited.len() - 1;
+                            return Equivalence::Counterexample(path(
+                                &visited, at,
+                            ));
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(()) => {
+                    let mut path = path(&visited, next);
+                    path.push((*event).clone());
+                    return Equivalence::Counterexample(path);
+                }
+            }
+        }
+        next += 1;
+    }
+    Equivalence::Equivalent
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    /// Accepts sequences with an even number of `a`s, `b` is always valid.
+    fn even() -> Machine<u8, char> {
+        let mut nano = Machine::new(0);
+        nano.when('a', 0, 1);
+        nano.when('a', 1, 0);
+        nano.when('b', 0, 0);
+        nano.when('b', 1, 1);
+        nano.set_accepting(0);
+        nano
+    }
+
+    /// The same as [`even`] with every state duplicated.
+    fn even_unrolled() -> Machine<u8, char> {
+        let mut nano = Machine::new(0);
+        nano.when('a', 0, 1);
+        nano.when('a', 1, 2);
+        nano.when('a', 2, 3);
+        nano.when('a', 3, 0);
+        for state in 0..4 {
+            nano.when('b', state, state);
+        }
+        nano.set_accepting(0);
+        nano.set_accepting(2);
+        nano
+    }
+
+    #[test]
+    fn equivalence_helpers() {
+        let equivalent: Equivalence<char> = Equivalence::Equivalent;
+        assert!(equivalent.is_equivalent());
+        assert_eq!(equivalent.counterexample(), None);
+
+        let different = Equivalence::Counterexample(vec!['a']);
+        assert!(!different.is_equivalent());
+        assert_eq!(different.counterexample(), Some(&['a'][..]));
+    }
+
+    #[test]
+    fn refactors_are_equivalent() {
+        let (nano, unrolled) = (even(), even_unrolled());
+
+        assert_eq!(nano.equivalent(&unrolled), Equivalence::Equivalent);
+        assert_eq!(nano.bisimilar(&unrolled), Equivalence::Equivalent);
+        assert_eq!(unrolled.bisimilar(&nano), Equivalence::Equivalent);
+    }
+
+    #[test]
+    fn minimized_machines_are_equivalent() {
+        let unrolled = even_unrolled();
+        let (minimal, _) = unrolled.minimize();
+
+        assert_eq!(minimal.states().count(), 2);
+        assert_eq!(unrolled.bisimilar(&minimal), Equivalence::Equivalent);
+    }
+
+    #[test]
+    fn shortest_counterexample() {
+        let nano = even();
+        let mut changed = even_unrolled();
+        changed.set_accepting(3);
+
+        let expected = Equivalence::Counterexample(vec!['a', 'a', 'a']);
+        assert_eq!(nano.equivalent(&changed), expected);
+        assert_eq!(nano.bisimilar(&changed), expected);
+    }
+
+    #[test]
+    fn initial_acceptance_differs() {
+        let nano = even();
+        let mut other = even();
+        other.trigger(&'a').unwrap();
+
+        assert_eq!(
+            nano.equivalent(&other),
+            Equivalence::Counterexample(vec![])
+        );
+    }
+
+    #[test]
+    fn dead_ends_only_matter_to_bisimulation() {
+        let nano = even();
+        let mut dead_end = even();
+        dead_end.when('c', 1, 9);
+
+        assert_eq!(nano.equivalent(&dead_end), Equivalence::Equivalent);
+        assert_eq!(
+            nano.bisimilar(&dead_end),
+            Equivalence::Counterexample(vec!['a', 'c'])
+        );
+    }
+
+    #[test]
+    fn acceptance_wins_over_a_later_dead_end() {
+        let mut left = Machine::new(0);
+        left.when('a', 0, 1);
+        left.when('b', 0, 2);
+        left.when('c', 1, 1);
+        let mut right = Machine::new(0);
+        right.when('a', 0, 1);
+        right.when('b', 0, 2);
+        right.set_accepting(2);
+
+        let expected = Equivalence::Counterexample(vec!['b']);
+        assert_eq!(left.bisimilar(&right), expected);
+        assert_eq!(right.bisimilar(&left), expected);
+        assert_eq!(left.equivalent(&right), expected);
+    }
+
+    #[test]
+    fn missing_events_are_counterexamples() {
+        let nano = even();
+        let mut fewer = even();
+        fewer.when('c', 0, 0);
+
+        assert_eq!(
+            nano.equivalent(&fewer),
+            Equivalence::Counterexample(vec!['c'])
+        );
+        assert_eq!(
+            fewer.bisimilar(&nano),
+            Equivalence::Counterexample(vec!['c'])
+        );
+    }
+}
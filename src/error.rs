@@ -1,19 +1,55 @@
 use core::{
-    fmt::{Debug, Display, Formatter},
-    hash::Hash,
+    error::Error,
+    fmt::{Display, Formatter},
 };
 
+#[cfg(feature = "alloc")]
+mod context;
+#[cfg(feature = "alloc")]
+pub use context::{CallbackError, SequenceError, TriggerError};
+
 /// Errors that can occur when triggering events on a [`Machine`].
 ///
 /// This error type is returned by [`Machine::trigger`] and
 /// [`Machine::trigger_with`] to indicate invalid operations.
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[non_exhaustive]
 pub enum MachineError {
     /// The specified event is not defined in the state machine.
     EventInvalid,
     /// The specified event is defined for this machine, but not valid from the
     /// current state.
     StateInvalid,
+    /// The event has a transition from the current state, but its guard
+    /// rejected it.
+    GuardRejected,
+    /// A fallible callback returned an error while handling the event.
+    #[cfg(feature = "alloc")]
+    CallbackFailed(CallbackError),
+}
+
+impl MachineError {
+    /// The name of the variant, without the data it carries.
+    ///
+    /// Handy as a label when counting failures by kind.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::MachineError;
+    ///
+    /// assert_eq!(MachineError::GuardRejected.name(), "GuardRejected");
+    /// ```
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            MachineError::EventInvalid => "EventInvalid",
+            MachineError::StateInvalid => "StateInvalid",
+            MachineError::GuardRejected => "GuardRejected",
+            #[cfg(feature = "alloc")]
+            MachineError::CallbackFailed(_) => "CallbackFailed",
+        }
+    }
 }
 
 impl Display for MachineError {
@@ -26,8 +62,23 @@ impl Display for MachineError {
             MachineError::StateInvalid => {
                 write!(f, "The event is not valid for the current state")
             }
+            MachineError::GuardRejected => {
+                write!(f, "The transition guard rejected the event")
+            }
+            #[cfg(feature = "alloc")]
+            MachineError::CallbackFailed(err) => {
+                write!(f, "A callback failed: {err}")
+            }
         }
     }
 }
 
-impl core::error::Error for MachineError {}
+impl Error for MachineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "alloc")]
+            MachineError::CallbackFailed(err) => Some(err.inner()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+};
+
+use super::MachineError;
+
+/// An error returned when triggering an event on a [`Machine`], along with
+/// the context in which it happened.
+///
+/// It carries the rejected event, the state the machine was in when the event
+/// was triggered and, for [`MachineError::StateInvalid`], the states from
+/// which the event would have been valid.
+///
+/// A `TriggerError` converts into the plain [`MachineError`] it wraps, and
+/// compares equal to it, so code that only cares about the kind of failure can
+/// keep using [`MachineError`].
+///
+/// [`Machine`]: crate::Machine
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TriggerError<S, E> {
+    error: MachineError,
+    event: E,
+    state: S,
+    /// Shared, so that machines can keep them ready for each event.
+    valid_from: Option<Arc<[S]>>,
+}
+
+impl<S, E> TriggerError<S, E> {
+    pub(crate) fn new(
+        error: MachineError,
+        event: E,
+        state: S,
+        valid_from: Option<Arc<[S]>>,
+    ) -> Self {
+        TriggerError { error, event, state, valid_from }
+    }
+
+    /// The kind of failure.
+    #[inline]
+    #[must_use]
+    pub fn error(&self) -> &MachineError {
+        &self.error
+    }
+
+    /// The event that was triggered.
+    #[inline]
+    #[must_use]
+    pub fn event(&self) -> &E {
+        &self.event
+    }
+
+    /// The state the machine was in when the event was triggered.
+    #[inline]
+    #[must_use]
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// The states from which the event has a transition defined.
+    ///
+    /// Only populated for [`MachineError::StateInvalid`]; empty otherwise.
+    #[inline]
+    #[must_use]
+    pub fn valid_from(&self) -> &[S] {
+        self.valid_from.as_deref().unwrap_or_default()
+    }
+
+    /// Discard the context and keep only the kind of failure.
+    #[inline]
+    #[must_use]
+    pub fn into_error(self) -> MachineError {
+        self.error
+    }
+}
+
+impl<S, E> From<TriggerError<S, E>> for MachineError {
+    fn from(err: TriggerError<S, E>) -> Self {
+        err.error
+    }
+}
+
+impl<S, E> PartialEq<MachineError> for TriggerError<S, E> {
+    fn eq(&self, other: &MachineError) -> bool {
+        self.error == *other
+    }
+}
+
+impl<S: Debug, E: Debug> Display for TriggerError<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} (event: {:?}, state: {:?}",
+            self.error, self.event, self.state
+        )?;
+        if !self.valid_from().is_empty() {
+            write!(f, ", valid from: {:?}", self.valid_from())?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<S: Debug, E: Debug> Error for TriggerError<S, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// An error returned by [`Machine::trigger_all`], along with the position of
+/// the event that failed.
+///
+/// [`Machine::trigger_all`]: crate::Machine::trigger_all
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SequenceError<S, E> {
+    index: usize,
+    error: TriggerError<S, E>,
+}
+
+impl<S, E> SequenceError<S, E> {
+    pub(crate) fn new(index: usize, error: TriggerError<S, E>) -> Self {
+        SequenceError { index, error }
+    }
+
+    /// The position of the failing event in the sequence.
+    #[inline]
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Why the event at [`index`](SequenceError::index) failed.
+    #[inline]
+    #[must_use]
+    pub fn error(&self) -> &TriggerError<S, E> {
+        &self.error
+    }
+
+    /// Discard the position and keep only the failure of the event.
+    #[inline]
+    #[must_use]
+    pub fn into_inner(self) -> TriggerError<S, E> {
+        self.error
+    }
+}
+
+impl<S, E> From<SequenceError<S, E>> for MachineError {
+    fn from(err: SequenceError<S, E>) -> Self {
+        err.error.error
+    }
+}
+
+impl<S, E> PartialEq<MachineError> for SequenceError<S, E> {
+    fn eq(&self, other: &MachineError) -> bool {
+        self.error == *other
+    }
+}
+
+impl<S: Debug, E: Debug> Display for SequenceError<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "event {} of the sequence failed: {}", self.index, self.error)
+    }
+}
+
+impl<S: Debug, E: Debug> Error for SequenceError<S, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// The error returned by a fallible callback.
+///
+/// Two `CallbackError`s compare equal only if they wrap the very same error
+/// value, which happens when one is a clone of the other.
+#[derive(Clone)]
+pub struct CallbackError(Arc<dyn Error + Send + Sync>);
+
+impl CallbackError {
+    /// The error returned by the callback.
+    #[must_use]
+    pub fn inner(&self) -> &(dyn Error + 'static) {
+        &*self.0
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for CallbackError {
+    fn from(err: Box<dyn Error + Send + Sync>) -> Self {
+        CallbackError(Arc::from(err))
+    }
+}
+
+impl Debug for CallbackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl Display for CallbackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl PartialEq for CallbackError {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CallbackError {}
+
+impl Hash for CallbackError {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).cast::<()>().hash(state);
+    }
+}
@@ -0,0 +1,495 @@
+use core::fmt::Debug;
+
+use crate::{MachineError, TransitionKind};
+
+/// A guard of a [`StaticMachine`] transition.
+pub type StaticGuard<C> = fn(&C) -> bool;
+
+/// A callback or action of a [`StaticMachine`], receiving the state entered,
+/// exited or left, the event and the machine's context.
+pub type StaticCallback<S, E, C> = fn(S, E, &mut C);
+
+/// A transition of a [`StaticMachine`].
+#[derive(Debug)]
+struct StaticTransition<S, E, C> {
+    event: E,
+    from: S,
+    to: S,
+    kind: TransitionKind,
+    guard: Option<StaticGuard<C>>,
+    action: Option<StaticCallback<S, E, C>>,
+}
+
+/// A state machine with room for `N` transitions that never allocates.
+///
+/// Unlike [`Machine`](crate::Machine), it doesn't need `alloc`, and is
+/// available with the crate's default features turned off. States and events
+/// must be `Copy`, callbacks are plain function pointers, and the whole
+/// machine can be built in a `const` or `static` item.
+///
+/// Transitions are looked up with a linear scan, which is fast for the small
+/// tables it is meant for. There are no payloads and callbacks can't fail:
+/// they receive the event and the machine's context instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::StaticMachine;
+///
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// enum State {
+///     Locked,
+///     Unlocked,
+/// }
+///
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// enum Event {
+///     InsertCoin,
+///     TurnKnob,
+/// }
+///
+/// const TURNSTILE: StaticMachine<State, Event, 2, u32> =
+///     StaticMachine::with_context(State::Locked, 0)
+///         .when(Event::InsertCoin, State::Locked, State::Unlocked)
+///         .when(Event::TurnKnob, State::Unlocked, State::Locked)
+///         .on_enter(|state, _, coins| {
+///             if let State::Unlocked = state {
+///                 *coins += 1;
+///             }
+///         });
+///
+/// let mut nano = TURNSTILE;
+/// nano.trigger(Event::InsertCoin).unwrap();
+/// assert_eq!(*nano.state(), State::Unlocked);
+/// assert_eq!(*nano.context(), 1);
+/// ```
+#[derive(Debug)]
+pub struct StaticMachine<S, E, const N: usize, C = ()> {
+    state: S,
+    context: C,
+    transitions: [Option<StaticTransition<S, E, C>>; N],
+    len: usize,
+    on_enter: Option<StaticCallback<S, E, C>>,
+    on_exit: Option<StaticCallback<S, E, C>>,
+    on_transition: Option<StaticCallback<S, E, C>>,
+}
+
+impl<S: Copy, E: Copy, C> Clone for StaticTransition<S, E, C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: Copy, E: Copy, C> Copy for StaticTransition<S, E, C> {}
+
+impl<S: Copy, E: Copy, const N: usize, C: Clone> Clone
+    for StaticMachine<S, E, N, C>
+{
+    fn clone(&self) -> Self {
+        StaticMachine {
+            context: self.context.clone(),
+            transitions: self.transitions,
+            ..*self
+        }
+    }
+}
+
+impl<S: Copy, E: Copy, const N: usize> StaticMachine<S, E, N> {
+    /// Create a new state machine with the given initial state.
+    #[must_use]
+    pub const fn new(initial_state: S) -> Self {
+        StaticMachine::with_context(initial_state, ())
+    }
+}
+
+impl<S: Copy, E: Copy, const N: usize, C> StaticMachine<S, E, N, C> {
+    /// Create a new state machine with the given initial state and context.
+    #[must_use]
+    pub const fn with_context(initial_state: S, context: C) -> Self {
+        StaticMachine {
+            state: initial_state,
+            context,
+            transitions: [const { None }; N],
+            len: 0,
+            on_enter: None,
+            on_exit: None,
+            on_transition: None,
+        }
+    }
+
+    /// Add `transition`, keeping the other transitions.
+    const fn with(mut self, transition: StaticTransition<S, E, C>) -> Self {
+        assert!(self.len < N, "too many transitions for this machine");
+        self.transitions[self.len] = Some(transition);
+        self.len += 1;
+        self
+    }
+
+    /// When `event` occurs in `state`, move to `new_state`.
+    ///
+    /// Like [`Machine::when`](crate::Machine::when), a transition defined
+    /// later for the same `(event, state)` replaces this one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the machine already has `N` transitions. In a `const`
+    /// context, this is a compile error.
+    #[must_use]
+    pub const fn when(self, event: E, state: S, new_state: S) -> Self {
+        self.with(StaticTransition {
+            event,
+            from: state,
+            to: new_state,
+            kind: TransitionKind::External,
+            guard: None,
+            action: None,
+        })
+    }
+
+    /// When `event` occurs in `state`, stay in `state` without exiting it.
+    ///
+    /// Only the [`on_transition`](StaticMachine::on_transition) callback
+    /// fires, see [`Machine::when_internal`](crate::Machine::when_internal).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the machine already has `N` transitions.
+    #[must_use]
+    pub const fn when_internal(self, event: E, state: S) -> Self {
+        self.with(StaticTransition {
+            event,
+            from: state,
+            to: state,
+            kind: TransitionKind::Internal,
+            guard: None,
+            action: None,
+        })
+    }
+
+    /// When `event` occurs in `state`, move to `new_state` if `guard` allows
+    /// it, see [`Machine::when_guarded`](crate::Machine::when_guarded).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the machine already has `N` transitions.
+    #[must_use]
+    pub const fn when_guarded(
+        self,
+        event: E,
+        state: S,
+        new_state: S,
+        guard: StaticGuard<C>,
+    ) -> Self {
+        self.with(StaticTransition {
+            event,
+            from: state,
+            to: new_state,
+            kind: TransitionKind::External,
+            guard: Some(guard),
+            action: None,
+        })
+    }
+
+    /// When `event` occurs in `state`, move to `new_state` and run `action`,
+    /// see [`Machine::when_then`](crate::Machine::when_then).
+    ///
+    /// The action receives `state`, the state being left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the machine already has `N` transitions.
+    #[must_use]
+    pub const fn when_then(
+        self,
+        event: E,
+        state: S,
+        new_state: S,
+        action: StaticCallback<S, E, C>,
+    ) -> Self {
+        self.with(StaticTransition {
+            event,
+            from: state,
+            to: new_state,
+            kind: TransitionKind::External,
+            guard: None,
+            action: Some(action),
+        })
+    }
+
+    /// Call `callback` with the new state whenever a state is entered.
+    ///
+    /// Replaces any previous enter callback.
+    #[must_use]
+    pub const fn on_enter(mut self, callback: StaticCallback<S, E, C>) -> Self {
+        self.on_enter = Some(callback);
+        self
+    }
+
+    /// Call `callback` with the old state whenever a state is exited.
+    ///
+    /// Replaces any previous exit callback.
+    #[must_use]
+    pub const fn on_exit(mut self, callback: StaticCallback<S, E, C>) -> Self {
+        self.on_exit = Some(callback);
+        self
+    }
+
+    /// Call `callback` with the current state after every transition,
+    /// including internal ones.
+    ///
+    /// Replaces any previous transition callback.
+    #[must_use]
+    pub const fn on_transition(
+        mut self,
+        callback: StaticCallback<S, E, C>,
+    ) -> Self {
+        self.on_transition = Some(callback);
+        self
+    }
+
+    /// A reference to the current state of the machine.
+    #[inline]
+    pub const fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// A reference to the machine's context.
+    #[inline]
+    pub const fn context(&self) -> &C {
+        &self.context
+    }
+
+    /// A mutable reference to the machine's context.
+    #[inline]
+    pub const fn context_mut(&mut self) -> &mut C {
+        &mut self.context
+    }
+}
+
+impl<S, E, const N: usize, C> StaticMachine<S, E, N, C>
+where
+    S: Copy + Eq,
+    E: Copy + Eq,
+{
+    /// Returns an iterator over all transitions as `(event, from, to)`
+    /// triples, in the order they were defined.
+    pub fn transitions(&self) -> impl Iterator<Item = (E, S, S)> + '_ {
+        self.transitions.iter().flatten().map(|t| (t.event, t.from, t.to))
+    }
+
+    /// Trigger the given `event` on the machine.
+    ///
+    /// For an [external](TransitionKind::External) transition, the exit
+    /// callback fires first, then the transition's action, if it has one,
+    /// then the state changes, then the enter and transition callbacks fire.
+    /// For an [internal](TransitionKind::Internal) transition, only the
+    /// transition callback fires.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`MachineError::EventInvalid`] if the event is not defined in
+    ///   this state machine.
+    /// - Returns [`MachineError::StateInvalid`] if no transition is defined for
+    ///   the machine's current state with the given event.
+    /// - Returns [`MachineError::GuardRejected`] if the transition's guard
+    ///   rejected the event.
+    pub fn trigger(&mut self, event: E) -> Result<(), MachineError> {
+        let mut defined = false;
+        let mut found = None;
+        // Scan backwards, so that the last definition for the state wins.
+        for transition in self.transitions.iter().flatten().rev() {
+            if transition.event == event {
+                defined = true;
+                if transition.from == self.state {
+                    found = Some(*transition);
+                    break;
+                }
+            }
+        }
+        let Some(transition) = found else {
+            return Err(if defined {
+                MachineError::StateInvalid
+            } else {
+                MachineError::EventInvalid
+            });
+        };
+        if let Some(guard) = transition.guard
+            && !guard(&self.context)
+        {
+            return Err(MachineError::GuardRejected);
+        }
+
+        if transition.kind == TransitionKind::External {
+            if let Some(on_exit) = self.on_exit {
+                on_exit(self.state, event, &mut self.context);
+            }
+            if let Some(action) = transition.action {
+                action(self.state, event, &mut self.context);
+            }
+            self.state = transition.to;
+            if let Some(on_enter) = self.on_enter {
+                on_enter(self.state, event, &mut self.context);
+            }
+        }
+        if let Some(on_transition) = self.on_transition {
+            on_transition(self.state, event, &mut self.context);
+        }
+        Ok(())
+    }
+}
+
+impl<S: Copy + Default, E: Copy, const N: usize, C: Default> Default
+    for StaticMachine<S, E, N, C>
+{
+    /// Create a machine without transitions, using `S::default()` as the
+    /// initial state and `C::default()` as the context.
+    fn default() -> Self {
+        StaticMachine::with_context(S::default(), C::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! These tests don't allocate, and CI also runs them with the `alloc`
+    //! feature off, where the crate doesn't link `alloc` at all.
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Locked,
+        Unlocked,
+        Broken,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        Coin,
+        Push,
+        Kick,
+        Repair,
+    }
+
+    use Event::{Coin, Kick, Push, Repair};
+    use State::{Broken, Locked, Unlocked};
+
+    /// Everything a test machine records, without any allocation.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    struct Log {
+        entered: u8,
+        exited: u8,
+        transitions: u8,
+        refunds: u8,
+        kicks: u8,
+    }
+
+    const TURNSTILE: StaticMachine<State, Event, 6, Log> =
+        StaticMachine::with_context(
+            Locked,
+            Log { entered: 0, exited: 0, transitions: 0, refunds: 0, kicks: 0 },
+        )
+        .when(Coin, Locked, Unlocked)
+        .when(Push, Unlocked, Locked)
+        .when_then(Coin, Unlocked, Unlocked, |_, _, log| log.refunds += 1)
+        .when_internal(Push, Locked)
+        .when_then(Kick, Locked, Broken, |_, _, log| log.kicks += 1)
+        .when_guarded(Repair, Broken, Locked, |log| log.kicks < 2)
+        .on_enter(|_, _, log| log.entered += 1)
+        .on_exit(|_, _, log| log.exited += 1)
+        .on_transition(|_, _, log| log.transitions += 1);
+
+    static PLAIN: StaticMachine<State, Event, 1> =
+        StaticMachine::new(Locked).when(Coin, Locked, Unlocked);
+
+    #[test]
+    fn transitions() {
+        let mut nano = TURNSTILE;
+
+        nano.trigger(Coin).unwrap();
+        assert_eq!(*nano.state(), Unlocked);
+        nano.trigger(Push).unwrap();
+        assert_eq!(*nano.state(), Locked);
+
+        assert_eq!(nano.context().entered, 2);
+        assert_eq!(nano.context().exited, 2);
+        assert_eq!(nano.context().transitions, 2);
+    }
+
+    #[test]
+    fn internal_transitions_only_fire_transition_callbacks() {
+        let mut nano = TURNSTILE;
+        nano.trigger(Push).unwrap();
+
+        assert_eq!(*nano.state(), Locked);
+        assert_eq!(*nano.context(), Log { transitions: 1, ..Log::default() });
+    }
+
+    #[test]
+    fn actions_and_guards() {
+        let mut nano = TURNSTILE;
+        nano.trigger(Coin).unwrap();
+        nano.trigger(Coin).unwrap();
+        assert_eq!(nano.context().refunds, 1);
+
+        nano.trigger(Push).unwrap();
+        nano.trigger(Kick).unwrap();
+        nano.trigger(Repair).unwrap();
+        nano.trigger(Kick).unwrap();
+        assert_eq!(nano.trigger(Repair), Err(MachineError::GuardRejected));
+        assert_eq!(*nano.state(), Broken);
+
+        nano.context_mut().kicks = 0;
+        nano.trigger(Repair).unwrap();
+        assert_eq!(*nano.state(), Locked);
+    }
+
+    #[test]
+    fn errors() {
+        let mut nano = TURNSTILE;
+        assert_eq!(nano.trigger(Repair), Err(MachineError::StateInvalid));
+
+        let mut plain = PLAIN.clone();
+        assert_eq!(plain.trigger(Push), Err(MachineError::EventInvalid));
+        assert_eq!(*plain.state(), Locked);
+    }
+
+    #[test]
+    fn last_definition_wins() {
+        let mut nano: StaticMachine<State, Event, 2> =
+            StaticMachine::new(Locked)
+                .when(Coin, Locked, Unlocked)
+                .when(Coin, Locked, Broken);
+
+        nano.trigger(Coin).unwrap();
+        assert_eq!(*nano.state(), Broken);
+    }
+
+    #[test]
+    fn listed_transitions() {
+        let mut transitions = [(Coin, Locked, Locked); 6];
+        for (slot, t) in transitions.iter_mut().zip(TURNSTILE.transitions()) {
+            *slot = t;
+        }
+
+        assert_eq!(TURNSTILE.transitions().count(), 6);
+        assert_eq!(transitions[0], (Coin, Locked, Unlocked));
+        assert_eq!(transitions[3], (Push, Locked, Locked));
+    }
+
+    #[test]
+    #[should_panic(expected = "too many transitions")]
+    fn capacity() {
+        let _ = StaticMachine::<State, Event, 1>::new(Locked)
+            .when(Coin, Locked, Unlocked)
+            .when(Push, Unlocked, Locked);
+    }
+
+    #[test]
+    fn default() {
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+        struct Idle;
+
+        let mut nano: StaticMachine<Idle, Event, 0> = StaticMachine::default();
+        assert_eq!(nano.trigger(Coin), Err(MachineError::EventInvalid));
+    }
+}
@@ -0,0 +1,365 @@
+use alloc::{collections::BTreeSet, vec::Vec};
+use core::{borrow::Borrow, hash::Hash};
+
+use hashbrown::HashSet;
+
+use crate::{Machine, Nfa};
+
+/// Operations treating a machine as an automaton over its events.
+///
+/// The language of a machine is the set of event sequences that lead from its
+/// current state to an [accepting](Machine::set_accepting) state. It only
+/// depends on the transitions and the accepting states: guards, actions and
+/// callbacks are ignored, and the machines built here have none.
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Whether triggering `events` from the current state would end in an
+    /// accepting state.
+    ///
+    /// No callbacks run and the machine itself is left untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::new("locked");
+    /// nano.when("insert coin", "locked", "unlocked");
+    /// nano.when("turn knob", "unlocked", "locked");
+    /// nano.set_accepting("locked");
+    ///
+    /// assert!(nano.accepts(["insert coin", "turn knob"]));
+    /// assert!(!nano.accepts(["insert coin"]));
+    /// assert!(!nano.accepts(["turn knob"]));
+    /// ```
+    pub fn accepts<I>(&self, events: I) -> bool
+    where
+        I: IntoIterator,
+        I::Item: Borrow<E>,
+    {
+        let mut state = &self.state;
+        for event in events {
+            match self.target(event.borrow(), state) {
+                Some(target) => state = target,
+                None => return false,
+            }
+        }
+        self.is_accepting(state)
+    }
+
+    /// Build a machine accepting the sequences accepted by both `self` and
+    /// `other`.
+    ///
+    /// Its states pair up a state of each machine, starting from their current
+    /// states, and an event is only valid when it is valid in both.
+    pub fn intersection<T, C2, P2>(
+        &self,
+        other: &Machine<T, E, C2, P2>,
+    ) -> Machine<(S, T), E>
+    where
+        T: Eq + Hash + Clone,
+        P2: ?Sized,
+    {
+        let events: Vec<&E> = self.events().collect();
+        explore(
+            (self.state.clone(), other.state.clone()),
+            &events,
+            |(s, t), event| {
+                let s = self.target(event, s)?;
+                let t = other.target(event, t)?;
+                Some((s.clone(), t.clone()))
+            },
+            |(s, t)| self.is_accepting(s) && other.is_accepting(t),
+        )
+    }
+
+    /// Build a machine accepting the sequences accepted by `self`, `other` or
+    /// both.
+    ///
+    /// Its states pair up a state of each machine, starting from their current
+    /// states. A side becomes `None` once it has rejected an event, and an
+    /// event is valid as long as one of the sides accepts it.
+    pub fn union<T, C2, P2>(
+        &self,
+        other: &Machine<T, E, C2, P2>,
+    ) -> Machine<(Option<S>, Option<T>), E>
+    where
+        T: Eq + Hash + Clone,
+        P2: ?Sized,
+    {
+        let mut events: Vec<&E> = self.events().collect();
+        events.extend(
+            other
+                .events()
+                .filter(|e| !self.definition.transitions.contains_key(*e)),
+        );
+        explore(
+            (Some(self.state.clone()), Some(other.state.clone())),
+            &events,
+            |(s, t), event| {
+                let s = s.as_ref().and_then(|s| self.target(event, s));
+                let t = t.as_ref().and_then(|t| other.target(event, t));
+                if s.is_none() && t.is_none() {
+                    return None;
+                }
+                Some((s.cloned(), t.cloned()))
+            },
+            |(s, t)| {
+                s.as_ref().is_some_and(|s| self.is_accepting(s))
+                    || t.as_ref().is_some_and(|t| other.is_accepting(t))
+            },
+        )
+    }
+
+    /// Build a machine accepting exactly the sequences of events from
+    /// `alphabet` that `self` rejects.
+    ///
+    /// The machine is completed with a `None` state that every missing
+    /// transition leads to, so every event of `alphabet` is valid from every
+    /// state. Transitions for events outside `alphabet` are dropped.
+    pub fn complement<I>(&self, alphabet: I) -> Machine<Option<S>, E>
+    where
+        I: IntoIterator<Item = E>,
+    {
+        let alphabet: Vec<E> = alphabet.into_iter().collect();
+        let events: Vec<&E> = alphabet.iter().collect();
+        explore(
+            Some(self.state.clone()),
+            &events,
+            |s, event| {
+                Some(s.as_ref().and_then(|s| self.target(event, s)).cloned())
+            },
+            |s| !s.as_ref().is_some_and(|s| self.is_accepting(s)),
+        )
+    }
+
+    /// Build a machine where the given events are hidden.
+    ///
+    /// Hidden events become silent moves, so the result accepts a sequence
+    /// whenever `self` accepts it with hidden events interleaved anywhere. It
+    /// is built with [`Nfa::determinize`], so its states are sets of states
+    /// of `self`.
+    pub fn hide<I>(&self, hidden: I) -> Machine<BTreeSet<S>, E>
+    where
+        S: Ord,
+        I: IntoIterator<Item = E>,
+    {
+        let hidden: HashSet<E> = hidden.into_iter().collect();
+        let mut nfa = Nfa::new(self.state.clone());
+        for (event, from, to) in self.transitions() {
+            if hidden.contains(event) {
+                nfa.when_epsilon(from.clone(), to.clone());
+            } else {
+                nfa.when(event.clone(), from.clone(), to.clone());
+            }
+        }
+        for state in self.accepting_states() {
+            nfa.set_accepting(state.clone());
+        }
+        nfa.determinize()
+    }
+
+    /// Build a machine that only observes the given events, hiding every
+    /// other one.
+    ///
+    /// See [`Machine::hide`].
+    pub fn project<I>(&self, visible: I) -> Machine<BTreeSet<S>, E>
+    where
+        S: Ord,
+        I: IntoIterator<Item = E>,
+    {
+        let visible: HashSet<E> = visible.into_iter().collect();
+        let hidden: Vec<E> =
+            self.events().filter(|e| !visible.contains(*e)).cloned().collect();
+        self.hide(hidden)
+    }
+}
+
+/// Build the part of a machine reachable from `start`, where `step` gives the
+/// target of each event and `accepting` tells which states are accepting.
+pub(crate) fn explore<Q, E, F, A>(
+    start: Q,
+    events: &[&E],
+    step: F,
+    accepting: A,
+) -> Machine<Q, E>
+where
+    Q: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    F: Fn(&Q, &E) -> Option<Q>,
+    A: Fn(&Q) -> bool,
+{
+    let mut machine = Machine::new(start.clone());
+    let mut seen = HashSet::new();
+    let mut pending = Vec::new();
+    seen.insert(start.clone());
+    pending.push(start);
+
+    while let Some(state) = pending.pop() {
+        if accepting(&state) {
+            machine.set_accepting(state.clone());
+        }
+        for event in events {
+            let Some(next) = step(&state, event) else {
+                continue;
+            };
+            if seen.insert(next.clone()) {
+                pending.push(next.clone());
+            }
+            machine.when((*event).clone(), state.clone(), next);
+        }
+    }
+    machine
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{Machine, testing::words};
+
+    const ALPHABET: [char; 3] = ['a', 'b', 'c'];
+
+    /// Accepts an even number of `a`s. `b` is only valid when even, `c` is
+    /// never valid.
+    fn even() -> Machine<u8, char> {
+        let mut nano = Machine::new(0);
+        nano.when('a', 0, 1);
+        nano.when('a', 1, 0);
+        nano.when('b', 0, 0);
+        nano.set_accepting(0);
+        nano
+    }
+
+    /// Accepts sequences ending in `c`, where `a` may not follow a `c`.
+    fn ends_in_c() -> Machine<&'static str, char> {
+        let mut nano = Machine::new("other");
+        nano.when_iter('c', [("other", "c"), ("c", "c")]);
+        nano.when_iter('b', [("other", "other"), ("c", "other")]);
+        nano.when('a', "other", "other");
+        nano.set_accepting("c");
+        nano
+    }
+
+    /// Whether `nano` accepts some sequence of at most `len` events that reads
+    /// as `word` once the events in `hidden` are removed.
+    fn accepts_hiding(
+        nano: &Machine<u8, char>,
+        hidden: &[char],
+        word: &[char],
+        len: usize,
+    ) -> bool {
+        words(&ALPHABET, len).into_iter().any(|w| {
+            let visible: Vec<char> =
+                w.iter().copied().filter(|c| !hidden.contains(c)).collect();
+            visible == word && nano.accepts(&w)
+        })
+    }
+
+    #[test]
+    fn accepts_matches_triggering() {
+        let nano = even();
+
+        for word in words(&['a', 'b', 'c', 'd'], 5) {
+            let mut run = nano.clone();
+            let valid = word.iter().all(|c| run.trigger(c).is_ok());
+            let expected = valid && run.is_accepting(run.state());
+            assert_eq!(nano.accepts(&word), expected, "word {word:?}");
+        }
+    }
+
+    #[test]
+    fn accepts_starts_at_current_state() {
+        let mut nano = even();
+        nano.trigger(&'a').unwrap();
+
+        assert!(!nano.accepts([] as [char; 0]));
+        assert!(nano.accepts(['a']));
+        assert_eq!(*nano.state(), 1);
+    }
+
+    #[test]
+    fn intersection() {
+        let (left, right) = (even(), ends_in_c());
+        let both = left.intersection(&right);
+
+        assert_eq!(*both.state(), (0, "other"));
+        for word in words(&ALPHABET, 6) {
+            let expected = left.accepts(&word) && right.accepts(&word);
+            assert_eq!(both.accepts(&word), expected, "word {word:?}");
+        }
+    }
+
+    #[test]
+    fn union() {
+        let (left, right) = (even(), ends_in_c());
+        let either = left.union(&right);
+
+        for word in words(&['a', 'b', 'c', 'd'], 6) {
+            let expected = left.accepts(&word) || right.accepts(&word);
+            assert_eq!(either.accepts(&word), expected, "word {word:?}");
+        }
+    }
+
+    #[test]
+    fn complement() {
+        let nano = even();
+        let not = nano.complement(ALPHABET);
+
+        for word in words(&ALPHABET, 6) {
+            assert_eq!(
+                not.accepts(&word),
+                !nano.accepts(&word),
+                "word {word:?}"
+            );
+        }
+        assert!(!not.accepts(['d']));
+        assert!(not.clone().trigger(&'c').is_ok());
+    }
+
+    #[test]
+    fn complement_twice() {
+        let nano = even();
+        let same = nano.complement(ALPHABET).complement(ALPHABET);
+
+        for word in words(&ALPHABET, 6) {
+            assert_eq!(
+                same.accepts(&word),
+                nano.accepts(&word),
+                "word {word:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn hide() {
+        let mut nano = even();
+        nano.when('c', 1, 2);
+        nano.when('b', 2, 0);
+        nano.set_accepting(2);
+        let hidden = nano.hide(['b']);
+
+        assert!(hidden.events().all(|e| *e != 'b'));
+        for word in words(&['a', 'c'], 2) {
+            let expected = accepts_hiding(&nano, &['b'], &word, 8);
+            assert_eq!(hidden.accepts(&word), expected, "word {word:?}");
+        }
+    }
+
+    #[test]
+    fn project() {
+        let mut nano = even();
+        nano.when('c', 1, 2);
+        nano.when('b', 2, 0);
+        let projected = nano.project(['a', 'c']);
+        let hidden = nano.hide(['b']);
+
+        for word in words(&ALPHABET, 4) {
+            assert_eq!(projected.accepts(&word), hidden.accepts(&word));
+        }
+    }
+}
@@ -10,6 +10,7 @@
 //! # Examples
 //!
 //! ```rust
+//! # #[cfg(feature = "alloc")] {
 //! use nanomachine::Machine;
 //!
 //! #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -48,58 +49,323 @@
 //!
 //! // Pass a payload when triggering.
 //! nano.trigger_with(&Event::InsertCoin, &50u32);
+//! # }
 //! ```
 
 #![warn(clippy::perf, clippy::pedantic, missing_docs)]
 #![no_std]
 
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+#[cfg(feature = "alloc")]
+mod callback;
+#[cfg(feature = "alloc")]
+mod check;
+#[cfg(feature = "alloc")]
+mod compiled;
+#[cfg(feature = "alloc")]
+mod coverage;
+#[cfg(feature = "alloc")]
+mod definition;
+#[cfg(feature = "alloc")]
+mod diff;
+#[cfg(feature = "alloc")]
+mod equivalence;
 mod error;
+mod fixed;
+#[cfg(feature = "alloc")]
+mod language;
+#[cfg(feature = "alloc")]
+mod metrics;
+#[cfg(feature = "alloc")]
+mod minimize;
+#[cfg(feature = "alloc")]
+mod nfa;
+#[cfg(feature = "alloc")]
+mod observer;
+#[cfg(feature = "alloc")]
+mod output;
+#[cfg(feature = "alloc")]
+mod parallel;
+mod payload;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+#[cfg(all(test, feature = "alloc"))]
+mod testing;
+#[cfg(any(feature = "tracing", feature = "log"))]
+mod trace;
+#[cfg(feature = "alloc")]
+mod walk;
+#[cfg(feature = "alloc")]
+pub use callback::{CallbackGuard, CallbackId};
+#[cfg(feature = "alloc")]
+pub use check::{Formula, Violation};
+#[cfg(feature = "alloc")]
+pub use compiled::{Compiled, EventId};
+#[cfg(feature = "alloc")]
+pub use coverage::Coverage;
+#[cfg(feature = "alloc")]
+pub use definition::{Definition, Instance, TransitionBuilder};
+#[cfg(feature = "alloc")]
+pub use diff::{Change, Diff, diff};
+#[cfg(feature = "alloc")]
+pub use equivalence::Equivalence;
 pub use error::MachineError;
-
+#[cfg(feature = "alloc")]
+pub use error::{CallbackError, SequenceError, TriggerError};
+pub use fixed::{StaticCallback, StaticGuard, StaticMachine};
+#[cfg(feature = "alloc")]
+pub use metrics::{Clock, Histogram, Metrics, Stats};
+#[cfg(feature = "alloc")]
+pub use nfa::Nfa;
+#[cfg(feature = "alloc")]
+pub use observer::MachineObserver;
+#[cfg(feature = "alloc")]
+pub use output::{Mealy, Moore, Outputs};
+#[cfg(feature = "alloc")]
+pub use parallel::Parallel;
+pub use payload::Payload;
+#[cfg(feature = "alloc")]
+pub use walk::{Rng, WalkError, Walker};
+
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
-use alloc::{rc::Rc, vec::Vec};
-use core::{any::Any, fmt::Debug, hash::Hash};
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::{any::Any, error::Error, fmt::Debug, hash::Hash};
 
-use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "alloc")]
+use callback::Callback;
+#[cfg(feature = "alloc")]
+use hashbrown::HashMap;
+#[cfg(feature = "alloc")]
+use metrics::Recorder;
 
 /// A specialized `Result` type for operations on a [`Machine`].
 ///
 /// This is an alias for `core::result::Result<T, MachineError>`.
 pub type MachineResult<T> = core::result::Result<T, MachineError>;
 
+#[cfg(feature = "alloc")]
 /// A trigger key for callbacks, either targeting a specific state or any state.
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 enum Trigger<S> {
     /// Callback should fire when entering this specific state.
-    State(S),
+    Enter(S),
+    /// Callback should fire when exiting this specific state.
+    Exit(S),
     /// Callback should fire on any state transition.
     AnyState,
 }
 
-/// Any `Fn` that takes an event and some arbitrary payload as input.
-type Callback<E> = Rc<dyn Fn(E, &dyn Any)>;
+/// How a transition treats the state it leaves and the state it enters.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum TransitionKind {
+    /// The machine exits the source state and enters the target state, even
+    /// when both are the same. Exit and enter callbacks fire.
+    External,
+    /// The machine stays in the current state. Only the transition's action
+    /// and transition callbacks fire; no state is exited or entered.
+    Internal,
+}
+
+#[cfg(feature = "alloc")]
+/// A predicate over the machine's context that must hold for a transition to
+/// be taken.
+type Guard<C> = Rc<dyn Fn(&C) -> bool>;
+
+#[cfg(feature = "alloc")]
+/// The target of a transition together with its kind, guard and action.
+struct Transition<S, E, C, P: ?Sized> {
+    target: S,
+    kind: TransitionKind,
+    guard: Option<Guard<C>>,
+    action: Option<Callback<E, C, P>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C, P: ?Sized> Transition<S, E, C, P> {
+    fn external(target: S) -> Self {
+        Transition {
+            target,
+            kind: TransitionKind::External,
+            guard: None,
+            action: None,
+        }
+    }
+
+    /// Whether the guard, if any, allows taking this transition.
+    fn allows(&self, context: &C) -> bool {
+        self.guard.as_ref().is_none_or(|guard| guard(context))
+    }
+}
+
+#[cfg(feature = "alloc")]
+/// The transition table, keyed by event and then by source state.
+type Transitions<S, E, C, P> = HashMap<E, HashMap<S, Transition<S, E, C, P>>>;
+
+#[cfg(feature = "alloc")]
+impl<S: Clone, E, C, P: ?Sized> Clone for Transition<S, E, C, P> {
+    fn clone(&self) -> Self {
+        Transition {
+            target: self.target.clone(),
+            kind: self.kind,
+            guard: self.guard.clone(),
+            action: self.action.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+/// The states a triggered event went between, held until the transition can
+/// no longer be rolled back to report it to coverage and metrics.
+struct Taken<S> {
+    from: S,
+    to: S,
+    exited: bool,
+}
 
+#[cfg(feature = "alloc")]
 /// A generic finite state machine.
 ///
 /// # Type Parameters
 /// - `S`: The state type. Must implement `Eq + Hash + Clone`.
 /// - `E`: The event type. Must implement `Eq + Hash + Clone`.
-#[derive(Clone)]
-pub struct Machine<S, E> {
+/// - `C`: The extended state, or context, owned by the machine. Defaults to
+///   `()`, see [`Machine::with_context`].
+/// - `P`: The payload type passed to callbacks. Defaults to `dyn Any`, which
+///   accepts payloads of any type, see [`Payload`]. It comes after `C`, so a
+///   typed machine without context is a `Machine<S, E, (), P>`, while a
+///   `Machine<S, E, u32>` is a machine owning a `u32` context, not one taking
+///   `u32` payloads.
+pub struct Machine<S, E, C = (), P: ?Sized = dyn Any> {
+    definition: Rc<Definition<S, E, C, P>>,
     state: S,
-    transitions: HashMap<E, HashMap<S, S>>,
-    callbacks: HashMap<Trigger<S>, Vec<Callback<E>>>,
+    context: C,
+    coverage: Option<Coverage<S, E>>,
+    metrics: Option<Recorder<S, E>>,
 }
 
+#[cfg(feature = "alloc")]
 impl<S, E> Machine<S, E> {
     /// Create a new state machine with the given initial state.
+    ///
+    /// The machine accepts payloads of any type. Use [`Machine::typed`] for a
+    /// machine that only accepts a single payload type.
     pub fn new(initial_state: S) -> Self {
-        Machine {
-            state: initial_state,
-            transitions: HashMap::new(),
-            callbacks: HashMap::new(),
-        }
+        Machine::from_parts(initial_state, ())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C> Machine<S, E, C> {
+    /// Create a new state machine with the given initial state and context.
+    ///
+    /// The context holds the data the machine works on, such as counters or
+    /// identifiers. Callbacks registered with `on_enter_ctx`, `on_exit_ctx`
+    /// and `on_transition_ctx` receive it as `&mut C`, and guards registered
+    /// with `when_guarded` receive it as `&C`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::with_context("locked", 0u32);
+    /// nano.when("insert coin", "locked", "unlocked");
+    /// nano.when("turn knob", "unlocked", "locked");
+    ///
+    /// nano.on_enter_ctx("unlocked", |_, (): &(), coins: &mut u32| *coins += 1);
+    ///
+    /// nano.trigger(&"insert coin").unwrap();
+    /// assert_eq!(*nano.context(), 1);
+    /// ```
+    pub fn with_context(initial_state: S, context: C) -> Self {
+        Machine::from_parts(initial_state, context)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, P> Machine<S, E, (), P> {
+    /// Create a new state machine with the given initial state, whose payloads
+    /// are of type `P`.
+    ///
+    /// Callbacks receive a `&P` directly, and registering a callback or
+    /// triggering an event with any other payload type fails to compile:
+    ///
+    /// ```rust,compile_fail
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::<_, _, (), u32>::typed("locked");
+    /// nano.when("insert coin", "locked", "unlocked");
+    ///
+    /// nano.on_enter_with("unlocked", |_, note: &String| println!("{note}"));
+    /// ```
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::<_, _, (), u32>::typed("locked");
+    /// nano.when("insert coin", "locked", "unlocked");
+    ///
+    /// nano.on_enter_with("unlocked", |_, amount: &u32| {
+    ///     println!("Unlocked after {amount} cents");
+    /// });
+    ///
+    /// nano.trigger_with(&"insert coin", &50).unwrap();
+    /// ```
+    pub fn typed(initial_state: S) -> Self {
+        Machine::from_parts(initial_state, ())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C, P> Machine<S, E, C, P> {
+    /// Create a new state machine with the given initial state and context,
+    /// whose payloads are of type `P`.
+    ///
+    /// Combines [`Machine::with_context`] and [`Machine::typed`].
+    pub fn typed_with_context(initial_state: S, context: C) -> Self {
+        Machine::from_parts(initial_state, context)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C, P: ?Sized> Machine<S, E, C, P> {
+    fn from_parts(initial_state: S, context: C) -> Self {
+        let instance = Instance::with_context(initial_state, context);
+        Machine::from_definition(Rc::new(Definition::default()), instance)
+    }
+
+    /// Create a machine from a shared definition and an instance of it.
+    pub fn from_definition(
+        definition: Rc<Definition<S, E, C, P>>,
+        instance: Instance<S, C>,
+    ) -> Self {
+        let (state, context) = instance.into_parts();
+        Machine { definition, state, context, coverage: None, metrics: None }
+    }
+
+    /// The definition of the machine, holding its transitions and callbacks.
+    ///
+    /// Clones of a machine share their definition until one of them defines a
+    /// transition or registers a callback, which gives it its own copy.
+    #[inline]
+    pub fn definition(&self) -> &Rc<Definition<S, E, C, P>> {
+        &self.definition
+    }
+
+    /// The state and context of the machine, as an instance of its
+    /// [definition](Machine::definition).
+    ///
+    /// Recorded [coverage](Machine::record_coverage) and the
+    /// [metrics](Machine::record_metrics) sink are dropped.
+    pub fn into_instance(self) -> Instance<S, C> {
+        Instance::with_context(self.state, self.context)
     }
 
     /// A reference to the current state of the machine.
@@ -107,29 +373,39 @@ impl<S, E> Machine<S, E> {
     pub fn state(&self) -> &S {
         &self.state
     }
+
+    /// A reference to the machine's context.
+    #[inline]
+    pub fn context(&self) -> &C {
+        &self.context
+    }
+
+    /// A mutable reference to the machine's context.
+    #[inline]
+    pub fn context_mut(&mut self) -> &mut C {
+        &mut self.context
+    }
 }
 
-impl<S, E> Machine<S, E>
+#[cfg(feature = "alloc")]
+impl<S, E, C, P> Machine<S, E, C, P>
 where
     S: Eq + Hash + Clone,
     E: Eq + Hash + Clone,
+    P: ?Sized,
 {
     /// Returns an iterator over all states known to the machine.
+    ///
+    /// These are the states that appear in a transition or that are
+    /// [accepting](Machine::set_accepting).
     pub fn states(&self) -> impl Iterator<Item = &S> {
-        let mut used = HashSet::new();
-        for state_map in self.transitions.values() {
-            for (from, to) in state_map {
-                used.insert(from);
-                used.insert(to);
-            }
-        }
-        used.into_iter()
+        self.definition.states()
     }
 
     /// Returns an iterator over all events the machine can react to.
     #[inline]
     pub fn events(&self) -> impl Iterator<Item = &E> {
-        self.transitions.keys()
+        self.definition.events()
     }
 
     /// Returns an iterator over events valid from the current state.
@@ -137,102 +413,494 @@ where
     /// Only events that have a defined transition from the machine's current
     /// state are included.
     pub fn triggerable_events(&self) -> impl Iterator<Item = &E> {
-        self.transitions
-            .iter()
-            .filter(|(_, mp)| mp.get(self.state()).is_some())
-            .map(|(e, _)| e)
+        self.definition.triggerable_events(&self.state)
+    }
+
+    /// Returns an iterator over all transitions as `(event, from, to)`
+    /// triples.
+    ///
+    /// [Internal](TransitionKind::Internal) transitions are reported with the
+    /// same state as `from` and `to`.
+    pub fn transitions(&self) -> impl Iterator<Item = (&E, &S, &S)> {
+        self.definition.transitions()
+    }
+
+    /// A mutable reference to the definition, copied first if it is shared
+    /// with other machines.
+    fn definition_mut(&mut self) -> &mut Definition<S, E, C, P> {
+        Rc::make_mut(&mut self.definition)
     }
 }
 
-impl<S, E> Machine<S, E>
+#[cfg(feature = "alloc")]
+impl<S, E, C, P> Machine<S, E, C, P>
 where
+    P: ?Sized,
     S: Eq + Hash + Clone,
     E: Eq + Hash + Clone,
 {
     /// When `event` occurs in `state`, move to `new_state`.
     ///
+    /// The transition is [external](TransitionKind::External): `state` is
+    /// exited and `new_state` is entered, even if they are the same state.
+    ///
     /// Multiple calls to `when` for the same `(event, state)` will overwrite
     /// the previous `new_state`.
     pub fn when(&mut self, event: E, state: S, new_state: S) {
-        self.transitions.entry(event).or_default().insert(state, new_state);
+        self.definition_mut().when(event, state, new_state);
     }
 
     /// Define multiple transitions for a single event.
     ///
-    /// The `mapping` iterator should yield `(from_state, to_state)` pairs.
+    /// The `mapping` iterator should yield `(from_state, to_state)` pairs. All
+    /// of them are [external](TransitionKind::External) transitions.
     pub fn when_iter<I>(&mut self, event: E, mapping: I)
     where
         I: IntoIterator<Item = (S, S)>,
     {
-        self.transitions.entry(event).or_default().extend(mapping);
+        self.definition_mut().when_iter(event, mapping);
+    }
+
+    /// When `event` occurs in `state`, exit and re-enter `state`.
+    ///
+    /// This is an [external](TransitionKind::External) self-transition, and is
+    /// equivalent to `when(event, state.clone(), state)`. Both exit and enter
+    /// callbacks for `state` fire.
+    pub fn when_self(&mut self, event: E, state: S) {
+        self.definition_mut().when_self(event, state);
+    }
+
+    /// When `event` occurs in `state`, stay in `state` without exiting it.
+    ///
+    /// This is an [internal](TransitionKind::Internal) transition: only the
+    /// callbacks registered with `on_transition` and `on_transition_with`
+    /// fire. Exit and enter callbacks for `state` do not. Use
+    /// [`Machine::transition`] to give it an action or a guard.
+    pub fn when_internal(&mut self, event: E, state: S) {
+        self.definition_mut().when_internal(event, state);
+    }
+
+    /// When `event` occurs in `state`, move to `new_state` if `guard` allows
+    /// it.
+    ///
+    /// The guard is evaluated against the machine's context every time the
+    /// event is triggered in `state`. When it returns `false`, the machine
+    /// stays put, no callbacks fire and the trigger returns
+    /// [`MachineError::GuardRejected`].
+    ///
+    /// Like `when`, this overwrites any transition previously defined for the
+    /// same `(event, state)`, including its guard.
+    pub fn when_guarded<G>(
+        &mut self,
+        event: E,
+        state: S,
+        new_state: S,
+        guard: G,
+    ) where
+        G: Fn(&C) -> bool + 'static,
+    {
+        self.definition_mut().when_guarded(event, state, new_state, guard);
+    }
+
+    /// When `event` occurs in `state`, move to `new_state` and run `action`.
+    ///
+    /// The action belongs to this edge only, so it runs exactly when the
+    /// machine goes from `state` to `new_state` through `event`. It runs after
+    /// the exit callbacks of `state` and before the enter callbacks of
+    /// `new_state`, and receives the event, the payload and the machine's
+    /// context. Like `on_enter_with`, it only runs if the payload is a `T`.
+    ///
+    /// Like `when`, this overwrites any transition previously defined for the
+    /// same `(event, state)`, including its action.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::with_context("paid", 0u32);
+    /// nano.when_then("cancel", "paid", "cancelled", |_, amount: &u32, refunded: &mut u32| {
+    ///     *refunded += amount;
+    /// });
+    ///
+    /// nano.trigger_with(&"cancel", &250u32).unwrap();
+    /// assert_eq!(*nano.context(), 250);
+    /// ```
+    pub fn when_then<T, F>(
+        &mut self,
+        event: E,
+        state: S,
+        new_state: S,
+        action: F,
+    ) where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        self.definition_mut().when_then(event, state, new_state, action);
+    }
+
+    /// When `event` occurs in `state`, move to `new_state`, returning a
+    /// builder to combine a guard, an action and the transition's kind.
+    ///
+    /// The transition is defined right away as with `when`, overwriting any
+    /// transition previously defined for the same `(event, state)`, and the
+    /// builder changes it in place. The guard and action behave as with
+    /// [`Machine::when_guarded`] and [`Machine::when_then`]. An
+    /// [internal](TransitionKind::Internal) transition runs its action
+    /// before the transition callbacks, without exiting `state`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::with_context("open", 0u32);
+    /// nano.transition("deposit", "open", "open")
+    ///     .internal()
+    ///     .guard(|balance: &u32| *balance < 100)
+    ///     .then(|_, amount: &u32, balance: &mut u32| *balance += amount);
+    ///
+    /// nano.trigger_with(&"deposit", &150u32).unwrap();
+    /// assert_eq!(*nano.context(), 150);
+    /// assert!(nano.trigger_with(&"deposit", &10u32).is_err());
+    /// ```
+    pub fn transition(
+        &mut self,
+        event: E,
+        state: S,
+        new_state: S,
+    ) -> TransitionBuilder<'_, S, E, C, P> {
+        self.definition_mut().transition(event, state, new_state)
+    }
+
+    /// The kind of the transition defined for `event` in `state`, if any.
+    pub fn transition_kind(
+        &self,
+        event: &E,
+        state: &S,
+    ) -> Option<TransitionKind> {
+        self.definition.transition_kind(event, state)
+    }
+
+    /// The state `event` leads to from `state`, if a transition is defined.
+    fn target(&self, event: &E, state: &S) -> Option<&S> {
+        self.definition.target(event, state)
+    }
+
+    /// Designate `state` as an accepting, or final, state.
+    ///
+    /// Accepting states don't change how events are handled. They matter when
+    /// the machine is treated as an automaton, e.g. by [`Machine::accepts`] or
+    /// [`Machine::minimize`].
+    pub fn set_accepting(&mut self, state: S) {
+        self.definition_mut().set_accepting(state);
+    }
+
+    /// Whether `state` is an accepting state.
+    #[inline]
+    pub fn is_accepting(&self, state: &S) -> bool {
+        self.definition.is_accepting(state)
     }
 
-    /// Internal helper to wrap a callback that expects a specific payload type
-    /// `P`.
-    #[doc(hidden)]
-    fn wrap_callback<P, F>(callback: F) -> Callback<E>
+    /// Returns an iterator over the accepting states.
+    #[inline]
+    pub fn accepting_states(&self) -> impl Iterator<Item = &S> {
+        self.definition.accepting_states()
+    }
+
+    /// Unregister the callback or [observer](Machine::observe) identified by
+    /// `id`.
+    ///
+    /// Returns `true` if the callback was registered on this machine.
+    pub fn remove_callback(&mut self, id: CallbackId) -> bool {
+        self.definition_mut().remove_callback(id)
+    }
+
+    /// Unregister every enter and exit callback registered for `state`.
+    ///
+    /// Callbacks registered with `on_transition` and `on_transition_with` are
+    /// left untouched.
+    pub fn clear_callbacks(&mut self, state: &S) {
+        self.definition_mut().clear_callbacks(state);
+    }
+
+    /// Hand the callback identified by `id` over to the returned guard.
+    ///
+    /// The machine keeps only a weak reference to the callback from then on,
+    /// so it stops firing, and is dropped along with everything it captured,
+    /// when the guard is dropped.
+    /// Returns `None` if no callback with this `id` is registered, or if it is
+    /// already guarded. Observers need no guard, they are unregistered when
+    /// dropped.
+    ///
+    /// Guarding changes the machine's [definition](Machine::definition), so a
+    /// machine that shares it with its clones gets its own copy first. Only
+    /// this machine and the clones made from it afterwards share the guarded
+    /// callback and stop firing it when the guard is dropped; clones made
+    /// before keep firing it.
+    pub fn guard_callback(&mut self, id: CallbackId) -> Option<CallbackGuard>
     where
+        E: 'static,
+        C: 'static,
         P: 'static,
-        F: Fn(E, &P) + 'static,
     {
-        Rc::new(move |evt, payload| {
-            if let Some(p) = payload.downcast_ref::<P>() {
-                callback(evt, p);
-            }
-        })
+        self.definition_mut().guard_callback(id)
+    }
+
+    /// Include states and events, formatted with their `Debug`
+    /// implementation, in what the machine reports to `tracing` and `log`.
+    ///
+    /// Only available with the `tracing` or `log` feature. With either one,
+    /// triggering an event runs its callbacks inside a `trigger` span, and
+    /// emits an event under the `nanomachine` target with an `outcome` field
+    /// and, on failure, an `error` field. `outcome` is `ok` or `rejected`,
+    /// when the event had no transition or its guard rejected it, both at the
+    /// debug level, or `failed` when a callback failed (info). `error` is the
+    /// name of the [`MachineError`] variant.
+    ///
+    /// Machines don't require `Debug` states and events, so this is how they
+    /// get into the report: once it is called, the span has `from` and
+    /// `event` fields, and the event `from`, `to` and `event` fields. Like
+    /// callbacks, the formatting belongs to the machine's
+    /// [definition](Machine::definition): it applies to this machine and the
+    /// clones made from it afterwards, not to clones made before.
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    pub fn instrument(&mut self)
+    where
+        S: Debug,
+        E: Debug,
+    {
+        self.definition_mut().instrument();
+    }
+
+    /// Register an observer, notified of every transition and rejection.
+    ///
+    /// The machine only keeps a weak reference to `observer`, which stops
+    /// being notified once the last [`Rc`] pointing to it is dropped. See
+    /// [`MachineObserver`] for when each hook fires.
+    ///
+    /// Like callbacks, observers belong to the machine's
+    /// [definition](Machine::definition). A machine that shares its definition
+    /// gets its own copy first, so the observer is notified by this machine
+    /// and the clones made from it afterwards, not by clones made before.
+    pub fn observe<O>(&mut self, observer: &Rc<O>) -> CallbackId
+    where
+        O: MachineObserver<S, E> + 'static,
+    {
+        self.definition_mut().observe(observer)
     }
 
     /// Register a callback that only cares about the event (no payload).
-    pub fn on_enter<F>(&mut self, state: S, callback: F)
+    ///
+    /// Like every registration function, this returns a [`CallbackId`] that
+    /// can be used to unregister the callback later.
+    pub fn on_enter<F>(&mut self, state: S, callback: F) -> CallbackId
     where
         F: Fn(E) + 'static,
     {
-        let callback: Callback<E> = Rc::new(move |evt, _payload| {
-            callback(evt);
-        });
-        self.callbacks.entry(Trigger::State(state)).or_default().push(callback);
+        self.definition_mut().on_enter(state, callback)
     }
 
-    /// Register a callback that expects a payload of type P.
+    /// Register a callback that expects a payload of type `T`.
     ///
-    /// The callback takes the triggering event and a payload of type `P`. It
-    /// will only be invoked if the payload downcasts to `P` successfully.
-    pub fn on_enter_with<P, F>(&mut self, state: S, callback: F)
+    /// The callback takes the triggering event and a payload of type `T`. On a
+    /// machine with `dyn Any` payloads, it will only be invoked if the payload
+    /// downcasts to `T` successfully. On a [typed](Machine::typed) machine, `T`
+    /// must be the machine's payload type.
+    pub fn on_enter_with<T, F>(&mut self, state: S, callback: F) -> CallbackId
     where
-        P: 'static,
-        F: Fn(E, &P) + 'static,
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static,
+    {
+        self.definition_mut().on_enter_with(state, callback)
+    }
+
+    /// Register a callback to fire when exiting `state`, without a payload.
+    ///
+    /// Exit callbacks fire before enter callbacks of the new state. They do not
+    /// fire for [internal](TransitionKind::Internal) transitions.
+    pub fn on_exit<F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        F: Fn(E) + 'static,
+    {
+        self.definition_mut().on_exit(state, callback)
+    }
+
+    /// Register a callback to fire when exiting `state`, with a payload of
+    /// type `T`.
+    ///
+    /// Works similarly to `on_exit`, but will only be invoked if the payload
+    /// is a `T`, see [`Machine::on_enter_with`].
+    pub fn on_exit_with<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static,
     {
-        let callback = Self::wrap_callback(callback);
-        self.callbacks.entry(Trigger::State(state)).or_default().push(callback);
+        self.definition_mut().on_exit_with(state, callback)
     }
 
     /// Register a callback to fire on any state transition.
     ///
     /// Works similarly to `on_enter`, but the callback runs regardless of the
     /// specific state entered.
-    pub fn on_transition<F>(&mut self, callback: F)
+    pub fn on_transition<F>(&mut self, callback: F) -> CallbackId
     where
         F: Fn(E) + 'static + Clone,
     {
-        let callback: Callback<E> = Rc::new(move |evt, _payload| {
-            callback(evt);
-        });
-        self.callbacks.entry(Trigger::AnyState).or_default().push(callback);
+        self.definition_mut().on_transition(callback)
     }
 
     /// Register a callback to fire on any state transition with a payload of
-    /// type `P`.
+    /// type `T`.
     ///
     /// Works similarly to `on_enter_with`, but the callback runs regardless of
-    /// the specific state, and only if the payload downcasts to `P`
-    /// successfully.
-    pub fn on_transition_with<P, F>(&mut self, callback: F)
+    /// the specific state, and only if the payload is a `T`.
+    pub fn on_transition_with<T, F>(&mut self, callback: F) -> CallbackId
     where
-        P: 'static,
-        F: Fn(E, &P) + 'static + Clone,
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) + 'static + Clone,
+    {
+        self.definition_mut().on_transition_with(callback)
+    }
+
+    /// Register a fallible callback for entering `state`, with a payload of
+    /// type `T`.
+    ///
+    /// Works similarly to `on_enter_with`, but the callback may fail. When it
+    /// does, no further callbacks run and the trigger returns
+    /// [`MachineError::CallbackFailed`]. Use [`Machine::try_trigger_with`] to
+    /// also roll back the transition.
+    ///
+    /// The error must be `Send + Sync`, so that [`MachineError`] can be
+    /// converted into thread-safe error types such as
+    /// `Box<dyn Error + Send + Sync>`.
+    pub fn try_on_enter_with<T, F>(
+        &mut self,
+        state: S,
+        callback: F,
+    ) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        self.definition_mut().try_on_enter_with(state, callback)
+    }
+
+    /// Register a fallible callback for exiting `state`, with a payload of
+    /// type `T`.
+    ///
+    /// Works similarly to `on_exit_with`, but the callback may fail, see
+    /// [`Machine::try_on_enter_with`].
+    pub fn try_on_exit_with<T, F>(
+        &mut self,
+        state: S,
+        callback: F,
+    ) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        self.definition_mut().try_on_exit_with(state, callback)
+    }
+
+    /// Register a fallible callback to fire on any state transition with a
+    /// payload of type `T`.
+    ///
+    /// Works similarly to `on_transition_with`, but the callback may fail, see
+    /// [`Machine::try_on_enter_with`].
+    pub fn try_on_transition_with<T, F>(&mut self, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T) -> Result<(), Box<dyn Error + Send + Sync>> + 'static,
+    {
+        self.definition_mut().try_on_transition_with(callback)
+    }
+
+    /// Register a callback for entering `state` that can update the machine's
+    /// context.
+    ///
+    /// Works similarly to `on_enter_with`, but the callback also receives the
+    /// context as `&mut C`.
+    pub fn on_enter_ctx<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        self.definition_mut().on_enter_ctx(state, callback)
+    }
+
+    /// Register a callback for exiting `state` that can update the machine's
+    /// context.
+    ///
+    /// Works similarly to `on_exit_with`, but the callback also receives the
+    /// context as `&mut C`.
+    pub fn on_exit_ctx<T, F>(&mut self, state: S, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
+    {
+        self.definition_mut().on_exit_ctx(state, callback)
+    }
+
+    /// Register a callback to fire on any state transition that can update the
+    /// machine's context.
+    ///
+    /// Works similarly to `on_transition_with`, but the callback also receives
+    /// the context as `&mut C`.
+    pub fn on_transition_ctx<T, F>(&mut self, callback: F) -> CallbackId
+    where
+        T: 'static,
+        P: Payload<T>,
+        F: Fn(E, &T, &mut C) + 'static,
     {
-        let callback = Self::wrap_callback(callback);
-        self.callbacks.entry(Trigger::AnyState).or_default().push(callback);
+        self.definition_mut().on_transition_ctx(callback)
+    }
+
+    /// Whether triggering `event` now would take a transition.
+    ///
+    /// The transition is looked up and its guard evaluated exactly as
+    /// [`Machine::trigger_with`] would, but the machine is left untouched and
+    /// no callbacks run. Callbacks can still fail once the event is actually
+    /// triggered.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::new("locked");
+    /// nano.when("insert coin", "locked", "unlocked");
+    ///
+    /// assert!(nano.can_trigger(&"insert coin"));
+    /// assert!(!nano.can_trigger(&"turn knob"));
+    /// ```
+    #[inline]
+    pub fn can_trigger(&self, event: &E) -> bool {
+        self.lookup(event).is_ok()
+    }
+
+    /// The state triggering `event` now would lead to, without triggering it.
+    ///
+    /// Like [`Machine::can_trigger`], this doesn't change the machine or run
+    /// any callbacks. For an [internal](TransitionKind::Internal) transition,
+    /// this is the current state.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error [`Machine::trigger_with`] would return before running
+    /// any callback: [`MachineError::EventInvalid`],
+    /// [`MachineError::StateInvalid`] or [`MachineError::GuardRejected`].
+    pub fn peek(&self, event: &E) -> MachineResult<&S> {
+        self.lookup(event).map(|transition| &transition.target)
     }
 
     /// Trigger the given `event` on the machine without any payload.
@@ -241,86 +909,375 @@ where
     /// transition to the corresponding new state and invoke any registered
     /// callbacks.
     ///
+    /// Callbacks receive `&()` as their payload. This is only available on
+    /// machines that accept a `()` payload, so a [typed](Machine::typed)
+    /// machine must be triggered with [`Machine::trigger_with`].
+    ///
     /// # Errors
     ///
+    /// Returns a [`TriggerError`] describing the event and state involved,
+    /// whose [`error`](TriggerError::error) is one of the following:
+    ///
     /// - Returns [`MachineError::EventInvalid`] if the event is not defined in
     ///   this state machine.
     /// - Returns [`MachineError::StateInvalid`] if the event has no transition
     ///   defined for the machine's current state.
+    /// - Returns [`MachineError::CallbackFailed`] if a fallible callback
+    ///   failed, see [`Machine::trigger_with`] for the state the machine is
+    ///   left in.
     #[inline]
-    pub fn trigger(&mut self, event: &E) -> Result<(), MachineError> {
+    pub fn trigger(&mut self, event: &E) -> Result<(), TriggerError<S, E>>
+    where
+        P: Payload<()>,
+    {
         self.trigger_with(event, &())
     }
 
     /// Trigger the given `event` on the machine with an associated payload.
     ///
     /// The payload will be provided to callbacks that accept the payload type
-    /// `P`. If the event is defined for the current state, the machine will
+    /// `T`. If the event is defined for the current state, the machine will
     ///  perform the transition and invoke any matching callbacks.
     ///
+    /// For an [external](TransitionKind::External) transition, exit callbacks
+    /// of the current state fire first, then the transition's action runs, if
+    /// it has one, then the state changes, then enter callbacks of the new
+    /// state and transition callbacks fire. For an
+    /// [internal](TransitionKind::Internal) transition, only the transition's
+    /// action and transition callbacks fire.
+    ///
     /// # Errors
     ///
     /// - Returns [`MachineError::EventInvalid`] if the event is not defined in
     ///   this state machine.
     /// - Returns [`MachineError::StateInvalid`] if no transition is defined for
     ///   the machine's current state with the given event.
-    pub fn trigger_with<P>(
+    /// - Returns [`MachineError::CallbackFailed`] if a fallible callback
+    ///   failed. Callbacks after it are skipped. If an exit callback or the
+    ///   transition's action failed, the state hasn't changed yet and the
+    ///   machine stays in the current state. If an enter or transition callback
+    ///   failed, the machine stays in the new state.
+    pub fn trigger_with<T>(
         &mut self,
         event: &E,
-        payload: &P,
-    ) -> Result<(), MachineError>
+        payload: &T,
+    ) -> Result<(), TriggerError<S, E>>
     where
-        P: 'static,
+        T: 'static,
+        P: Payload<T>,
     {
-        let Some(state_map) = self.transitions.get(event) else {
-            return Err(MachineError::EventInvalid);
-        };
+        self.fire(event, P::wrap(payload))
+    }
 
-        let Some(new_state) = state_map.get(&self.state) else {
-            return Err(MachineError::StateInvalid);
-        };
+    /// Trigger the given `event` without any payload, rolling back on failure.
+    ///
+    /// See [`Machine::try_trigger_with`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger`].
+    #[inline]
+    pub fn try_trigger(&mut self, event: &E) -> Result<(), TriggerError<S, E>>
+    where
+        C: Clone,
+        P: Payload<()>,
+    {
+        self.try_trigger_with(event, &())
+    }
+
+    /// Trigger the given `event` with an associated payload, rolling back on
+    /// failure.
+    ///
+    /// Works like [`Machine::trigger_with`], except that if a fallible callback
+    /// fails, the machine's state and context are restored to what they were
+    /// before the event, and the transition isn't counted in coverage. Side
+    /// effects of callbacks that already ran outside of the machine are not
+    /// undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger_with`]. When
+    /// [`MachineError::CallbackFailed`] is returned, the machine's state and
+    /// context are unchanged.
+    pub fn try_trigger_with<T>(
+        &mut self,
+        event: &E,
+        payload: &T,
+    ) -> Result<(), TriggerError<S, E>>
+    where
+        T: 'static,
+        C: Clone,
+        P: Payload<T>,
+    {
+        let previous = (self.state.clone(), self.context.clone());
+        self.fire_or_restore(event, P::wrap(payload), Some(|| previous))
+    }
 
-        self.state = new_state.clone();
-        let state_cbs = self.callbacks.get(&Trigger::State(self.state.clone()));
-        let any_cbs = self.callbacks.get(&Trigger::AnyState);
-        for cb in state_cbs.into_iter().chain(any_cbs.into_iter()).flatten() {
-            cb(event.clone(), payload as &dyn Any);
+    /// Trigger a sequence of `events` as one unit, without any payload.
+    ///
+    /// Every event is first checked against the transition table, starting
+    /// from the current state, with the same lookups and guard evaluation as
+    /// [`Machine::trigger`]. Guards see the context as it is now, since no
+    /// callbacks run while checking. Only if every event has a transition are
+    /// the events triggered, in order, running their callbacks. If one of them
+    /// still fails, the machine's state and context are restored to what they
+    /// were before the sequence, as with [`Machine::try_trigger`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::{Machine, MachineError};
+    ///
+    /// let mut nano = Machine::new("created");
+    /// nano.when("pay", "created", "paid");
+    /// nano.when("ship", "paid", "shipped");
+    ///
+    /// let err = nano.trigger_all(&["pay", "pay"]).unwrap_err();
+    /// assert_eq!(err.index(), 1);
+    /// assert_eq!(err, MachineError::StateInvalid);
+    /// assert_eq!(nano.state(), &"created");
+    ///
+    /// nano.trigger_all(&["pay", "ship"]).unwrap();
+    /// assert_eq!(nano.state(), &"shipped");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SequenceError`] holding the position of the first event
+    /// that failed and the [`TriggerError`] [`Machine::trigger`] would have
+    /// returned for it. The machine's state and context are unchanged.
+    ///
+    /// A rejection found while checking is reported to observers, metrics and
+    /// `tracing` or `log` like one returned by [`Machine::trigger`], without
+    /// running any callback. If an event fails once triggered, because a
+    /// fallible callback failed or a callback changed the context so that a
+    /// later guard rejects its event, the callbacks of the events before it and
+    /// their reports to observers and `tracing` or `log` are not undone. Only
+    /// the failure is counted in coverage and metrics, as the events before it
+    /// are only counted once the whole sequence succeeds.
+    pub fn trigger_all<'a, I>(
+        &mut self,
+        events: I,
+    ) -> Result<(), SequenceError<S, E>>
+    where
+        I: IntoIterator<Item = &'a E>,
+        E: 'a,
+        C: Clone,
+        P: Payload<()>,
+    {
+        let events: Vec<&E> = events.into_iter().collect();
+        let mut state = self.state.clone();
+        for (index, event) in events.iter().enumerate() {
+            match self.definition.resolve(&state, &self.context, event) {
+                Ok(transition) => state = transition.target.clone(),
+                Err(err) => {
+                    self.reject(event, &err);
+                    return Err(SequenceError::new(index, err));
+                }
+            }
         }
 
+        // Reports wait for the whole sequence, which may still be rolled back.
+        let previous = (self.state.clone(), self.context.clone());
+        let mut taken = Vec::with_capacity(events.len());
+        for (index, event) in events.iter().enumerate() {
+            let restore = || previous.clone();
+            let mut step = None;
+            let result = self.fire_unreported(
+                &mut step,
+                event,
+                P::wrap(&()),
+                Some(restore),
+            );
+            if result.is_err() {
+                (self.state, self.context) = previous;
+                let failed = step.map(|step| Taken { exited: false, ..step });
+                self.report(event, failed, &result);
+                return result.map_err(|err| SequenceError::new(index, err));
+            }
+            taken.push(step);
+        }
+        for (event, step) in events.into_iter().zip(taken) {
+            self.report(event, step, &Ok(()));
+        }
         Ok(())
     }
-}
 
-impl<S, E> Default for Machine<S, E>
-where
-    S: Default,
-{
-    /// Create a default machine, using `S::default()` as the initial state.
-    fn default() -> Self {
-        Machine::new(S::default())
+    /// The transition `event` would take from the current state, or the kind
+    /// of failure if the event would be rejected, without allocating.
+    fn lookup(&self, event: &E) -> MachineResult<&Transition<S, E, C, P>> {
+        self.definition.lookup(&self.state, &self.context, event)
     }
-}
 
-impl<S: Debug + Eq + Hash + Clone, E: Debug + Eq + Hash + Clone> Debug
-    for Machine<S, E>
-{
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("Machine")
-            .field("state", &self.state)
-            .field("events", &self.transitions.keys().collect::<Vec<_>>())
-            .field("callbacks", &self.callbacks.len())
-            .finish()
+    /// The transition `event` would take from the current state, or why the
+    /// event would be rejected.
+    fn resolve(
+        &self,
+        event: &E,
+    ) -> Result<&Transition<S, E, C, P>, TriggerError<S, E>> {
+        self.definition.resolve(&self.state, &self.context, event)
     }
-}
 
+    /// Perform the transition for `event` and run the matching callbacks,
+    /// reporting it to the metrics sink if there is one.
+    #[inline]
+    fn fire(
+        &mut self,
+        event: &E,
+        payload: &P,
+    ) -> Result<(), TriggerError<S, E>> {
+        self.fire_or_restore(event, payload, None::<fn() -> (S, C)>)
+    }
+
+    /// [`Machine::fire`], restoring the state and context to the ones
+    /// `previous` returns, if given, when a fallible callback fails.
+    ///
+    /// Coverage and metrics are reported once the machine is restored, so
+    /// that a rolled back transition isn't counted.
+    fn fire_or_restore(
+        &mut self,
+        event: &E,
+        payload: &P,
+        previous: Option<impl FnOnce() -> (S, C)>,
+    ) -> Result<(), TriggerError<S, E>> {
+        let mut taken = None;
+        let result = self.fire_unreported(&mut taken, event, payload, previous);
+        self.report(event, taken, &result);
+        result
+    }
+
+    /// [`Machine::fire_or_restore`], without reporting to coverage and
+    /// metrics. `taken` is set to what to [report](Machine::report) once the
+    /// transition can no longer be rolled back, if anything records it.
+    fn fire_unreported(
+        &mut self,
+        taken: &mut Option<Taken<S>>,
+        event: &E,
+        payload: &P,
+        previous: Option<impl FnOnce() -> (S, C)>,
+    ) -> Result<(), TriggerError<S, E>> {
+        let recording = self.coverage.is_some() || self.metrics.is_some();
+        let from = recording.then(|| self.state.clone());
+        let mut exited = false;
+        let result = self.definition.fire(
+            &mut self.state,
+            &mut self.context,
+            &mut exited,
+            event,
+            payload,
+        );
+        if let Err(err) = &result
+            && let MachineError::CallbackFailed(_) = err.error()
+            && let Some(previous) = previous
+        {
+            (self.state, self.context) = previous();
+            exited = false;
+        }
+        *taken =
+            from.map(|from| Taken { from, to: self.state.clone(), exited });
+        result
+    }
+
+    /// Report to coverage and metrics that triggering `event` went as
+    /// `taken` says, with `result`.
+    ///
+    /// A failed transition is only counted as covered if it entered its
+    /// target, which a callback failing afterwards doesn't undo.
+    fn report(
+        &mut self,
+        event: &E,
+        taken: Option<Taken<S>>,
+        result: &Result<(), TriggerError<S, E>>,
+    ) {
+        let Some(Taken { from, to, exited }) = taken else {
+            return;
+        };
+        if let Some(coverage) = &mut self.coverage
+            && (result.is_ok() || exited)
+        {
+            coverage.record(event, &from, &to, exited);
+        }
+        if let Some(metrics) = &mut self.metrics {
+            metrics.record(event, &from, &to, exited, result);
+        }
+    }
+
+    /// Report that triggering `event` failed with `err` without running
+    /// anything, as [`Machine::fire`] would have.
+    fn reject(&self, event: &E, err: &TriggerError<S, E>) {
+        self.definition.reject(event, err);
+        if let Some(metrics) = &self.metrics {
+            metrics.reject(event, err);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C> Default for Machine<S, E, C>
+where
+    S: Default,
+    C: Default,
+{
+    /// Create a default machine, using `S::default()` as the initial state and
+    /// `C::default()` as the context.
+    fn default() -> Self {
+        Machine::with_context(S::default(), C::default())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C, P> Clone for Machine<S, E, C, P>
+where
+    S: Clone,
+    E: Clone,
+    C: Clone,
+    P: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Machine {
+            definition: self.definition.clone(),
+            state: self.state.clone(),
+            context: self.context.clone(),
+            coverage: self.coverage.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<S, E, C, P> Debug for Machine<S, E, C, P>
+where
+    S: Debug + Eq + Hash + Clone,
+    E: Debug + Eq + Hash + Clone,
+    C: Debug,
+    P: ?Sized,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Machine")
+            .field("state", &self.state)
+            .field("context", &self.context)
+            .field("definition", &self.definition)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "alloc")]
 #[cfg(test)]
+// Some of the original tests hit these pedantic lints, and are kept as they
+// were.
+#[allow(
+    clippy::ignored_unit_patterns,
+    clippy::semicolon_if_nothing_returned,
+    clippy::similar_names
+)]
 mod tests {
     use alloc::{
+        format,
         rc::Rc,
         string::{String, ToString},
         vec::Vec,
     };
-    use core::cell::Cell;
+    use core::cell::{Cell, RefCell};
 
     use super::*;
 
@@ -605,4 +1562,816 @@ mod tests {
         // The callback should not fire for the wrong payload type.
         assert!(!called.get());
     }
+
+    #[test]
+    fn exit_callback() {
+        let mut m = create_machine();
+        let exited = Rc::new(Cell::new(None));
+
+        let e = exited.clone();
+        m.on_exit(TestState::Idle, move |evt| e.set(Some(evt)));
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(exited.take(), Some(TestEvent::Start));
+
+        m.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(exited.take(), None);
+    }
+
+    #[test]
+    fn exit_callbacks_fire_before_enter_callbacks() {
+        let mut m = create_machine();
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let o = order.clone();
+        m.on_enter(TestState::Running, move |_| o.borrow_mut().push("enter"));
+        let o = order.clone();
+        m.on_exit_with(TestState::Idle, move |_, _: &u32| {
+            o.borrow_mut().push("exit");
+        });
+        let o = order.clone();
+        m.on_transition(move |_| o.borrow_mut().push("transition"));
+
+        m.trigger_with(&TestEvent::Start, &1u32).unwrap();
+        assert_eq!(*order.borrow(), ["exit", "enter", "transition"]);
+    }
+
+    #[test]
+    fn external_self_transition() {
+        let mut m = create_machine();
+        m.when_self(TestEvent::Resume, TestState::Idle);
+
+        let entered = Rc::new(Cell::new(0));
+        let exited = Rc::new(Cell::new(0));
+        let transitioned = Rc::new(Cell::new(0));
+
+        let e = entered.clone();
+        m.on_enter(TestState::Idle, move |_| e.set(e.get() + 1));
+        let e = exited.clone();
+        m.on_exit(TestState::Idle, move |_| e.set(e.get() + 1));
+        let t = transitioned.clone();
+        m.on_transition(move |_| t.set(t.get() + 1));
+
+        m.trigger(&TestEvent::Resume).unwrap();
+        assert_eq!(*m.state(), TestState::Idle);
+        assert_eq!(entered.get(), 1);
+        assert_eq!(exited.get(), 1);
+        assert_eq!(transitioned.get(), 1);
+        assert_eq!(
+            m.transition_kind(&TestEvent::Resume, &TestState::Idle),
+            Some(TransitionKind::External)
+        );
+    }
+
+    #[test]
+    fn internal_transition() {
+        let mut m = create_machine();
+        m.when_internal(TestEvent::Resume, TestState::Idle);
+
+        let entered = Rc::new(Cell::new(0));
+        let exited = Rc::new(Cell::new(0));
+        let transitioned = Rc::new(Cell::new(0));
+
+        let e = entered.clone();
+        m.on_enter(TestState::Idle, move |_| e.set(e.get() + 1));
+        let e = exited.clone();
+        m.on_exit(TestState::Idle, move |_| e.set(e.get() + 1));
+        let t = transitioned.clone();
+        m.on_transition_with(move |_, _: &u32| t.set(t.get() + 1));
+
+        m.trigger_with(&TestEvent::Resume, &7u32).unwrap();
+        assert_eq!(*m.state(), TestState::Idle);
+        assert_eq!(entered.get(), 0);
+        assert_eq!(exited.get(), 0);
+        assert_eq!(transitioned.get(), 1);
+        assert_eq!(
+            m.transition_kind(&TestEvent::Resume, &TestState::Idle),
+            Some(TransitionKind::Internal)
+        );
+    }
+
+    #[test]
+    fn internal_transition_is_overwritten_by_when() {
+        let mut m = create_machine();
+        m.when_internal(TestEvent::Resume, TestState::Idle);
+        m.when(TestEvent::Resume, TestState::Idle, TestState::Idle);
+
+        let entered = Rc::new(Cell::new(false));
+        let e = entered.clone();
+        m.on_enter(TestState::Idle, move |_| e.set(true));
+
+        m.trigger(&TestEvent::Resume).unwrap();
+        assert!(entered.get());
+    }
+
+    #[test]
+    fn callback_ids_are_unique() {
+        let mut m = create_machine();
+        let a = m.on_enter(TestState::Running, |_| {});
+        let b = m.on_exit(TestState::Running, |_| {});
+        let c = m.on_transition_with(|_, (): &()| {});
+        assert_ne!(a, b);
+        assert_ne!(b, c);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn remove_callback() {
+        let mut m = create_machine();
+        let counter = Rc::new(Cell::new(0));
+
+        let c = counter.clone();
+        let id = m.on_enter(TestState::Running, move |_| c.set(c.get() + 1));
+        let c = counter.clone();
+        m.on_enter(TestState::Running, move |_| c.set(c.get() + 10));
+
+        assert!(m.remove_callback(id));
+        assert!(!m.remove_callback(id));
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(counter.get(), 10);
+    }
+
+    #[test]
+    fn remove_transition_callback() {
+        let mut m = create_machine();
+        let called = Rc::new(Cell::new(false));
+
+        let c = called.clone();
+        let id = m.on_transition(move |_| c.set(true));
+        assert!(m.remove_callback(id));
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert!(!called.get());
+    }
+
+    #[test]
+    fn clear_callbacks() {
+        let mut m = create_machine();
+        let counter = Rc::new(Cell::new(0));
+
+        let c = counter.clone();
+        m.on_enter(TestState::Running, move |_| c.set(c.get() + 1));
+        let c = counter.clone();
+        m.on_exit_with(TestState::Running, move |_, (): &()| {
+            c.set(c.get() + 1);
+        });
+        let c = counter.clone();
+        m.on_transition(move |_| c.set(c.get() + 100));
+
+        m.clear_callbacks(&TestState::Running);
+
+        m.trigger(&TestEvent::Start).unwrap();
+        m.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(counter.get(), 200);
+    }
+
+    #[test]
+    fn guarded_callback_unregisters_on_drop() {
+        let mut m = create_machine();
+        let counter = Rc::new(Cell::new(0));
+
+        let c = counter.clone();
+        let id = m.on_transition(move |_| c.set(c.get() + 1));
+        let guard = m.guard_callback(id).unwrap();
+        assert_eq!(guard.id(), id);
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(counter.get(), 1);
+
+        drop(guard);
+        m.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(counter.get(), 1);
+        assert!(!m.remove_callback(id));
+        assert!(m.guard_callback(id).is_none());
+    }
+
+    #[test]
+    fn dropping_the_guard_drops_the_callback() {
+        let mut m = create_machine();
+        let counter = Rc::new(Cell::new(0));
+        let c = counter.clone();
+        let id = m.on_enter(TestState::Paused, move |_| c.set(c.get() + 1));
+        let guard = m.guard_callback(id).unwrap();
+        assert_eq!(Rc::strong_count(&counter), 2);
+
+        drop(guard);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn guarding_forks_a_shared_definition() {
+        let mut m = create_machine();
+        let counter = Rc::new(Cell::new(0));
+        let c = counter.clone();
+        let id = m.on_transition(move |_| c.set(c.get() + 1));
+
+        let mut before = m.clone();
+        let guard = m.guard_callback(id).unwrap();
+        let mut after = m.clone();
+        assert!(!Rc::ptr_eq(before.definition(), m.definition()));
+        assert!(Rc::ptr_eq(after.definition(), m.definition()));
+
+        drop(guard);
+        m.trigger(&TestEvent::Start).unwrap();
+        after.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(counter.get(), 0);
+        before.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn guard_callback_once() {
+        let mut m = create_machine();
+        let counter = Rc::new(Cell::new(0));
+        let c = counter.clone();
+        let id = m.on_transition(move |_| c.set(c.get() + 1));
+
+        let guard = m.guard_callback(id).unwrap();
+        assert!(m.guard_callback(id).is_none());
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(counter.get(), 1);
+
+        drop(guard);
+        m.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn guard_unknown_callback() {
+        let mut m = create_machine();
+        let id = m.on_enter(TestState::Running, |_| {});
+        m.remove_callback(id);
+        assert!(m.guard_callback(id).is_none());
+    }
+
+    #[test]
+    fn fallible_callback_succeeds() {
+        let mut m = create_machine();
+        m.try_on_enter_with(TestState::Running, |_, amount: &u32| {
+            if *amount > 0 { Ok(()) } else { Err("nothing to charge".into()) }
+        });
+
+        m.try_trigger_with(&TestEvent::Start, &10u32).unwrap();
+        assert_eq!(*m.state(), TestState::Running);
+    }
+
+    #[test]
+    fn fallible_callback_fails_without_rollback() {
+        let mut m = create_machine();
+        let after = Rc::new(Cell::new(false));
+
+        m.try_on_enter_with(TestState::Running, |_, (): &()| {
+            Err("card declined".into())
+        });
+        let a = after.clone();
+        m.on_transition(move |_| a.set(true));
+
+        let err = m.trigger(&TestEvent::Start).unwrap_err();
+        assert!(matches!(err.error(), MachineError::CallbackFailed(_)));
+        assert_eq!(err.state(), &TestState::Idle);
+        assert_eq!(
+            err.to_string(),
+            "A callback failed: card declined (event: Start, state: Idle)"
+        );
+        assert_eq!(*m.state(), TestState::Running);
+        // Callbacks after the failing one are skipped.
+        assert!(!after.get());
+    }
+
+    #[test]
+    fn failing_exit_callback_keeps_the_current_state() {
+        let mut m = create_machine();
+        let entered = Rc::new(Cell::new(false));
+        m.try_on_exit_with(TestState::Idle, |_, (): &()| {
+            Err("still busy".into())
+        });
+        let e = entered.clone();
+        m.on_enter(TestState::Running, move |_| e.set(true));
+
+        let err = m.trigger(&TestEvent::Start).unwrap_err();
+        assert!(matches!(err.error(), MachineError::CallbackFailed(_)));
+        assert_eq!(*m.state(), TestState::Idle);
+        assert!(!entered.get());
+    }
+
+    #[test]
+    fn fallible_callback_rolls_back() {
+        let mut m = create_machine();
+        m.try_on_transition_with(|_, (): &()| Err("card declined".into()));
+
+        let err = m.try_trigger(&TestEvent::Start).unwrap_err();
+        let MachineError::CallbackFailed(source) = err.into_error() else {
+            panic!("expected a callback error");
+        };
+        assert_eq!(source.to_string(), "card declined");
+        assert_eq!(*m.state(), TestState::Idle);
+    }
+
+    #[test]
+    fn failing_exit_callback_rolls_back_before_enter() {
+        let mut m = create_machine();
+        let entered = Rc::new(Cell::new(false));
+
+        m.try_on_exit_with(TestState::Idle, |_, (): &()| Err("busy".into()));
+        let e = entered.clone();
+        m.on_enter(TestState::Running, move |_| e.set(true));
+
+        assert!(m.try_trigger(&TestEvent::Start).is_err());
+        assert_eq!(*m.state(), TestState::Idle);
+        assert!(!entered.get());
+    }
+
+    #[test]
+    fn fallible_callback_ignores_other_payloads() {
+        let mut m = create_machine();
+        m.try_on_enter_with(TestState::Running, |_, _: &String| {
+            Err("unreachable".into())
+        });
+
+        m.try_trigger_with(&TestEvent::Start, &1u32).unwrap();
+        assert_eq!(*m.state(), TestState::Running);
+    }
+
+    #[test]
+    fn invalid_transition_is_not_rolled_back() {
+        let mut m = create_machine();
+        assert_eq!(
+            m.try_trigger(&TestEvent::Pause).unwrap_err(),
+            MachineError::StateInvalid
+        );
+        assert_eq!(*m.state(), TestState::Idle);
+    }
+
+    #[test]
+    fn trigger_error_for_invalid_event() {
+        let mut m = Machine::new(TestState::Idle);
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+
+        let err = m.trigger(&TestEvent::Stop).unwrap_err();
+        assert_eq!(err, MachineError::EventInvalid);
+        assert_eq!(err.event(), &TestEvent::Stop);
+        assert_eq!(err.state(), &TestState::Idle);
+        assert!(err.valid_from().is_empty());
+    }
+
+    #[test]
+    fn trigger_error_for_invalid_state() {
+        let mut m = create_machine();
+        m.trigger(&TestEvent::Start).unwrap();
+
+        let err = m.trigger(&TestEvent::Resume).unwrap_err();
+        assert_eq!(err.error(), &MachineError::StateInvalid);
+        assert_eq!(err.event(), &TestEvent::Resume);
+        assert_eq!(err.state(), &TestState::Running);
+        assert_eq!(err.valid_from(), &[TestState::Paused]);
+        assert_eq!(
+            err.to_string(),
+            "The event is not valid for the current state (event: Resume, \
+             state: Running, valid from: [Paused])"
+        );
+    }
+
+    #[test]
+    fn trigger_error_lists_every_valid_state() {
+        let mut m = create_machine();
+        m.trigger(&TestEvent::Start).unwrap();
+
+        let err = m.trigger(&TestEvent::Start).unwrap_err();
+        let mut valid_from = err.valid_from().to_vec();
+        valid_from.sort_by_key(|s| format!("{s:?}"));
+        assert_eq!(valid_from, [TestState::Idle, TestState::Stopped]);
+    }
+
+    #[test]
+    fn trigger_error_converts_to_machine_error() {
+        let mut m = create_machine();
+        let err: MachineError =
+            m.trigger(&TestEvent::Pause).unwrap_err().into();
+        assert_eq!(err, MachineError::StateInvalid);
+    }
+
+    #[test]
+    fn errors_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<MachineError>();
+        assert_send_sync::<CallbackError>();
+        assert_send_sync::<TriggerError<TestState, TestEvent>>();
+        assert_send_sync::<SequenceError<TestState, TestEvent>>();
+    }
+
+    #[test]
+    fn typed_payload() {
+        let mut m = Machine::<_, _, (), String>::typed(TestState::Idle);
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+        m.when(TestEvent::Pause, TestState::Running, TestState::Paused);
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let r = received.clone();
+        m.on_enter_with(TestState::Running, move |_, p: &String| {
+            r.borrow_mut().push(p.clone());
+        });
+        let r = received.clone();
+        m.on_transition_with(move |_, p: &String| {
+            r.borrow_mut().push(p.to_uppercase());
+        });
+
+        m.trigger_with(&TestEvent::Start, &"go".to_string()).unwrap();
+        assert_eq!(*received.borrow(), ["go", "GO"]);
+    }
+
+    #[test]
+    fn typed_payload_callbacks_without_payload() {
+        let mut m = Machine::<_, _, (), u32>::typed(TestState::Idle);
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+
+        let fired = Rc::new(Cell::new(None));
+        let f = fired.clone();
+        m.on_enter(TestState::Running, move |evt| f.set(Some(evt)));
+
+        m.trigger_with(&TestEvent::Start, &1).unwrap();
+        assert_eq!(fired.take(), Some(TestEvent::Start));
+    }
+
+    #[test]
+    fn typed_unit_payload() {
+        let mut m = Machine::<_, _, (), ()>::typed(TestState::Idle);
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+
+        let fired = Rc::new(Cell::new(false));
+        let f = fired.clone();
+        m.try_on_enter_with(TestState::Running, move |_, ()| {
+            f.set(true);
+            Ok(())
+        });
+
+        m.try_trigger(&TestEvent::Start).unwrap();
+        assert!(fired.get());
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Order {
+        retries: u32,
+        paid: u32,
+    }
+
+    fn create_machine_with_context() -> Machine<TestState, TestEvent, Order> {
+        let mut m = Machine::with_context(TestState::Idle, Order::default());
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+        m.when(TestEvent::Stop, TestState::Running, TestState::Stopped);
+        m.when(TestEvent::Start, TestState::Stopped, TestState::Running);
+        m
+    }
+
+    #[test]
+    fn context_accessors() {
+        let mut m = create_machine_with_context();
+        assert_eq!(*m.context(), Order::default());
+
+        m.context_mut().retries = 3;
+        assert_eq!(m.context().retries, 3);
+    }
+
+    #[test]
+    fn context_callbacks() {
+        let mut m = create_machine_with_context();
+        m.on_enter_ctx(
+            TestState::Running,
+            |_, amount: &u32, order: &mut Order| {
+                order.paid += amount;
+            },
+        );
+        m.on_exit_ctx(TestState::Running, |_, (): &(), order: &mut Order| {
+            order.retries += 1;
+        });
+        m.on_transition_ctx(|_, _: &u32, order: &mut Order| {
+            order.retries += 10;
+        });
+
+        m.trigger_with(&TestEvent::Start, &25u32).unwrap();
+        assert_eq!(*m.context(), Order { retries: 10, paid: 25 });
+
+        m.trigger(&TestEvent::Stop).unwrap();
+        assert_eq!(*m.context(), Order { retries: 11, paid: 25 });
+    }
+
+    #[test]
+    fn guarded_transition() {
+        let mut m = create_machine_with_context();
+        m.when_guarded(
+            TestEvent::Pause,
+            TestState::Running,
+            TestState::Paused,
+            |order: &Order| order.paid > 0,
+        );
+
+        let entered = Rc::new(Cell::new(false));
+        let e = entered.clone();
+        m.on_enter(TestState::Paused, move |_| e.set(true));
+
+        m.trigger(&TestEvent::Start).unwrap();
+        let err = m.trigger(&TestEvent::Pause).unwrap_err();
+        assert_eq!(err, MachineError::GuardRejected);
+        assert_eq!(*m.state(), TestState::Running);
+        assert!(!entered.get());
+
+        m.context_mut().paid = 10;
+        m.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(*m.state(), TestState::Paused);
+        assert!(entered.get());
+    }
+
+    #[test]
+    fn rollback_restores_context() {
+        let mut m = create_machine_with_context();
+        m.on_enter_ctx(TestState::Running, |_, (): &(), order: &mut Order| {
+            order.retries += 1;
+        });
+        m.try_on_transition_with(|_, (): &()| Err("declined".into()));
+
+        assert!(m.try_trigger(&TestEvent::Start).is_err());
+        assert_eq!(*m.state(), TestState::Idle);
+        assert_eq!(m.context().retries, 0);
+
+        // Without rollback, both the state and the context keep the changes.
+        assert!(m.trigger(&TestEvent::Start).is_err());
+        assert_eq!(*m.state(), TestState::Running);
+        assert_eq!(m.context().retries, 1);
+    }
+
+    #[test]
+    fn clone_snapshots_context() {
+        let mut m = create_machine_with_context();
+        m.on_enter_ctx(TestState::Running, |_, (): &(), order: &mut Order| {
+            order.retries += 1;
+        });
+
+        let snapshot = m.clone();
+        m.trigger(&TestEvent::Start).unwrap();
+
+        assert_eq!(*snapshot.state(), TestState::Idle);
+        assert_eq!(snapshot.context().retries, 0);
+        assert_eq!(m.context().retries, 1);
+    }
+
+    #[test]
+    fn typed_with_context() {
+        let mut m =
+            Machine::<_, _, u32, u32>::typed_with_context(TestState::Idle, 0);
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+        m.on_enter_ctx(TestState::Running, |_, amount, total| *total += amount);
+
+        m.trigger_with(&TestEvent::Start, &5).unwrap();
+        assert_eq!(*m.context(), 5);
+    }
+
+    #[test]
+    fn edge_action_runs_only_on_its_edge() {
+        let mut m = create_machine_with_context();
+        m.when_then(
+            TestEvent::Stop,
+            TestState::Running,
+            TestState::Stopped,
+            |_, amount: &u32, order: &mut Order| order.paid -= amount,
+        );
+        m.context_mut().paid = 100;
+
+        m.trigger_with(&TestEvent::Start, &30u32).unwrap();
+        assert_eq!(m.context().paid, 100);
+
+        m.trigger_with(&TestEvent::Stop, &30u32).unwrap();
+        assert_eq!(*m.state(), TestState::Stopped);
+        assert_eq!(m.context().paid, 70);
+    }
+
+    #[test]
+    fn edge_action_runs_between_exit_and_enter() {
+        let mut m = create_machine();
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let o = order.clone();
+        m.when_then(
+            TestEvent::Start,
+            TestState::Idle,
+            TestState::Running,
+            move |_, (): &(), ()| o.borrow_mut().push("action"),
+        );
+        let o = order.clone();
+        m.on_exit(TestState::Idle, move |_| o.borrow_mut().push("exit"));
+        let o = order.clone();
+        m.on_enter(TestState::Running, move |_| o.borrow_mut().push("enter"));
+        let o = order.clone();
+        m.on_transition(move |_| o.borrow_mut().push("transition"));
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(*order.borrow(), ["exit", "action", "enter", "transition"]);
+    }
+
+    #[test]
+    fn edge_action_ignores_other_payloads() {
+        let mut m = create_machine_with_context();
+        m.when_then(
+            TestEvent::Start,
+            TestState::Idle,
+            TestState::Running,
+            |_, _: &String, order: &mut Order| order.retries += 1,
+        );
+
+        m.trigger_with(&TestEvent::Start, &1u32).unwrap();
+        assert_eq!(*m.state(), TestState::Running);
+        assert_eq!(m.context().retries, 0);
+    }
+
+    #[test]
+    fn when_overwrites_edge_action() {
+        let mut m = create_machine_with_context();
+        m.when_then(
+            TestEvent::Start,
+            TestState::Idle,
+            TestState::Running,
+            |_, (): &(), order: &mut Order| order.retries += 1,
+        );
+        m.when(TestEvent::Start, TestState::Idle, TestState::Running);
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(m.context().retries, 0);
+    }
+
+    #[test]
+    fn internal_edge_action() {
+        let mut m = create_machine();
+        let order = Rc::new(RefCell::new(Vec::new()));
+
+        let o = order.clone();
+        m.transition(TestEvent::Pause, TestState::Idle, TestState::Idle)
+            .internal()
+            .then(move |_, (): &(), ()| o.borrow_mut().push("action"));
+        let o = order.clone();
+        m.on_exit(TestState::Idle, move |_| o.borrow_mut().push("exit"));
+        let o = order.clone();
+        m.on_transition(move |_| o.borrow_mut().push("transition"));
+
+        m.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(*order.borrow(), ["action", "transition"]);
+        assert_eq!(
+            m.transition_kind(&TestEvent::Pause, &TestState::Idle),
+            Some(TransitionKind::Internal)
+        );
+
+        let mut compiled = m.compile();
+        order.borrow_mut().clear();
+        compiled.trigger(&TestEvent::Pause).unwrap();
+        assert_eq!(*order.borrow(), ["action", "transition"]);
+    }
+
+    #[test]
+    fn guarded_edge_action() {
+        let mut m = create_machine_with_context();
+        m.transition(TestEvent::Stop, TestState::Running, TestState::Stopped)
+            .guard(|order: &Order| order.paid >= 30)
+            .then(|_, amount: &u32, order: &mut Order| order.paid -= amount);
+        m.trigger(&TestEvent::Start).unwrap();
+
+        let err = m.trigger_with(&TestEvent::Stop, &30u32).unwrap_err();
+        assert_eq!(err, MachineError::GuardRejected);
+
+        m.context_mut().paid = 100;
+        m.trigger_with(&TestEvent::Stop, &30u32).unwrap();
+        assert_eq!(*m.state(), TestState::Stopped);
+        assert_eq!(m.context().paid, 70);
+    }
+
+    #[test]
+    fn internal_targets_its_own_state() {
+        let mut m = create_machine();
+        m.transition(TestEvent::Start, TestState::Idle, TestState::Running)
+            .internal();
+
+        m.trigger(&TestEvent::Start).unwrap();
+        assert_eq!(*m.state(), TestState::Idle);
+    }
+
+    #[test]
+    fn peek_and_can_trigger() {
+        let mut m = create_machine();
+        let entered = Rc::new(Cell::new(false));
+        let e = entered.clone();
+        m.on_enter(TestState::Running, move |_| e.set(true));
+
+        assert!(m.can_trigger(&TestEvent::Start));
+        assert_eq!(m.peek(&TestEvent::Start), Ok(&TestState::Running));
+        assert!(!m.can_trigger(&TestEvent::Pause));
+        assert_eq!(m.peek(&TestEvent::Pause), Err(MachineError::StateInvalid));
+
+        let mut empty = Machine::<TestState, TestEvent>::new(TestState::Idle);
+        empty.when_internal(TestEvent::Pause, TestState::Idle);
+        assert_eq!(empty.peek(&TestEvent::Pause), Ok(&TestState::Idle));
+        assert_eq!(
+            empty.peek(&TestEvent::Start),
+            Err(MachineError::EventInvalid)
+        );
+
+        assert_eq!(*m.state(), TestState::Idle);
+        assert!(!entered.get());
+    }
+
+    #[test]
+    fn peek_evaluates_guards() {
+        let mut m = create_machine_with_context();
+        m.when_guarded(
+            TestEvent::Start,
+            TestState::Idle,
+            TestState::Running,
+            |order: &Order| order.paid > 0,
+        );
+
+        assert!(!m.can_trigger(&TestEvent::Start));
+        assert_eq!(m.peek(&TestEvent::Start), Err(MachineError::GuardRejected));
+
+        m.context_mut().paid = 10;
+        assert_eq!(m.peek(&TestEvent::Start), Ok(&TestState::Running));
+        assert_eq!(*m.state(), TestState::Idle);
+    }
+
+    #[test]
+    fn trigger_all_applies_every_event() {
+        let mut m = create_machine();
+        let entered = Rc::new(RefCell::new(Vec::new()));
+        let e = entered.clone();
+        m.on_transition(move |evt| e.borrow_mut().push(evt));
+
+        m.trigger_all(&[TestEvent::Start, TestEvent::Pause, TestEvent::Resume])
+            .unwrap();
+        assert_eq!(*m.state(), TestState::Running);
+        assert_eq!(
+            *entered.borrow(),
+            [TestEvent::Start, TestEvent::Pause, TestEvent::Resume]
+        );
+
+        m.trigger_all(&[]).unwrap();
+        assert_eq!(*m.state(), TestState::Running);
+    }
+
+    #[test]
+    fn trigger_all_rejects_without_side_effects() {
+        let mut m = create_machine();
+        let fired = Rc::new(Cell::new(0));
+        let f = fired.clone();
+        m.on_transition(move |_| f.set(f.get() + 1));
+
+        let err = m
+            .trigger_all(&[
+                TestEvent::Start,
+                TestEvent::Pause,
+                TestEvent::Pause,
+            ])
+            .unwrap_err();
+        assert_eq!(err.index(), 2);
+        assert_eq!(err.error().state(), &TestState::Paused);
+        assert_eq!(err, MachineError::StateInvalid);
+        assert_eq!(*m.state(), TestState::Idle);
+        assert_eq!(fired.get(), 0);
+        assert_eq!(
+            err.to_string(),
+            "event 2 of the sequence failed: The event is not valid for the \
+             current state (event: Pause, state: Paused, valid from: \
+             [Running])"
+        );
+    }
+
+    #[test]
+    fn trigger_all_rolls_back_on_failure() {
+        let mut m = create_machine_with_context();
+        m.on_enter_ctx(TestState::Running, |_, (): &(), order: &mut Order| {
+            order.retries += 1;
+        });
+        m.try_on_enter_with(
+            TestState::Stopped,
+            |_, (): &()| Err("down".into()),
+        );
+
+        let err =
+            m.trigger_all(&[TestEvent::Start, TestEvent::Stop]).unwrap_err();
+        assert_eq!(err.index(), 1);
+        assert_eq!(err.error().error().name(), "CallbackFailed");
+        assert_eq!(*m.state(), TestState::Idle);
+        assert_eq!(*m.context(), Order::default());
+    }
+
+    #[test]
+    fn trigger_all_checks_guards() {
+        let mut m = create_machine_with_context();
+        m.when_guarded(
+            TestEvent::Start,
+            TestState::Idle,
+            TestState::Running,
+            |order: &Order| order.paid > 0,
+        );
+
+        let err = m.trigger_all(&[TestEvent::Start]).unwrap_err();
+        assert_eq!(err.index(), 0);
+        assert_eq!(MachineError::from(err), MachineError::GuardRejected);
+        assert_eq!(*m.state(), TestState::Idle);
+    }
 }
@@ -0,0 +1,605 @@
+use alloc::{rc::Rc, vec, vec::Vec};
+use core::{cell::RefCell, hash::Hash, time::Duration};
+
+use hashbrown::HashMap;
+
+use crate::{Machine, MachineError, TriggerError};
+
+/// A sink for the metrics of a machine, to bridge to a metrics library.
+///
+/// Installed with [`Machine::record_metrics`]. Every method has an empty
+/// default, so a sink only implements the metrics it exports. [`Stats`] is a
+/// sink keeping the aggregates in memory.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+///
+/// use nanomachine::{Machine, MachineError, Metrics};
+///
+/// #[derive(Default)]
+/// struct Counts {
+///     edges: RefCell<HashMap<(&'static str, &'static str), u64>>,
+///     rejected: RefCell<HashMap<&'static str, u64>>,
+///     dwell: RefCell<Vec<(&'static str, Duration)>>,
+/// }
+///
+/// impl Metrics<&'static str, &'static str> for Counts {
+///     fn transition(&self, event: &&'static str, from: &&'static str, _to: &&'static str) {
+///         *self.edges.borrow_mut().entry((*from, *event)).or_default() += 1;
+///     }
+///
+///     fn rejected(&self, _state: &&'static str, _event: &&'static str, error: &MachineError) {
+///         *self.rejected.borrow_mut().entry(error.name()).or_default() += 1;
+///     }
+///
+///     fn dwell(&self, state: &&'static str, time: Duration) {
+///         self.dwell.borrow_mut().push((*state, time));
+///     }
+/// }
+///
+/// let mut nano = Machine::new("created");
+/// nano.when("pay", "created", "paid");
+///
+/// let now = Rc::new(RefCell::new(Duration::ZERO));
+/// let counts = Rc::new(Counts::default());
+/// let clock = now.clone();
+/// nano.record_metrics(counts.clone(), move || *clock.borrow());
+///
+/// *now.borrow_mut() = Duration::from_secs(5);
+/// nano.trigger(&"pay").unwrap();
+/// nano.trigger(&"pay").unwrap_err();
+///
+/// assert_eq!(counts.edges.borrow()[&("created", "pay")], 1);
+/// assert_eq!(counts.rejected.borrow()["StateInvalid"], 1);
+/// assert_eq!(*counts.dwell.borrow(), [("created", Duration::from_secs(5))]);
+/// ```
+#[allow(unused_variables)]
+pub trait Metrics<S, E> {
+    /// Count one transition taken along the edge `from --event--> to`.
+    fn transition(&self, event: &E, from: &S, to: &S) {}
+
+    /// Count one `event` triggered in `state` that failed with `error`.
+    ///
+    /// [`MachineError::name`] gives a label for the kind of failure.
+    fn rejected(&self, state: &S, event: &E, error: &MachineError) {}
+
+    /// Record that the machine spent `time` in `state` before leaving it.
+    fn dwell(&self, state: &S, time: Duration) {}
+}
+
+impl<S, E, M> Metrics<S, E> for Rc<M>
+where
+    M: Metrics<S, E> + ?Sized,
+{
+    fn transition(&self, event: &E, from: &S, to: &S) {
+        (**self).transition(event, from, to);
+    }
+
+    fn rejected(&self, state: &S, event: &E, error: &MachineError) {
+        (**self).rejected(state, event, error);
+    }
+
+    fn dwell(&self, state: &S, time: Duration) {
+        (**self).dwell(state, time);
+    }
+}
+
+/// A [`Metrics`] sink aggregating what a machine reports: how many times it
+/// took each edge, how many events it rejected with each kind of
+/// [`MachineError`], and a [`Histogram`] of the time it spent in each state.
+///
+/// Install it behind an [`Rc`] to keep a handle to read it back.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::{cell::Cell, rc::Rc, time::Duration};
+///
+/// use nanomachine::{Machine, Stats};
+///
+/// let mut nano = Machine::new("created");
+/// nano.when("pay", "created", "paid");
+///
+/// let secs = Rc::new(Cell::new(0));
+/// let stats = Rc::new(Stats::new());
+/// let clock = secs.clone();
+/// nano.record_metrics(stats.clone(), move || Duration::from_secs(clock.get()));
+///
+/// secs.set(5);
+/// nano.trigger(&"pay").unwrap();
+/// nano.trigger(&"pay").unwrap_err();
+///
+/// assert_eq!(stats.edge_count(&"pay", &"created", &"paid"), 1);
+/// assert_eq!(stats.rejection_count("StateInvalid"), 1);
+///
+/// let dwell = stats.dwell_histogram(&"created").unwrap();
+/// assert_eq!(dwell.count(), 1);
+/// assert_eq!(dwell.sum(), Duration::from_secs(5));
+/// ```
+#[derive(Debug)]
+pub struct Stats<S, E> {
+    bounds: Rc<[Duration]>,
+    edges: RefCell<HashMap<(E, S, S), u64>>,
+    rejections: RefCell<HashMap<&'static str, u64>>,
+    dwell: RefCell<HashMap<S, Histogram>>,
+}
+
+impl<S, E> Default for Stats<S, E> {
+    fn default() -> Self {
+        let bounds = [1, 10, 100, 1_000, 10_000, 60_000, 600_000, 3_600_000];
+        Stats::with_buckets(bounds.map(Duration::from_millis))
+    }
+}
+
+impl<S, E> Stats<S, E> {
+    /// Create empty stats, with dwell time buckets from a millisecond up to an
+    /// hour.
+    #[must_use]
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    /// Create empty stats, with dwell time buckets bounded above by `bounds`.
+    ///
+    /// The bounds are sorted, and a last bucket counts the times above all of
+    /// them.
+    pub fn with_buckets(bounds: impl IntoIterator<Item = Duration>) -> Self {
+        let mut bounds: Vec<Duration> = bounds.into_iter().collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+        Stats {
+            bounds: bounds.into(),
+            edges: RefCell::new(HashMap::new()),
+            rejections: RefCell::new(HashMap::new()),
+            dwell: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// How many events were rejected with the [`MachineError`] variant named
+    /// `name`, as returned by [`MachineError::name`].
+    #[must_use]
+    pub fn rejection_count(&self, name: &str) -> u64 {
+        self.rejections.borrow().get(name).copied().unwrap_or_default()
+    }
+}
+
+impl<S, E> Stats<S, E>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+{
+    /// How many times the edge `from --event--> to` was taken.
+    #[must_use]
+    pub fn edge_count(&self, event: &E, from: &S, to: &S) -> u64 {
+        let edge = (event.clone(), from.clone(), to.clone());
+        self.edges.borrow().get(&edge).copied().unwrap_or_default()
+    }
+
+    /// The times spent in `state` before leaving it, if it was ever left.
+    #[must_use]
+    pub fn dwell_histogram(&self, state: &S) -> Option<Histogram> {
+        self.dwell.borrow().get(state).cloned()
+    }
+}
+
+impl<S, E> Metrics<S, E> for Stats<S, E>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+{
+    fn transition(&self, event: &E, from: &S, to: &S) {
+        let edge = (event.clone(), from.clone(), to.clone());
+        *self.edges.borrow_mut().entry(edge).or_default() += 1;
+    }
+
+    fn rejected(&self, _state: &S, _event: &E, error: &MachineError) {
+        *self.rejections.borrow_mut().entry(error.name()).or_default() += 1;
+    }
+
+    fn dwell(&self, state: &S, time: Duration) {
+        self.dwell
+            .borrow_mut()
+            .entry(state.clone())
+            .or_insert_with(|| Histogram::new(self.bounds.clone()))
+            .observe(time);
+    }
+}
+
+/// A histogram of durations, counting them in buckets.
+///
+/// Bucket `i` counts the durations up to [`bounds`](Histogram::bounds)`[i]`
+/// and above the bound before it. A last bucket counts the durations above
+/// every bound.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bounds: Rc<[Duration]>,
+    counts: Vec<u64>,
+    sum: Duration,
+}
+
+impl Histogram {
+    /// Create an empty histogram with buckets bounded above by `bounds`,
+    /// sorted.
+    fn new(bounds: Rc<[Duration]>) -> Self {
+        let counts = vec![0; bounds.len() + 1];
+        Histogram { bounds, counts, sum: Duration::ZERO }
+    }
+
+    /// Count `time` in its bucket.
+    fn observe(&mut self, time: Duration) {
+        let bucket = self.bounds.partition_point(|bound| *bound < time);
+        self.counts[bucket] += 1;
+        self.sum = self.sum.saturating_add(time);
+    }
+
+    /// The upper bounds of the buckets, in increasing order.
+    #[inline]
+    #[must_use]
+    pub fn bounds(&self) -> &[Duration] {
+        &self.bounds
+    }
+
+    /// The count of each bucket, one more than there are bounds.
+    #[inline]
+    #[must_use]
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// How many durations were counted.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The total of the durations counted.
+    #[inline]
+    #[must_use]
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+}
+
+/// A source of time for measuring how long a machine stays in each state.
+///
+/// `now` returns the time elapsed since some fixed point, which only has to be
+/// the same for every call. Any `Fn() -> Duration` is a clock, e.g. a closure
+/// returning `start.elapsed()` for some `std::time::Instant`, or a manual
+/// clock in tests.
+pub trait Clock {
+    /// The current time.
+    fn now(&self) -> Duration;
+}
+
+impl<F: Fn() -> Duration> Clock for F {
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+/// The metrics sink of a machine, with the time it entered its state.
+pub(crate) struct Recorder<S, E> {
+    metrics: Rc<dyn Metrics<S, E>>,
+    clock: Rc<dyn Clock>,
+    entered: Duration,
+}
+
+impl<S, E> Clone for Recorder<S, E> {
+    fn clone(&self) -> Self {
+        Recorder {
+            metrics: self.metrics.clone(),
+            clock: self.clock.clone(),
+            entered: self.entered,
+        }
+    }
+}
+
+impl<S, E> Recorder<S, E> {
+    /// Report the outcome of triggering `event` from `from`, which left the
+    /// machine in `to`, having `exited` `from` on the way or not.
+    pub(crate) fn record(
+        &mut self,
+        event: &E,
+        from: &S,
+        to: &S,
+        exited: bool,
+        result: &Result<(), TriggerError<S, E>>,
+    ) {
+        match result {
+            Ok(()) => self.metrics.transition(event, from, to),
+            Err(err) => self.reject(event, err),
+        }
+        if exited {
+            self.leave(from);
+        }
+    }
+
+    /// Report that triggering `event` failed with `err`.
+    pub(crate) fn reject(&self, event: &E, err: &TriggerError<S, E>) {
+        self.metrics.rejected(err.state(), event, err.error());
+    }
+
+    /// Report the time spent in `state`, which the machine just left.
+    fn leave(&mut self, state: &S) {
+        let now = self.clock.now();
+        self.metrics.dwell(state, now.saturating_sub(self.entered));
+        self.entered = now;
+    }
+}
+
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Start reporting metrics to `metrics`, timing states with `clock`.
+    ///
+    /// From then on, triggering an event reports:
+    ///
+    /// - on success, the edge it took to [`Metrics::transition`],
+    /// - on failure, the [`MachineError`] to [`Metrics::rejected`],
+    /// - whenever the machine exits its state, the time since it entered it to
+    ///   [`Metrics::dwell`]. [Internal](crate::TransitionKind::Internal)
+    ///   transitions don't exit the state. An external transition exits it once
+    ///   its exit callbacks and action succeeded, even if an enter or
+    ///   transition callback fails afterwards, unless [`Machine::try_trigger`]
+    ///   rolls it back. The time in the current state is counted from now.
+    ///
+    /// The events of [`Machine::trigger_all`] are only reported once the whole
+    /// sequence succeeded, and only the failure is if it is rolled back.
+    ///
+    /// Replaces any sink installed before. Clones of the machine report to the
+    /// same sink.
+    pub fn record_metrics<M, K>(&mut self, metrics: M, clock: K)
+    where
+        M: Metrics<S, E> + 'static,
+        K: Clock + 'static,
+    {
+        let entered = clock.now();
+        self.metrics = Some(Recorder {
+            metrics: Rc::new(metrics),
+            clock: Rc::new(clock),
+            entered,
+        });
+    }
+
+    /// Stop reporting metrics.
+    #[inline]
+    pub fn stop_metrics(&mut self) {
+        self.metrics = None;
+    }
+
+    /// How long the machine has been in its current state, if
+    /// [reporting metrics](Machine::record_metrics).
+    pub fn dwell_time(&self) -> Option<Duration> {
+        let recorder = self.metrics.as_ref()?;
+        Some(recorder.clock.now().saturating_sub(recorder.entered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+    use core::cell::{Cell, RefCell};
+
+    use super::*;
+
+    /// Records every metric as a line.
+    #[derive(Default)]
+    struct Log(RefCell<Vec<String>>);
+
+    impl Metrics<&'static str, &'static str> for Log {
+        fn transition(
+            &self,
+            event: &&'static str,
+            from: &&'static str,
+            to: &&'static str,
+        ) {
+            let line = alloc::format!("{from} --{event}--> {to}");
+            self.0.borrow_mut().push(line);
+        }
+
+        fn rejected(
+            &self,
+            state: &&'static str,
+            event: &&'static str,
+            error: &MachineError,
+        ) {
+            let line = alloc::format!("{event} in {state}: {}", error.name());
+            self.0.borrow_mut().push(line);
+        }
+
+        fn dwell(&self, state: &&'static str, time: Duration) {
+            let line = alloc::format!("{state} for {}s", time.as_secs());
+            self.0.borrow_mut().push(line);
+        }
+    }
+
+    fn order() -> (Machine<&'static str, &'static str>, Rc<Log>, Rc<Cell<u64>>)
+    {
+        let mut m = Machine::new("created");
+        m.when("pay", "created", "paid");
+        m.when_internal("remind", "paid");
+        m.when_self("recheck", "paid");
+        m.when("ship", "paid", "shipped");
+        m.when_self("retry", "shipped");
+        m.try_on_enter_with("shipped", |_, (): &()| Err("down".into()));
+
+        let log = Rc::new(Log::default());
+        let secs = Rc::new(Cell::new(0));
+        let clock = secs.clone();
+        m.record_metrics(log.clone(), move || Duration::from_secs(clock.get()));
+        (m, log, secs)
+    }
+
+    #[test]
+    fn opt_in() {
+        let mut m = Machine::new("created");
+        m.when("pay", "created", "paid");
+        m.trigger(&"pay").unwrap();
+        assert_eq!(m.dwell_time(), None);
+    }
+
+    #[test]
+    fn counts_edges_and_dwell_time() {
+        let (mut m, log, secs) = order();
+        secs.set(3);
+        assert_eq!(m.dwell_time(), Some(Duration::from_secs(3)));
+        m.trigger(&"pay").unwrap();
+        secs.set(5);
+        m.trigger(&"remind").unwrap();
+        secs.set(9);
+        m.trigger(&"recheck").unwrap();
+        secs.set(10);
+
+        assert_eq!(m.dwell_time(), Some(Duration::from_secs(1)));
+        assert_eq!(
+            *log.0.borrow(),
+            [
+                "created --pay--> paid",
+                "created for 3s",
+                "paid --remind--> paid",
+                "paid --recheck--> paid",
+                "paid for 6s",
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_rejections() {
+        let (mut m, log, secs) = order();
+        m.trigger(&"cancel").unwrap_err();
+        m.trigger(&"ship").unwrap_err();
+        m.trigger(&"pay").unwrap();
+        secs.set(2);
+        m.trigger(&"ship").unwrap_err();
+        m.trigger_all(&["retry", "pay"]).unwrap_err();
+
+        assert_eq!(*m.state(), "shipped");
+        assert_eq!(
+            *log.0.borrow(),
+            [
+                "cancel in created: EventInvalid",
+                "ship in created: StateInvalid",
+                "created --pay--> paid",
+                "created for 0s",
+                "ship in paid: CallbackFailed",
+                "paid for 2s",
+                "pay in shipped: StateInvalid",
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_self_transitions_exit() {
+        let (mut m, log, secs) = order();
+        m.trigger(&"pay").unwrap();
+        m.trigger(&"ship").unwrap_err();
+        log.0.borrow_mut().clear();
+        secs.set(4);
+        m.trigger(&"retry").unwrap_err();
+
+        assert_eq!(m.dwell_time(), Some(Duration::ZERO));
+        assert_eq!(
+            *log.0.borrow(),
+            ["retry in shipped: CallbackFailed", "shipped for 4s"]
+        );
+    }
+
+    #[test]
+    fn rolled_back_transitions_dont_exit() {
+        let (mut m, log, secs) = order();
+        m.trigger(&"pay").unwrap();
+        secs.set(2);
+        m.try_trigger(&"ship").unwrap_err();
+        secs.set(3);
+
+        assert_eq!(*m.state(), "paid");
+        assert_eq!(m.dwell_time(), Some(Duration::from_secs(3)));
+        assert_eq!(
+            *log.0.borrow(),
+            [
+                "created --pay--> paid",
+                "created for 0s",
+                "ship in paid: CallbackFailed",
+            ]
+        );
+    }
+
+    #[test]
+    fn rolled_back_sequences_are_not_reported() {
+        let (mut m, log, secs) = order();
+        m.trigger(&"pay").unwrap();
+        log.0.borrow_mut().clear();
+        secs.set(2);
+        m.trigger_all(&["recheck", "ship"]).unwrap_err();
+        secs.set(3);
+
+        assert_eq!(*m.state(), "paid");
+        assert_eq!(m.dwell_time(), Some(Duration::from_secs(3)));
+        assert_eq!(*log.0.borrow(), ["ship in paid: CallbackFailed"]);
+
+        m.trigger_all(&["remind", "recheck"]).unwrap();
+        assert_eq!(
+            *log.0.borrow(),
+            [
+                "ship in paid: CallbackFailed",
+                "paid --remind--> paid",
+                "paid --recheck--> paid",
+                "paid for 3s",
+            ]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut m = Machine::new("created");
+        m.when("pay", "created", "paid");
+        m.when("refund", "paid", "created");
+        let stats = Rc::new(Stats::with_buckets([
+            Duration::from_secs(10),
+            Duration::from_secs(1),
+        ]));
+        let secs = Rc::new(Cell::new(0));
+        let clock = secs.clone();
+        m.record_metrics(stats.clone(), move || {
+            Duration::from_secs(clock.get())
+        });
+
+        for (time, event) in [(1, "pay"), (6, "refund"), (26, "pay")] {
+            secs.set(time);
+            m.trigger(&event).unwrap();
+        }
+        m.trigger(&"ship").unwrap_err();
+        m.trigger(&"pay").unwrap_err();
+
+        assert_eq!(stats.edge_count(&"pay", &"created", &"paid"), 2);
+        assert_eq!(stats.edge_count(&"refund", &"paid", &"created"), 1);
+        assert_eq!(stats.edge_count(&"pay", &"paid", &"created"), 0);
+        assert_eq!(stats.rejection_count("EventInvalid"), 1);
+        assert_eq!(stats.rejection_count("StateInvalid"), 1);
+        assert_eq!(stats.rejection_count("GuardRejected"), 0);
+
+        let created = stats.dwell_histogram(&"created").unwrap();
+        assert_eq!(created.bounds(), [1, 10].map(Duration::from_secs));
+        assert_eq!(created.counts(), [1, 0, 1]);
+        assert_eq!(created.count(), 2);
+        assert_eq!(created.sum(), Duration::from_secs(21));
+        let paid = stats.dwell_histogram(&"paid").unwrap();
+        assert_eq!(paid.counts(), [0, 1, 0]);
+        assert_eq!(paid.sum(), Duration::from_secs(5));
+        assert_eq!(stats.dwell_histogram(&"shipped"), None);
+    }
+
+    #[test]
+    fn stop() {
+        let (mut m, log, _) = order();
+        m.stop_metrics();
+        m.trigger(&"pay").unwrap();
+        assert!(log.0.borrow().is_empty());
+        assert_eq!(m.dwell_time(), None);
+    }
+}
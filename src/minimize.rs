@@ -0,0 +1,338 @@
+use alloc::{vec, vec::Vec};
+use core::hash::Hash;
+
+use hashbrown::HashMap;
+
+use crate::Machine;
+
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Build the smallest machine that behaves like this one, merging
+    /// equivalent states with Hopcroft's partition refinement.
+    ///
+    /// Two states are equivalent when they agree on whether they are
+    /// [accepting](Machine::set_accepting) and, for every sequence of events,
+    /// accept or reject it the same way and end up in equivalent states. A
+    /// missing transition counts as a rejection, so merging never turns an
+    /// invalid event into a valid one.
+    ///
+    /// Only states reachable from the current state are kept. Returns the
+    /// minimised machine, which starts in the current state, along with a
+    /// `(state, representative)` pair for every reachable state, pairing it
+    /// with the state representing its class in the minimised machine. The
+    /// current state represents its own class and comes first.
+    ///
+    /// The minimised machine only carries the transition structure and the
+    /// accepting states: guards, actions, callbacks and context are not
+    /// copied, and every transition is [external](crate::TransitionKind).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use nanomachine::Machine;
+    ///
+    /// let mut nano = Machine::new("idle");
+    /// nano.when("start", "idle", "left");
+    /// nano.when("start", "left", "right");
+    /// nano.when("start", "right", "left");
+    /// nano.set_accepting("left");
+    /// nano.set_accepting("right");
+    ///
+    /// let (minimal, representatives) = nano.minimize();
+    ///
+    /// assert_eq!(minimal.states().count(), 2);
+    /// assert!(representatives.contains(&("right", "left")));
+    /// ```
+    #[must_use]
+    pub fn minimize(&self) -> (Machine<S, E>, Vec<(S, S)>) {
+        let events: Vec<&E> = self.events().collect();
+        let event_index: HashMap<&E, usize> =
+            events.iter().enumerate().map(|(i, e)| (*e, i)).collect();
+        let mut outgoing: HashMap<&S, Vec<(usize, &S)>> = HashMap::new();
+        for (event, from, to) in self.transitions() {
+            outgoing.entry(from).or_default().push((event_index[event], to));
+        }
+
+        // Number the reachable states in breadth-first order, so that the
+        // current state gets index 0.
+        let mut states = vec![&self.state];
+        let mut index = HashMap::new();
+        index.insert(&self.state, 0);
+        let mut next = 0;
+        while let Some(from) = states.get(next) {
+            for (_, to) in outgoing.get(from).into_iter().flatten() {
+                if !index.contains_key(to) {
+                    index.insert(*to, states.len());
+                    states.push(*to);
+                }
+            }
+            next += 1;
+        }
+
+        // Missing transitions lead to an extra sink state, which is kept in a
+        // class of its own so that no state is merged with it.
+        let sink = states.len();
+        let m = events.len();
+        let mut delta = vec![sink; (sink + 1) * m];
+        for (s, state) in states.iter().enumerate() {
+            for (e, to) in outgoing.get(state).into_iter().flatten() {
+                delta[s * m + e] = index[to];
+            }
+        }
+        let mut inverse = vec![Vec::new(); (sink + 1) * m];
+        for s in 0..=sink {
+            for e in 0..m {
+                inverse[delta[s * m + e] * m + e].push(s);
+            }
+        }
+
+        let (accepting, rejecting) =
+            (0..sink).partition(|&s| self.is_accepting(states[s]));
+        let blocks = refine(&[accepting, rejecting, vec![sink]], &inverse, m);
+
+        let mut block_of = vec![0; sink + 1];
+        for (b, block) in blocks.iter().enumerate() {
+            for &s in block {
+                block_of[s] = b;
+            }
+        }
+        // Blocks are sorted, so their first member is the earliest state.
+        let representative = |s: usize| states[blocks[block_of[s]][0]];
+
+        let mut minimal = Machine::new(self.state.clone());
+        let mut representatives = Vec::with_capacity(states.len());
+        for (s, state) in states.iter().enumerate() {
+            let rep = representative(s);
+            representatives.push(((*state).clone(), rep.clone()));
+            if rep != *state {
+                continue;
+            }
+            if self.is_accepting(state) {
+                minimal.set_accepting(rep.clone());
+            }
+            for (e, event) in events.iter().enumerate() {
+                let to = delta[s * m + e];
+                if to != sink {
+                    let target = representative(to).clone();
+                    minimal.when((*event).clone(), rep.clone(), target);
+                }
+            }
+        }
+        (minimal, representatives)
+    }
+}
+
+/// Refine `initial` until no block can be split by the predecessors of
+/// another, returning the resulting blocks, each sorted.
+///
+/// `inverse[t * m + e]` lists the states that move to `t` on event `e`, where
+/// `m` is the number of events.
+fn refine(
+    initial: &[Vec<usize>],
+    inverse: &[Vec<usize>],
+    m: usize,
+) -> Vec<Vec<usize>> {
+    let mut blocks: Vec<Vec<usize>> =
+        initial.iter().filter(|b| !b.is_empty()).cloned().collect();
+    let n = blocks.iter().map(Vec::len).sum();
+    let mut block_of = vec![0; n];
+    for (b, block) in blocks.iter().enumerate() {
+        for &s in block {
+            block_of[s] = b;
+        }
+    }
+
+    let mut pending: Vec<usize> = (0..blocks.len()).collect();
+    let mut is_pending = vec![true; blocks.len()];
+    let mut marked = vec![false; n];
+    while let Some(splitter) = pending.pop() {
+        is_pending[splitter] = false;
+        let splitter = blocks[splitter].clone();
+        for e in 0..m {
+            let mut touched: HashMap<usize, Vec<usize>> = HashMap::new();
+            for &t in &splitter {
+                for &s in &inverse[t * m + e] {
+                    touched.entry(block_of[s]).or_default().push(s);
+                }
+            }
+            for (b, inside) in touched {
+                if inside.len() == blocks[b].len() {
+                    continue;
+                }
+                for &s in &inside {
+                    marked[s] = true;
+                }
+                let (moved, kept): (Vec<_>, Vec<_>) =
+                    blocks[b].iter().partition(|&&s| marked[s]);
+                for &s in &inside {
+                    marked[s] = false;
+                }
+
+                let new = blocks.len();
+                for &s in &moved {
+                    block_of[s] = new;
+                }
+                let smaller = if is_pending[b] || moved.len() <= kept.len() {
+                    new
+                } else {
+                    b
+                };
+                blocks[b] = kept;
+                blocks.push(moved);
+                is_pending.push(false);
+                if !is_pending[smaller] {
+                    is_pending[smaller] = true;
+                    pending.push(smaller);
+                }
+            }
+        }
+    }
+
+    for block in &mut blocks {
+        block.sort_unstable();
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{Machine, MachineError, TriggerError, testing::words};
+
+    /// Counts `a`s modulo 2 using four states, where `0`/`2` and `1`/`3` are
+    /// equivalent. `b` is only valid from even states.
+    fn redundant() -> Machine<u8, char> {
+        let mut nano = Machine::new(0);
+        nano.when('a', 0, 1);
+        nano.when('a', 1, 2);
+        nano.when('a', 2, 3);
+        nano.when('a', 3, 0);
+        nano.when('b', 0, 0);
+        nano.when('b', 2, 0);
+        nano.set_accepting(0);
+        nano.set_accepting(2);
+        nano
+    }
+
+    /// Runs `word` and reports the error of the first rejected event, or
+    /// whether the machine ends in an accepting state.
+    fn run(
+        mut nano: Machine<u8, char>,
+        word: &[char],
+    ) -> Result<bool, MachineError> {
+        for c in word {
+            nano.trigger(c)?;
+        }
+        Ok(nano.is_accepting(nano.state()))
+    }
+
+    #[test]
+    fn accepting_states() {
+        let nano = redundant();
+
+        assert!(nano.is_accepting(&0));
+        assert!(!nano.is_accepting(&1));
+
+        let mut accepting: Vec<_> = nano.accepting_states().copied().collect();
+        accepting.sort_unstable();
+        assert_eq!(accepting, [0, 2]);
+    }
+
+    #[test]
+    fn accepting_states_are_known() {
+        let mut nano: Machine<u8, char> = Machine::new(0);
+        nano.set_accepting(5);
+
+        assert_eq!(nano.states().copied().collect::<Vec<_>>(), [5]);
+    }
+
+    /// The state representing `state`'s class.
+    fn rep<S: PartialEq + Copy>(representatives: &[(S, S)], state: S) -> S {
+        representatives.iter().find(|(s, _)| *s == state).unwrap().1
+    }
+
+    #[test]
+    fn merges_equivalent_states() {
+        let (minimal, representatives) = redundant().minimize();
+
+        let mut states: Vec<_> = minimal.states().copied().collect();
+        states.sort_unstable();
+        assert_eq!(states, [0, 1]);
+        assert_eq!(*minimal.state(), 0);
+        assert!(minimal.is_accepting(&0));
+        assert!(!minimal.is_accepting(&1));
+
+        assert_eq!(rep(&representatives, 0), 0);
+        assert_eq!(rep(&representatives, 1), 1);
+        assert_eq!(rep(&representatives, 2), 0);
+        assert_eq!(rep(&representatives, 3), 1);
+    }
+
+    #[test]
+    fn keeps_behaviour() {
+        let nano = redundant();
+        let (minimal, _) = nano.minimize();
+
+        for word in words(&['a', 'b', 'c'], 6) {
+            let expected = run(nano.clone(), &word);
+            assert_eq!(run(minimal.clone(), &word), expected, "word {word:?}");
+        }
+    }
+
+    #[test]
+    fn distinguishes_missing_transitions() {
+        let mut nano = Machine::new(0);
+        nano.when('a', 0, 1);
+        nano.when('b', 0, 2);
+        nano.when('c', 0, 3);
+        nano.when('c', 1, 1);
+
+        let (minimal, representatives) = nano.minimize();
+
+        assert_eq!(minimal.states().count(), 3);
+        assert_eq!(rep(&representatives, 1), 1);
+        assert_eq!(rep(&representatives, 2), rep(&representatives, 3));
+        assert_eq!(
+            minimal.clone().trigger(&'c').map_err(TriggerError::into_error),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn drops_unreachable_states() {
+        let mut nano = redundant();
+        nano.when('a', 7, 0);
+
+        let (minimal, representatives) = nano.minimize();
+
+        assert!(representatives.iter().all(|(s, _)| *s != 7));
+        assert!(minimal.states().all(|s| *s != 7));
+    }
+
+    #[test]
+    fn current_state_represents_its_class() {
+        let mut nano = redundant();
+        nano.trigger(&'a').unwrap();
+        nano.trigger(&'a').unwrap();
+
+        let (minimal, representatives) = nano.minimize();
+
+        assert_eq!(*minimal.state(), 2);
+        assert_eq!(rep(&representatives, 0), 2);
+        assert_eq!(rep(&representatives, 1), 3);
+    }
+
+    #[test]
+    fn minimal_machines_are_unchanged() {
+        let (minimal, _) = redundant().minimize();
+        let (again, representatives) = minimal.minimize();
+
+        assert_eq!(again.states().count(), 2);
+        assert!(representatives.iter().all(|(s, r)| s == r));
+    }
+}
@@ -0,0 +1,451 @@
+use alloc::{collections::BTreeSet, vec::Vec};
+use core::{borrow::Borrow, hash::Hash};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{Machine, MachineError, TriggerError};
+
+/// A nondeterministic finite automaton.
+///
+/// Unlike a [`Machine`], an event may lead from a state to several targets,
+/// and states may be linked by epsilon moves that are taken without consuming
+/// an event. The automaton tracks the set of states it could be in, and
+/// [`Nfa::determinize`] turns it into an ordinary [`Machine`] over those sets.
+///
+/// States are kept in [`BTreeSet`]s so that state sets can themselves be used
+/// as states of a [`Machine`], which is why `S` must be `Ord`.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::Nfa;
+///
+/// // Recognise identifiers that end in "ab".
+/// let mut nfa = Nfa::new(0);
+/// nfa.when('a', 0, 0);
+/// nfa.when('b', 0, 0);
+/// nfa.when('a', 0, 1);
+/// nfa.when('b', 1, 2);
+///
+/// nfa.trigger(&'a').unwrap();
+/// assert_eq!(nfa.active().iter().copied().collect::<Vec<_>>(), [0, 1]);
+///
+/// nfa.trigger(&'b').unwrap();
+/// assert!(nfa.is_active(&2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Nfa<S, E> {
+    initial: S,
+    active: BTreeSet<S>,
+    transitions: HashMap<E, HashMap<S, BTreeSet<S>>>,
+    epsilon: HashMap<S, BTreeSet<S>>,
+    accepting: HashSet<S>,
+}
+
+impl<S, E> Nfa<S, E>
+where
+    S: Ord + Hash + Clone,
+    E: Eq + Hash + Clone,
+{
+    /// Create a new automaton with the given initial state.
+    pub fn new(initial_state: S) -> Self {
+        let mut active = BTreeSet::new();
+        active.insert(initial_state.clone());
+        Nfa {
+            initial: initial_state,
+            active,
+            transitions: HashMap::new(),
+            epsilon: HashMap::new(),
+            accepting: HashSet::new(),
+        }
+    }
+
+    /// The state the automaton starts in.
+    #[inline]
+    #[must_use]
+    pub fn initial(&self) -> &S {
+        &self.initial
+    }
+
+    /// The set of states the automaton could currently be in.
+    ///
+    /// Always closed under epsilon moves.
+    #[inline]
+    #[must_use]
+    pub fn active(&self) -> &BTreeSet<S> {
+        &self.active
+    }
+
+    /// Whether `state` is one of the states the automaton could currently be
+    /// in.
+    #[inline]
+    #[must_use]
+    pub fn is_active(&self, state: &S) -> bool {
+        self.active.contains(state)
+    }
+
+    /// Returns an iterator over all states known to the automaton.
+    pub fn states(&self) -> impl Iterator<Item = &S> {
+        let mut used: HashSet<&S> = self.accepting.iter().collect();
+        used.insert(&self.initial);
+        let moves = self.transitions.values().flatten();
+        for (from, targets) in moves.chain(&self.epsilon) {
+            used.insert(from);
+            used.extend(targets);
+        }
+        used.into_iter()
+    }
+
+    /// Returns an iterator over all events the automaton can react to.
+    #[inline]
+    pub fn events(&self) -> impl Iterator<Item = &E> {
+        self.transitions.keys()
+    }
+
+    /// When `event` occurs in `state`, `new_state` becomes active.
+    ///
+    /// Multiple calls to `when` for the same `(event, state)` add targets
+    /// rather than replacing them.
+    pub fn when(&mut self, event: E, state: S, new_state: S) {
+        self.transitions
+            .entry(event)
+            .or_default()
+            .entry(state)
+            .or_default()
+            .insert(new_state);
+    }
+
+    /// Whenever `state` is active, `new_state` is active too.
+    ///
+    /// The move is taken without consuming an event. If `state` is currently
+    /// active, `new_state` and everything reachable from it through epsilon
+    /// moves becomes active immediately.
+    pub fn when_epsilon(&mut self, state: S, new_state: S) {
+        self.epsilon.entry(state).or_default().insert(new_state);
+        let active = core::mem::take(&mut self.active);
+        self.active = self.epsilon_closure(active);
+    }
+
+    /// Designate `state` as an accepting, or final, state.
+    pub fn set_accepting(&mut self, state: S) {
+        self.accepting.insert(state);
+    }
+
+    /// Whether `state` is an accepting state.
+    #[inline]
+    #[must_use]
+    pub fn is_accepting(&self, state: &S) -> bool {
+        self.accepting.contains(state)
+    }
+
+    /// Returns an iterator over the accepting states.
+    #[inline]
+    pub fn accepting_states(&self) -> impl Iterator<Item = &S> {
+        self.accepting.iter()
+    }
+
+    /// Whether triggering `events` from the active states can end in an
+    /// accepting state.
+    ///
+    /// The automaton itself is left untouched.
+    pub fn accepts<I>(&self, events: I) -> bool
+    where
+        I: IntoIterator,
+        I::Item: Borrow<E>,
+    {
+        let mut active = self.active.clone();
+        for event in events {
+            match self.step(&active, event.borrow()) {
+                Some(next) if !next.is_empty() => active = next,
+                _ => return false,
+            }
+        }
+        active.iter().any(|state| self.is_accepting(state))
+    }
+
+    /// Every state reachable from `states` through epsilon moves alone,
+    /// including `states` themselves.
+    pub fn epsilon_closure<I>(&self, states: I) -> BTreeSet<S>
+    where
+        I: IntoIterator<Item = S>,
+    {
+        let mut closure = BTreeSet::new();
+        let mut pending: Vec<S> = states.into_iter().collect();
+        while let Some(state) = pending.pop() {
+            if let Some(targets) = self.epsilon.get(&state) {
+                pending.extend(
+                    targets.iter().filter(|t| !closure.contains(*t)).cloned(),
+                );
+            }
+            closure.insert(state);
+        }
+        closure
+    }
+
+    /// The states that become active when `event` occurs while `states` are
+    /// active, or `None` if the event is not defined for this automaton.
+    fn step(&self, states: &BTreeSet<S>, event: &E) -> Option<BTreeSet<S>> {
+        let state_map = self.transitions.get(event)?;
+        let targets = states
+            .iter()
+            .filter_map(|state| state_map.get(state))
+            .flatten()
+            .cloned();
+        Some(self.epsilon_closure(targets))
+    }
+
+    /// Move every active state along `event`.
+    ///
+    /// Active states without a transition for `event` are dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TriggerError`] whose state is the active set, and leaves the
+    /// active set untouched:
+    ///
+    /// - Returns [`MachineError::EventInvalid`] if the event is not defined in
+    ///   this automaton.
+    /// - Returns [`MachineError::StateInvalid`] if none of the active states
+    ///   has a transition for the event.
+    pub fn trigger(
+        &mut self,
+        event: &E,
+    ) -> Result<(), TriggerError<BTreeSet<S>, E>> {
+        let fail = |error, active: &BTreeSet<S>| {
+            TriggerError::new(error, event.clone(), active.clone(), None)
+        };
+        let Some(next) = self.step(&self.active, event) else {
+            return Err(fail(MachineError::EventInvalid, &self.active));
+        };
+        if next.is_empty() {
+            return Err(fail(MachineError::StateInvalid, &self.active));
+        }
+        self.active = next;
+        Ok(())
+    }
+
+    /// Go back to the epsilon closure of the initial state.
+    pub fn reset(&mut self) {
+        self.active = self.epsilon_closure([self.initial.clone()]);
+    }
+
+    /// Build the equivalent deterministic [`Machine`] with the subset
+    /// construction.
+    ///
+    /// Each state of the resulting machine is a set of states of the
+    /// automaton, starting at the epsilon closure of the initial state. Only
+    /// sets reachable from there are built, and events that would leave no
+    /// state active have no transition, so they are rejected with
+    /// [`MachineError::StateInvalid`] just like they are by
+    /// [`Nfa::trigger`]. A set is accepting if any of its states is.
+    #[must_use]
+    pub fn determinize(&self) -> Machine<BTreeSet<S>, E> {
+        let start = self.epsilon_closure([self.initial.clone()]);
+        let mut machine = Machine::new(start.clone());
+        let mut seen = HashSet::new();
+        let mut pending = Vec::new();
+        seen.insert(start.clone());
+        pending.push(start);
+
+        while let Some(states) = pending.pop() {
+            if states.iter().any(|state| self.is_accepting(state)) {
+                machine.set_accepting(states.clone());
+            }
+            for event in self.transitions.keys() {
+                let Some(next) = self.step(&states, event) else {
+                    continue;
+                };
+                if next.is_empty() {
+                    continue;
+                }
+                if seen.insert(next.clone()) {
+                    pending.push(next.clone());
+                }
+                machine.when(event.clone(), states.clone(), next);
+            }
+        }
+        machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::words;
+
+    fn set(states: &[u8]) -> BTreeSet<u8> {
+        states.iter().copied().collect()
+    }
+
+    /// Recognises `a*b` and `ab*` with epsilon moves out of the start state.
+    fn tokenizer() -> Nfa<u8, char> {
+        let mut nfa = Nfa::new(0);
+        nfa.when_epsilon(0, 1);
+        nfa.when_epsilon(0, 3);
+        nfa.when('a', 1, 1);
+        nfa.when('b', 1, 2);
+        nfa.when('a', 3, 4);
+        nfa.when('b', 4, 4);
+        nfa
+    }
+
+    #[test]
+    fn starts_in_epsilon_closure() {
+        let nfa = tokenizer();
+
+        assert_eq!(*nfa.initial(), 0);
+        assert_eq!(*nfa.active(), set(&[0, 1, 3]));
+    }
+
+    #[test]
+    fn tracks_all_active_states() {
+        let mut nfa = tokenizer();
+
+        nfa.trigger(&'a').unwrap();
+        assert_eq!(*nfa.active(), set(&[1, 4]));
+
+        nfa.trigger(&'b').unwrap();
+        assert_eq!(*nfa.active(), set(&[2, 4]));
+
+        nfa.trigger(&'b').unwrap();
+        assert_eq!(*nfa.active(), set(&[4]));
+        assert!(nfa.is_active(&4));
+        assert!(!nfa.is_active(&2));
+    }
+
+    #[test]
+    fn when_adds_targets() {
+        let mut nfa = Nfa::new(0);
+        nfa.when('a', 0, 1);
+        nfa.when('a', 0, 2);
+
+        nfa.trigger(&'a').unwrap();
+        assert_eq!(*nfa.active(), set(&[1, 2]));
+    }
+
+    #[test]
+    fn epsilon_moves_are_transitive() {
+        let mut nfa: Nfa<u8, char> = Nfa::new(0);
+        nfa.when_epsilon(1, 2);
+        nfa.when_epsilon(0, 1);
+        nfa.when_epsilon(2, 0);
+
+        assert_eq!(*nfa.active(), set(&[0, 1, 2]));
+        assert_eq!(nfa.epsilon_closure([2]), set(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn rejected_events_keep_active_states() {
+        let mut nfa = tokenizer();
+        nfa.trigger(&'b').unwrap();
+
+        let err = nfa.trigger(&'a').unwrap_err();
+        assert_eq!(err, MachineError::StateInvalid);
+        assert_eq!(*err.state(), set(&[2]));
+        assert_eq!(*nfa.active(), set(&[2]));
+
+        let err = nfa.trigger(&'c').unwrap_err();
+        assert_eq!(err, MachineError::EventInvalid);
+        assert_eq!(*err.event(), 'c');
+    }
+
+    #[test]
+    fn reset() {
+        let mut nfa = tokenizer();
+        nfa.trigger(&'b').unwrap();
+
+        nfa.reset();
+        assert_eq!(*nfa.active(), set(&[0, 1, 3]));
+    }
+
+    #[test]
+    fn states_and_events() {
+        let nfa = tokenizer();
+
+        let mut states: Vec<_> = nfa.states().copied().collect();
+        states.sort_unstable();
+        assert_eq!(states, [0, 1, 2, 3, 4]);
+
+        let mut events: Vec<_> = nfa.events().copied().collect();
+        events.sort_unstable();
+        assert_eq!(events, ['a', 'b']);
+    }
+
+    #[test]
+    fn determinize_starts_at_closure() {
+        let machine = tokenizer().determinize();
+
+        assert_eq!(*machine.state(), set(&[0, 1, 3]));
+    }
+
+    #[test]
+    fn determinize_matches_simulation() {
+        let nfa = tokenizer();
+        let alphabet = ['a', 'b', 'c'];
+
+        for word in words(&alphabet, 5) {
+            let mut simulated = nfa.clone();
+            let mut machine = nfa.determinize();
+            for c in &word {
+                let expected =
+                    simulated.trigger(c).map_err(TriggerError::into_error);
+                let actual =
+                    machine.trigger(c).map_err(TriggerError::into_error);
+                assert_eq!(actual, expected, "word {word:?}");
+                assert_eq!(
+                    machine.state(),
+                    simulated.active(),
+                    "word {word:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn determinize_only_builds_reachable_sets() {
+        let mut nfa = tokenizer();
+        nfa.when('a', 9, 9);
+
+        let machine = nfa.determinize();
+        let mut states: Vec<_> = machine.states().cloned().collect();
+        states.sort_unstable();
+        assert_eq!(
+            states,
+            [
+                set(&[0, 1, 3]),
+                set(&[1]),
+                set(&[1, 4]),
+                set(&[2]),
+                set(&[2, 4]),
+                set(&[4])
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts() {
+        let mut nfa = tokenizer();
+        nfa.set_accepting(2);
+        nfa.set_accepting(4);
+
+        assert!(nfa.accepts(['a', 'a', 'b']));
+        assert!(nfa.accepts(['a', 'b', 'b']));
+        assert!(nfa.accepts(['a']));
+        assert!(!nfa.accepts(['a', 'a']));
+        assert!(!nfa.accepts(['b', 'a']));
+        assert!(!nfa.accepts(['c']));
+        assert_eq!(*nfa.active(), set(&[0, 1, 3]));
+    }
+
+    #[test]
+    fn determinize_keeps_accepting_sets() {
+        let mut nfa = tokenizer();
+        nfa.set_accepting(4);
+        let machine = nfa.determinize();
+
+        let mut accepting: Vec<_> =
+            machine.accepting_states().cloned().collect();
+        accepting.sort_unstable();
+        assert_eq!(accepting, [set(&[1, 4]), set(&[2, 4]), set(&[4])]);
+    }
+}
@@ -0,0 +1,363 @@
+use alloc::{boxed::Box, rc::Weak};
+use core::{error::Error, iter};
+
+use crate::{CallbackId, MachineError, callback::Registered};
+
+/// A type that follows the transitions of a machine, as an alternative to
+/// registering several closures.
+///
+/// Every method has an empty default, so an observer only implements the hooks
+/// it cares about. Observers are registered with [`Machine::observe`], which
+/// holds them by weak reference: dropping the last [`Rc`] to an observer
+/// unregisters it.
+///
+/// Each hook fires along with the closures registered for the same moment,
+/// closures and observers running in the order they were registered:
+///
+/// - [`on_exit`](MachineObserver::on_exit) with `on_exit` callbacks,
+/// - [`on_enter`](MachineObserver::on_enter) with `on_enter` callbacks,
+/// - [`on_transition`](MachineObserver::on_transition) with `on_transition`
+///   callbacks, for [internal](crate::TransitionKind::Internal) transitions
+///   too,
+/// - [`on_rejected`](MachineObserver::on_rejected) whenever triggering an event
+///   returns an error.
+///
+/// If a fallible callback fails, the observers registered after it aren't
+/// notified of that moment.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::{cell::Cell, rc::Rc};
+///
+/// use nanomachine::{Machine, MachineError, MachineObserver};
+///
+/// #[derive(Default)]
+/// struct Counter {
+///     transitions: Cell<u32>,
+///     rejected: Cell<u32>,
+/// }
+///
+/// impl<S, E> MachineObserver<S, E> for Counter {
+///     fn on_transition(&self, _from: &S, _to: &S, _event: &E) {
+///         self.transitions.set(self.transitions.get() + 1);
+///     }
+///
+///     fn on_rejected(&self, _state: &S, _event: &E, _error: &MachineError) {
+///         self.rejected.set(self.rejected.get() + 1);
+///     }
+/// }
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+///
+/// let counter = Rc::new(Counter::default());
+/// nano.observe(&counter);
+///
+/// nano.trigger(&"insert coin").unwrap();
+/// nano.trigger(&"insert coin").unwrap_err();
+/// assert_eq!(counter.transitions.get(), 1);
+/// assert_eq!(counter.rejected.get(), 1);
+/// ```
+///
+/// [`Machine::observe`]: crate::Machine::observe
+/// [`Rc`]: alloc::rc::Rc
+#[allow(unused_variables)]
+pub trait MachineObserver<S, E> {
+    /// Called when the machine exits `state` because of `event`.
+    fn on_exit(&self, state: &S, event: &E) {}
+
+    /// Called when the machine enters `state` because of `event`.
+    fn on_enter(&self, state: &S, event: &E) {}
+
+    /// Called when `event` took the machine from `from` to `to`.
+    fn on_transition(&self, from: &S, to: &S, event: &E) {}
+
+    /// Called when triggering `event` in `state` failed with `error`.
+    ///
+    /// For [`MachineError::CallbackFailed`], the hooks that fired before the
+    /// failing callback have already been called.
+    fn on_rejected(&self, state: &S, event: &E, error: &MachineError) {}
+}
+
+/// An observer stored in a machine along with its id.
+pub(crate) struct Observer<S, E> {
+    pub(crate) id: CallbackId,
+    pub(crate) observer: Weak<dyn MachineObserver<S, E>>,
+}
+
+impl<S, E> Clone for Observer<S, E> {
+    fn clone(&self) -> Self {
+        Observer { id: self.id, observer: self.observer.clone() }
+    }
+}
+
+impl<S, E> Observer<S, E> {
+    /// Whether the observer hasn't been dropped yet.
+    pub(crate) fn is_live(&self) -> bool {
+        self.observer.strong_count() > 0
+    }
+}
+
+/// Call `hook` on every observer that is still alive, in order.
+pub(crate) fn notify<'a, S: 'a, E: 'a>(
+    observers: impl IntoIterator<Item = &'a Observer<S, E>>,
+    hook: impl Fn(&dyn MachineObserver<S, E>),
+) {
+    let observers = observers.into_iter();
+    for observer in observers.filter_map(|o| o.observer.upgrade()) {
+        hook(&*observer);
+    }
+}
+
+/// Invoke every live callback in `registered`, and `hook` on every live
+/// observer, in the order they were registered.
+///
+/// Stops at the first callback that fails, skipping the observers registered
+/// after it.
+pub(crate) fn dispatch<S, E: Clone, C, P: ?Sized>(
+    registered: &[Registered<E, C, P>],
+    observers: &[Observer<S, E>],
+    event: &E,
+    payload: &P,
+    context: &mut C,
+    hook: impl Fn(&dyn MachineObserver<S, E>),
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut observers = observers.iter().peekable();
+    for r in registered {
+        let Some(callback) = r.callback() else {
+            continue;
+        };
+        let before = iter::from_fn(|| observers.next_if(|o| o.id < r.id));
+        notify(before, &hook);
+        callback(event.clone(), payload, context)?;
+    }
+    notify(observers, hook);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, string::String, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::Machine;
+
+    /// Records every hook it receives, prefixed with its name.
+    struct Log {
+        name: &'static str,
+        seen: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl MachineObserver<&'static str, &'static str> for Log {
+        fn on_exit(&self, state: &&'static str, event: &&'static str) {
+            let entry =
+                alloc::format!("{}: exit {state} on {event}", self.name);
+            self.seen.borrow_mut().push(entry);
+        }
+
+        fn on_enter(&self, state: &&'static str, event: &&'static str) {
+            let entry =
+                alloc::format!("{}: enter {state} on {event}", self.name);
+            self.seen.borrow_mut().push(entry);
+        }
+
+        fn on_transition(
+            &self,
+            from: &&'static str,
+            to: &&'static str,
+            event: &&'static str,
+        ) {
+            let entry =
+                alloc::format!("{}: {from} -> {to} on {event}", self.name);
+            self.seen.borrow_mut().push(entry);
+        }
+
+        fn on_rejected(
+            &self,
+            state: &&'static str,
+            event: &&'static str,
+            error: &MachineError,
+        ) {
+            let entry = alloc::format!(
+                "{}: rejected {event} in {state}: {error:?}",
+                self.name
+            );
+            self.seen.borrow_mut().push(entry);
+        }
+    }
+
+    fn order() -> Machine<&'static str, &'static str> {
+        let mut m = Machine::new("created");
+        m.when("pay", "created", "paid");
+        m.when_internal("remind", "paid");
+        m
+    }
+
+    #[test]
+    fn dispatches_in_registration_order() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let first = Rc::new(Log { name: "first", seen: seen.clone() });
+        let second = Rc::new(Log { name: "second", seen: seen.clone() });
+        let machine = || {
+            let mut m = order();
+            m.observe(&first);
+            let s = seen.clone();
+            m.on_enter("paid", move |_| s.borrow_mut().push("closure".into()));
+            m.observe(&second);
+            m
+        };
+        let expected = [
+            "first: exit created on pay",
+            "second: exit created on pay",
+            "first: enter paid on pay",
+            "closure",
+            "second: enter paid on pay",
+            "first: created -> paid on pay",
+            "second: created -> paid on pay",
+            "first: paid -> paid on remind",
+            "second: paid -> paid on remind",
+        ];
+
+        let mut m = machine();
+        m.trigger(&"pay").unwrap();
+        m.trigger(&"remind").unwrap();
+        assert_eq!(*seen.borrow(), expected);
+
+        seen.borrow_mut().clear();
+        let mut compiled = machine().compile();
+        compiled.trigger(&"pay").unwrap();
+        compiled.trigger(&"remind").unwrap();
+        assert_eq!(*seen.borrow(), expected);
+    }
+
+    #[test]
+    fn failing_callbacks_skip_later_observers() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let first = Rc::new(Log { name: "first", seen: seen.clone() });
+        let second = Rc::new(Log { name: "second", seen: seen.clone() });
+        m.observe(&first);
+        m.try_on_enter_with("paid", |_, (): &()| Err("down".into()));
+        m.observe(&second);
+
+        m.trigger(&"pay").unwrap_err();
+        let seen = seen.borrow();
+        assert_eq!(
+            seen[..3],
+            [
+                "first: exit created on pay",
+                "second: exit created on pay",
+                "first: enter paid on pay",
+            ]
+        );
+        assert!(seen[3].starts_with("first: rejected pay in created"));
+        assert!(seen[4].starts_with("second: rejected pay in created"));
+        assert_eq!(seen.len(), 5);
+    }
+
+    #[test]
+    fn reports_rejections() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let log = Rc::new(Log { name: "log", seen: seen.clone() });
+        m.observe(&log);
+        m.try_on_transition_with(|_, (): &()| Err("down".into()));
+
+        m.trigger(&"ship").unwrap_err();
+        m.trigger(&"remind").unwrap_err();
+        m.trigger(&"pay").unwrap_err();
+        let seen = seen.borrow();
+        assert_eq!(
+            seen[..5],
+            [
+                "log: rejected ship in created: EventInvalid",
+                "log: rejected remind in created: StateInvalid",
+                "log: exit created on pay",
+                "log: enter paid on pay",
+                "log: created -> paid on pay",
+            ]
+        );
+        assert!(
+            seen[5].starts_with("log: rejected pay in created: CallbackFailed")
+        );
+        assert_eq!(seen.len(), 6);
+    }
+
+    #[test]
+    fn reports_rejected_sequences() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let log = Rc::new(Log { name: "log", seen: seen.clone() });
+        m.observe(&log);
+
+        m.trigger_all(&["pay", "ship"]).unwrap_err();
+        assert_eq!(
+            *seen.borrow(),
+            ["log: rejected ship in paid: EventInvalid"]
+        );
+    }
+
+    #[test]
+    fn observing_forks_a_shared_definition() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let mut before = m.clone();
+        let log = Rc::new(Log { name: "log", seen: seen.clone() });
+        m.observe(&log);
+        let mut after = m.clone();
+
+        before.trigger(&"pay").unwrap();
+        assert!(seen.borrow().is_empty());
+        after.trigger(&"pay").unwrap();
+        assert_eq!(seen.borrow().len(), 3);
+    }
+
+    #[test]
+    fn dropped_observers_are_skipped() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let log = Rc::new(Log { name: "log", seen: seen.clone() });
+        let id = m.observe(&log);
+        drop(log);
+
+        m.trigger(&"pay").unwrap();
+        assert!(seen.borrow().is_empty());
+        assert!(!m.remove_callback(id));
+    }
+
+    #[test]
+    fn observers_can_be_removed() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let log = Rc::new(Log { name: "log", seen: seen.clone() });
+        let id = m.observe(&log);
+
+        assert!(m.guard_callback(id).is_none());
+        assert!(m.remove_callback(id));
+        m.trigger(&"pay").unwrap();
+        assert!(seen.borrow().is_empty());
+    }
+
+    #[test]
+    fn compiled_machines_notify_observers() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut m = order();
+        let log = Rc::new(Log { name: "log", seen: seen.clone() });
+        m.observe(&log);
+
+        let mut compiled = m.compile();
+        compiled.trigger(&"pay").unwrap();
+        compiled.trigger(&"pay").unwrap_err();
+        assert_eq!(
+            *seen.borrow(),
+            [
+                "log: exit created on pay",
+                "log: enter paid on pay",
+                "log: created -> paid on pay",
+                "log: rejected pay in paid: StateInvalid",
+            ]
+        );
+    }
+}
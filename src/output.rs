@@ -0,0 +1,540 @@
+use alloc::{collections::VecDeque, rc::Rc, vec::Vec};
+use core::{
+    any::Any,
+    borrow::Borrow,
+    fmt::{Debug, Formatter},
+    hash::Hash,
+};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{Machine, Payload, TriggerError};
+
+/// Computes a Mealy output from the source state, the event and the target
+/// state. The target is only used when converting from a [`Moore`] machine.
+type MealyOutput<S, E, O> = Rc<dyn Fn(&S, &E, &S) -> O>;
+
+/// A machine that produces an output on every transition.
+///
+/// The output is a function of the state the machine leaves and the event that
+/// triggered the transition. Triggering an event returns the output instead of
+/// `()`.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Machine, Mealy};
+///
+/// // Detect rising edges in a stream of bits.
+/// let mut nano = Machine::new(false);
+/// nano.when_iter(true, [(false, true), (true, true)]);
+/// nano.when_iter(false, [(false, false), (true, false)]);
+///
+/// let mut edges = Mealy::new(nano, |high: &bool, bit: &bool| !high && *bit);
+///
+/// let outputs: Result<Vec<_>, _> =
+///     edges.outputs([false, true, true, false, true]).collect();
+/// assert_eq!(outputs.unwrap(), [false, true, false, false, true]);
+/// ```
+pub struct Mealy<S, E, O, C = (), P: ?Sized = dyn Any> {
+    machine: Machine<S, E, C, P>,
+    output: MealyOutput<S, E, O>,
+}
+
+/// A machine whose output is determined by the state it is in.
+///
+/// Triggering an event returns the output of the state the machine moved to.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Machine, Moore};
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+/// nano.when("turn knob", "unlocked", "locked");
+///
+/// let mut light = Moore::new(nano, |state: &&str| *state == "unlocked");
+///
+/// assert!(!light.output());
+/// assert!(light.trigger(&"insert coin").unwrap());
+/// assert!(!light.trigger(&"turn knob").unwrap());
+/// ```
+pub struct Moore<S, E, O, C = (), P: ?Sized = dyn Any> {
+    machine: Machine<S, E, C, P>,
+    output: Rc<dyn Fn(&S) -> O>,
+}
+
+/// An iterator that triggers every event of an input stream on an output
+/// machine and yields the resulting outputs.
+///
+/// Created by [`Mealy::outputs`] and [`Moore::outputs`]. Rejected events yield
+/// an error and leave the machine where it was, so the stream can carry on.
+pub struct Outputs<'a, M, I> {
+    machine: &'a mut M,
+    events: I,
+}
+
+impl<S, E, O, C, P: ?Sized> Mealy<S, E, O, C, P> {
+    /// Turn `machine` into a Mealy machine whose output for a transition is
+    /// `output(state, event)`.
+    pub fn new<F>(machine: Machine<S, E, C, P>, output: F) -> Self
+    where
+        F: Fn(&S, &E) -> O + 'static,
+    {
+        let output =
+            Rc::new(move |from: &S, event: &E, _: &S| output(from, event));
+        Mealy { machine, output }
+    }
+
+    /// A reference to the underlying machine.
+    #[inline]
+    pub fn machine(&self) -> &Machine<S, E, C, P> {
+        &self.machine
+    }
+
+    /// A mutable reference to the underlying machine, for defining transitions
+    /// or registering callbacks.
+    #[inline]
+    pub fn machine_mut(&mut self) -> &mut Machine<S, E, C, P> {
+        &mut self.machine
+    }
+
+    /// Discard the output function and return the underlying machine.
+    #[inline]
+    pub fn into_machine(self) -> Machine<S, E, C, P> {
+        self.machine
+    }
+
+    /// A reference to the current state of the machine.
+    #[inline]
+    pub fn state(&self) -> &S {
+        self.machine.state()
+    }
+}
+
+impl<S, E, O, C, P> Mealy<S, E, O, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Trigger `event` without any payload and return the transition's output.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger`].
+    #[inline]
+    pub fn trigger(&mut self, event: &E) -> Result<O, TriggerError<S, E>>
+    where
+        P: Payload<()>,
+    {
+        self.trigger_with(event, &())
+    }
+
+    /// Trigger `event` with an associated payload and return the transition's
+    /// output.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger_with`].
+    pub fn trigger_with<T>(
+        &mut self,
+        event: &E,
+        payload: &T,
+    ) -> Result<O, TriggerError<S, E>>
+    where
+        T: 'static,
+        P: Payload<T>,
+    {
+        let from = self.machine.state().clone();
+        self.machine.trigger_with(event, payload)?;
+        Ok((self.output)(&from, event, self.machine.state()))
+    }
+
+    /// Turn a stream of input events into a stream of outputs.
+    ///
+    /// Each event is triggered without a payload, see [`Mealy::trigger`].
+    pub fn outputs<I>(&mut self, events: I) -> Outputs<'_, Self, I::IntoIter>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<E>,
+    {
+        Outputs { machine: self, events: events.into_iter() }
+    }
+
+    /// Build the equivalent Moore machine.
+    ///
+    /// Each state of the Moore machine pairs a state of this machine with the
+    /// output of the transition that led to it. The starting state has no
+    /// such output, hence the `Option`. Only states reachable from the current
+    /// state are included.
+    ///
+    /// Only the transition structure is carried over: callbacks, guards,
+    /// actions and the context are not.
+    #[must_use]
+    pub fn to_moore(&self) -> Moore<(S, Option<O>), E, Option<O>>
+    where
+        O: Eq + Hash + Clone + 'static,
+    {
+        let mut outgoing: HashMap<&S, Vec<(&E, &S)>> = HashMap::new();
+        for (event, from, to) in self.machine.transitions() {
+            outgoing.entry(from).or_default().push((event, to));
+        }
+
+        let start = (self.machine.state().clone(), None);
+        let mut machine = Machine::new(start.clone());
+        let mut seen = HashSet::new();
+        seen.insert(start.clone());
+        let mut queue = VecDeque::from([start]);
+        while let Some(state) = queue.pop_front() {
+            for &(event, to) in outgoing.get(&state.0).into_iter().flatten() {
+                let output = (self.output)(&state.0, event, to);
+                let next = (to.clone(), Some(output));
+                if seen.insert(next.clone()) {
+                    queue.push_back(next.clone());
+                }
+                machine.when(event.clone(), state.clone(), next);
+            }
+        }
+
+        Moore::new(machine, |(_, output): &(S, Option<O>)| output.clone())
+    }
+}
+
+impl<S, E, O, C, P: ?Sized> Moore<S, E, O, C, P> {
+    /// Turn `machine` into a Moore machine whose output in `state` is
+    /// `output(state)`.
+    pub fn new<F>(machine: Machine<S, E, C, P>, output: F) -> Self
+    where
+        F: Fn(&S) -> O + 'static,
+    {
+        Moore { machine, output: Rc::new(output) }
+    }
+
+    /// The output of the current state.
+    #[inline]
+    pub fn output(&self) -> O {
+        (self.output)(self.machine.state())
+    }
+
+    /// A reference to the underlying machine.
+    #[inline]
+    pub fn machine(&self) -> &Machine<S, E, C, P> {
+        &self.machine
+    }
+
+    /// A mutable reference to the underlying machine, for defining transitions
+    /// or registering callbacks.
+    #[inline]
+    pub fn machine_mut(&mut self) -> &mut Machine<S, E, C, P> {
+        &mut self.machine
+    }
+
+    /// Discard the output function and return the underlying machine.
+    #[inline]
+    pub fn into_machine(self) -> Machine<S, E, C, P> {
+        self.machine
+    }
+
+    /// A reference to the current state of the machine.
+    #[inline]
+    pub fn state(&self) -> &S {
+        self.machine.state()
+    }
+}
+
+impl<S, E, O, C, P> Moore<S, E, O, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Trigger `event` without any payload and return the output of the new
+    /// state.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger`].
+    #[inline]
+    pub fn trigger(&mut self, event: &E) -> Result<O, TriggerError<S, E>>
+    where
+        P: Payload<()>,
+    {
+        self.trigger_with(event, &())
+    }
+
+    /// Trigger `event` with an associated payload and return the output of the
+    /// new state.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Machine::trigger_with`].
+    pub fn trigger_with<T>(
+        &mut self,
+        event: &E,
+        payload: &T,
+    ) -> Result<O, TriggerError<S, E>>
+    where
+        T: 'static,
+        P: Payload<T>,
+    {
+        self.machine.trigger_with(event, payload)?;
+        Ok(self.output())
+    }
+
+    /// Turn a stream of input events into a stream of outputs.
+    ///
+    /// Each event is triggered without a payload, see [`Moore::trigger`]. The
+    /// output of the starting state is not part of the stream; use
+    /// [`Moore::output`] to get it.
+    pub fn outputs<I>(&mut self, events: I) -> Outputs<'_, Self, I::IntoIter>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<E>,
+    {
+        Outputs { machine: self, events: events.into_iter() }
+    }
+}
+
+impl<S, E, O, C, P> From<Moore<S, E, O, C, P>> for Mealy<S, E, O, C, P>
+where
+    S: 'static,
+    O: 'static,
+    P: ?Sized,
+{
+    /// Build the equivalent Mealy machine, whose output for a transition is
+    /// the Moore output of the state it leads to.
+    fn from(moore: Moore<S, E, O, C, P>) -> Self {
+        let output = moore.output;
+        Mealy {
+            machine: moore.machine,
+            output: Rc::new(move |_, _, to| output(to)),
+        }
+    }
+}
+
+impl<S, E, O, C, P, I> Iterator for Outputs<'_, Mealy<S, E, O, C, P>, I>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: Payload<()> + ?Sized,
+    I: Iterator,
+    I::Item: Borrow<E>,
+{
+    type Item = Result<O, TriggerError<S, E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.events.next()?;
+        Some(self.machine.trigger(event.borrow()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl<S, E, O, C, P, I> Iterator for Outputs<'_, Moore<S, E, O, C, P>, I>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: Payload<()> + ?Sized,
+    I: Iterator,
+    I::Item: Borrow<E>,
+{
+    type Item = Result<O, TriggerError<S, E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.events.next()?;
+        Some(self.machine.trigger(event.borrow()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl<S, E, O, C, P> Clone for Mealy<S, E, O, C, P>
+where
+    S: Clone,
+    E: Clone,
+    C: Clone,
+    P: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Mealy { machine: self.machine.clone(), output: self.output.clone() }
+    }
+}
+
+impl<S, E, O, C, P> Clone for Moore<S, E, O, C, P>
+where
+    S: Clone,
+    E: Clone,
+    C: Clone,
+    P: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Moore { machine: self.machine.clone(), output: self.output.clone() }
+    }
+}
+
+impl<S, E, O, C, P> Debug for Mealy<S, E, O, C, P>
+where
+    S: Debug + Eq + Hash + Clone,
+    E: Debug + Eq + Hash + Clone,
+    C: Debug,
+    P: ?Sized,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Mealy")
+            .field("machine", &self.machine)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, E, O, C, P> Debug for Moore<S, E, O, C, P>
+where
+    S: Debug + Eq + Hash + Clone,
+    E: Debug + Eq + Hash + Clone,
+    C: Debug,
+    P: ?Sized,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Moore")
+            .field("machine", &self.machine)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::MachineError;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Light {
+        Red,
+        Green,
+        Yellow,
+    }
+
+    fn traffic_light() -> Machine<Light, &'static str> {
+        let mut m = Machine::new(Light::Red);
+        m.when("tick", Light::Red, Light::Green);
+        m.when("tick", Light::Green, Light::Yellow);
+        m.when("tick", Light::Yellow, Light::Red);
+        m.when("emergency", Light::Green, Light::Red);
+        m.when("emergency", Light::Yellow, Light::Red);
+        m
+    }
+
+    fn duration(light: &Light) -> u32 {
+        match light {
+            Light::Red => 30,
+            Light::Green => 25,
+            Light::Yellow => 5,
+        }
+    }
+
+    #[test]
+    fn mealy_outputs_depend_on_source_and_event() {
+        let mut m =
+            Mealy::new(traffic_light(), |from: &Light, event: &&str| {
+                (from.clone(), *event == "emergency")
+            });
+
+        assert_eq!(m.trigger(&"tick").unwrap(), (Light::Red, false));
+        assert_eq!(m.trigger(&"emergency").unwrap(), (Light::Green, true));
+        assert_eq!(*m.state(), Light::Red);
+    }
+
+    #[test]
+    fn mealy_rejects_invalid_events() {
+        let mut m = Mealy::new(traffic_light(), |_: &Light, _: &&str| ());
+        let err = m.trigger(&"emergency").unwrap_err();
+        assert_eq!(err, MachineError::StateInvalid);
+        assert_eq!(*m.state(), Light::Red);
+    }
+
+    #[test]
+    fn moore_outputs_depend_on_target() {
+        let mut m = Moore::new(traffic_light(), duration);
+
+        assert_eq!(m.output(), 30);
+        assert_eq!(m.trigger(&"tick").unwrap(), 25);
+        assert_eq!(m.trigger(&"tick").unwrap(), 5);
+        assert_eq!(m.output(), 5);
+    }
+
+    #[test]
+    fn outputs_adapter() {
+        let mut m = Moore::new(traffic_light(), duration);
+        let outputs: Vec<_> = m
+            .outputs(["tick", "tick", "nope", "emergency"])
+            .map(|o| o.map_err(MachineError::from))
+            .collect();
+
+        assert_eq!(
+            outputs,
+            [Ok(25), Ok(5), Err(MachineError::EventInvalid), Ok(30)]
+        );
+        assert_eq!(*m.state(), Light::Red);
+    }
+
+    #[test]
+    fn outputs_adapter_accepts_references() {
+        let events = ["tick", "tick"];
+        let mut m =
+            Mealy::new(traffic_light(), |from: &Light, _: &&str| from.clone());
+        let outputs: Vec<_> = m.outputs(&events).map(Result::unwrap).collect();
+        assert_eq!(outputs, [Light::Red, Light::Green]);
+    }
+
+    #[test]
+    fn moore_to_mealy() {
+        let events = ["tick", "emergency", "tick", "tick", "tick", "emergency"];
+        let mut moore = Moore::new(traffic_light(), duration);
+        let mut mealy = Mealy::from(moore.clone());
+
+        let expected: Vec<_> = moore.outputs(&events).collect();
+        let outputs: Vec<_> = mealy.outputs(&events).collect();
+        assert_eq!(outputs, expected);
+        assert_eq!(mealy.state(), moore.state());
+    }
+
+    #[test]
+    fn mealy_to_moore() {
+        let events = ["tick", "emergency", "tick", "tick", "emergency", "tick"];
+        let mut mealy =
+            Mealy::new(traffic_light(), |from: &Light, event: &&str| {
+                (from.clone(), *event == "emergency")
+            });
+        let mut moore = mealy.to_moore();
+        assert_eq!(moore.output(), None);
+
+        for event in events {
+            let expected = mealy.trigger(&event).unwrap();
+            assert_eq!(moore.trigger(&event).unwrap(), Some(expected));
+            assert_eq!(moore.state().0, *mealy.state());
+        }
+    }
+
+    #[test]
+    fn mealy_to_moore_only_keeps_reachable_states() {
+        let mut m = traffic_light();
+        m.trigger(&"tick").unwrap();
+        m.when("reset", Light::Red, Light::Red);
+
+        // From Green, Red is reachable again, so every transition is kept, but
+        // only pairs that can actually occur become states.
+        let moore =
+            Mealy::new(m, |from: &Light, _: &&str| from.clone()).to_moore();
+        let states: HashSet<_> = moore.machine().states().cloned().collect();
+        assert!(states.contains(&(Light::Green, None)));
+        assert!(states.contains(&(Light::Red, Some(Light::Red))));
+        assert!(!states.contains(&(Light::Red, None)));
+        assert!(!states.contains(&(Light::Green, Some(Light::Green))));
+    }
+}
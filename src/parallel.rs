@@ -0,0 +1,530 @@
+use alloc::vec::Vec;
+use core::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    hash::Hash,
+};
+
+use crate::{Machine, MachineError, Payload, TriggerError, language::explore};
+
+/// Two machines running side by side, composed in the style of CSP.
+///
+/// Events that both machines define are shared: they are only accepted when
+/// both machines accept them, and then both take their transition. Every other
+/// event is private to the machine that defines it, which takes it on its own.
+///
+/// Which events are shared is worked out on every trigger, so the machines can
+/// still be changed through [`Parallel::left_mut`] and
+/// [`Parallel::right_mut`]. [`Machine::parallel`] builds the same composition
+/// as a single machine instead.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Machine, Parallel};
+///
+/// let mut door = Machine::new("closed");
+/// door.when("open", "closed", "open");
+/// door.when("close", "open", "closed");
+/// door.when("lock", "closed", "closed");
+///
+/// let mut lock = Machine::new("unlocked");
+/// lock.when("lock", "unlocked", "locked");
+/// lock.when("unlock", "locked", "unlocked");
+/// lock.when("open", "unlocked", "unlocked");
+///
+/// let mut both = Parallel::new(door, lock);
+///
+/// both.trigger(&"lock").unwrap();
+/// assert_eq!(both.state(), (&"closed", &"locked"));
+///
+/// // The lock refuses to open.
+/// assert!(both.trigger(&"open").is_err());
+///
+/// both.trigger(&"unlock").unwrap();
+/// both.trigger(&"open").unwrap();
+/// assert_eq!(both.state(), (&"open", &"unlocked"));
+/// ```
+pub struct Parallel<S, T, E, C = (), D = (), P: ?Sized = dyn Any> {
+    left: Machine<S, E, C, P>,
+    right: Machine<T, E, D, P>,
+}
+
+impl<S, T, E, C, D, P: ?Sized> Parallel<S, T, E, C, D, P> {
+    /// Run `left` and `right` side by side.
+    pub fn new(left: Machine<S, E, C, P>, right: Machine<T, E, D, P>) -> Self {
+        Parallel { left, right }
+    }
+
+    /// A reference to the left machine.
+    #[inline]
+    pub fn left(&self) -> &Machine<S, E, C, P> {
+        &self.left
+    }
+
+    /// A mutable reference to the left machine.
+    #[inline]
+    pub fn left_mut(&mut self) -> &mut Machine<S, E, C, P> {
+        &mut self.left
+    }
+
+    /// A reference to the right machine.
+    #[inline]
+    pub fn right(&self) -> &Machine<T, E, D, P> {
+        &self.right
+    }
+
+    /// A mutable reference to the right machine.
+    #[inline]
+    pub fn right_mut(&mut self) -> &mut Machine<T, E, D, P> {
+        &mut self.right
+    }
+
+    /// The current states of both machines.
+    #[inline]
+    pub fn state(&self) -> (&S, &T) {
+        (&self.left.state, &self.right.state)
+    }
+}
+
+impl<S, T, E, C, D, P> Parallel<S, T, E, C, D, P>
+where
+    S: Eq + Hash + Clone,
+    T: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Returns an iterator over all events either machine can react to.
+    pub fn events(&self) -> impl Iterator<Item = &E> {
+        let right = self.right.events();
+        let private = right
+            .filter(|e| !self.left.definition.transitions.contains_key(*e));
+        self.left.events().chain(private)
+    }
+
+    /// Whether both machines define `event`, so that they must take it
+    /// together.
+    #[inline]
+    pub fn is_shared(&self, event: &E) -> bool {
+        self.left.definition.transitions.contains_key(event)
+            && self.right.definition.transitions.contains_key(event)
+    }
+
+    /// Trigger the given `event` without any payload.
+    ///
+    /// See [`Parallel::trigger_with`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Parallel::trigger_with`].
+    #[inline]
+    pub fn trigger(&mut self, event: &E) -> Result<(), TriggerError<(S, T), E>>
+    where
+        C: Clone,
+        D: Clone,
+        P: Payload<()>,
+    {
+        self.trigger_with(event, &())
+    }
+
+    /// Trigger the given `event` with an associated payload.
+    ///
+    /// A shared event is first checked against both machines, and only fired
+    /// if both accept it, left first. A private event is fired on the machine
+    /// that defines it. Either way, a rejection is reported to the observers,
+    /// metrics and `tracing` or `log` of the machine that rejected the event.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TriggerError`] reporting the states of both machines, whose
+    /// [`error`](TriggerError::error) is the one returned by the machine that
+    /// rejected the event. It is [`MachineError::EventInvalid`] if neither
+    /// machine defines the event.
+    ///
+    /// If a fallible callback fails while handling a shared event, both
+    /// machines' states and contexts are restored, as with
+    /// [`Machine::try_trigger`], and neither counts the event in its coverage
+    /// or metrics as taken. Side effects of callbacks that already ran, on
+    /// either machine, are not undone.
+    pub fn trigger_with<X>(
+        &mut self,
+        event: &E,
+        payload: &X,
+    ) -> Result<(), TriggerError<(S, T), E>>
+    where
+        X: 'static,
+        C: Clone,
+        D: Clone,
+        P: Payload<X>,
+    {
+        self.step(event, P::wrap(payload)).map_err(|error| {
+            let state = (self.left.state.clone(), self.right.state.clone());
+            TriggerError::new(error, event.clone(), state, None)
+        })
+    }
+
+    /// Fire `event` on the machines that define it.
+    fn step(&mut self, event: &E, payload: &P) -> Result<(), MachineError>
+    where
+        C: Clone,
+        D: Clone,
+    {
+        let left = self.left.definition.transitions.contains_key(event);
+        let right = self.right.definition.transitions.contains_key(event);
+        if left && right {
+            self.left
+                .resolve(event)
+                .inspect_err(|err| self.left.reject(event, err))?;
+            self.right
+                .resolve(event)
+                .inspect_err(|err| self.right.reject(event, err))?;
+            return self.step_both(event, payload);
+        }
+        // When neither machine defines the event, the left one reports it.
+        if left || !right {
+            self.left.fire(event, payload)?;
+        }
+        if right {
+            self.right.fire(event, payload)?;
+        }
+        Ok(())
+    }
+
+    /// Fire a shared `event` on both machines as one step, restoring both if
+    /// either fails.
+    ///
+    /// The left machine only reports its transition to coverage and metrics
+    /// once the right one took the event too.
+    fn step_both(&mut self, event: &E, payload: &P) -> Result<(), MachineError>
+    where
+        C: Clone,
+        D: Clone,
+    {
+        let left = (self.left.state.clone(), self.left.context.clone());
+        let mut taken = None;
+        let restore = || left.clone();
+        let result = self.left.fire_unreported(
+            &mut taken,
+            event,
+            payload,
+            Some(restore),
+        );
+        if result.is_ok() {
+            let right = (self.right.state.clone(), self.right.context.clone());
+            let restore = || right;
+            if let Err(err) =
+                self.right.fire_or_restore(event, payload, Some(restore))
+            {
+                (self.left.state, self.left.context) = left;
+                return Err(err.into());
+            }
+        }
+        self.left.report(event, taken, &result);
+        Ok(result?)
+    }
+}
+
+impl<S, T, E, C, D, P> Clone for Parallel<S, T, E, C, D, P>
+where
+    S: Clone,
+    T: Clone,
+    E: Clone,
+    C: Clone,
+    D: Clone,
+    P: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Parallel { left: self.left.clone(), right: self.right.clone() }
+    }
+}
+
+impl<S, T, E, C, D, P> Debug for Parallel<S, T, E, C, D, P>
+where
+    S: Debug + Eq + Hash + Clone,
+    T: Debug + Eq + Hash + Clone,
+    E: Debug + Eq + Hash + Clone,
+    C: Debug,
+    D: Debug,
+    P: ?Sized,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Parallel")
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl<S, E, C, P> Machine<S, E, C, P>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone,
+    P: ?Sized,
+{
+    /// Build the parallel composition of `self` and `other` as a single
+    /// machine.
+    ///
+    /// Its states pair up a state of each machine, starting from their current
+    /// states, and only the reachable pairs are built. Events defined by both
+    /// machines move both at once, every other event moves only the machine
+    /// that defines it, as with [`Parallel`]. A pair is accepting when both of
+    /// its states are.
+    ///
+    /// Like the other operations treating machines as automata, this only
+    /// looks at transitions and accepting states: guards, actions and
+    /// callbacks are not carried over.
+    pub fn parallel<T, C2, P2>(
+        &self,
+        other: &Machine<T, E, C2, P2>,
+    ) -> Machine<(S, T), E>
+    where
+        T: Eq + Hash + Clone,
+        P2: ?Sized,
+    {
+        let mut events: Vec<&E> = self.events().collect();
+        events.extend(
+            other
+                .events()
+                .filter(|e| !self.definition.transitions.contains_key(*e)),
+        );
+        explore(
+            (self.state.clone(), other.state.clone()),
+            &events,
+            |(s, t), event| {
+                let left = self.definition.transitions.contains_key(event);
+                let right = other.definition.transitions.contains_key(event);
+                let s = if left { self.target(event, s)? } else { s };
+                let t = if right { other.target(event, t)? } else { t };
+                Some((s.clone(), t.clone()))
+            },
+            |(s, t)| self.is_accepting(s) && other.is_accepting(t),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    use super::*;
+    use crate::{MachineObserver, testing::words};
+
+    type Door = Machine<&'static str, &'static str>;
+
+    /// The door can only be locked while closed.
+    fn door() -> Door {
+        let mut door = Machine::new("closed");
+        door.when("open", "closed", "open");
+        door.when("close", "open", "closed");
+        door.when("lock", "closed", "closed");
+        door.set_accepting("closed");
+        door
+    }
+
+    /// The lock can only be opened while unlocked.
+    fn lock() -> Door {
+        let mut lock = Machine::new("unlocked");
+        lock.when("lock", "unlocked", "locked");
+        lock.when("unlock", "locked", "unlocked");
+        lock.when("open", "unlocked", "unlocked");
+        lock.set_accepting("locked");
+        lock
+    }
+
+    const EVENTS: [&str; 5] = ["open", "close", "lock", "unlock", "knock"];
+
+    #[test]
+    fn shared_events_move_both() {
+        let mut both = Parallel::new(door(), lock());
+
+        assert!(both.is_shared(&"lock"));
+        assert!(both.is_shared(&"open"));
+        assert!(!both.is_shared(&"close"));
+
+        both.trigger(&"lock").unwrap();
+        assert_eq!(both.state(), (&"closed", &"locked"));
+    }
+
+    #[test]
+    fn shared_events_need_both() {
+        let mut both = Parallel::new(door(), lock());
+        both.trigger(&"lock").unwrap();
+
+        let err = both.trigger(&"open").unwrap_err();
+        assert_eq!(err, MachineError::StateInvalid);
+        assert_eq!(*err.state(), ("closed", "locked"));
+        assert_eq!(both.state(), (&"closed", &"locked"));
+
+        both.trigger(&"unlock").unwrap();
+        both.trigger(&"open").unwrap();
+
+        let err = both.trigger(&"lock").unwrap_err();
+        assert_eq!(err, MachineError::StateInvalid);
+        assert_eq!(both.state(), (&"open", &"unlocked"));
+    }
+
+    #[test]
+    fn private_events_interleave() {
+        let mut both = Parallel::new(door(), lock());
+
+        both.trigger(&"open").unwrap();
+        both.trigger(&"close").unwrap();
+        assert_eq!(both.state(), (&"closed", &"unlocked"));
+
+        both.trigger(&"lock").unwrap();
+        both.trigger(&"unlock").unwrap();
+        assert_eq!(both.state(), (&"closed", &"unlocked"));
+    }
+
+    #[test]
+    fn unknown_events() {
+        let mut both = Parallel::new(door(), lock());
+
+        let err = both.trigger(&"knock").unwrap_err();
+        assert_eq!(err, MachineError::EventInvalid);
+        assert_eq!(*err.event(), "knock");
+    }
+
+    #[test]
+    fn events() {
+        let both = Parallel::new(door(), lock());
+
+        let mut events: Vec<_> = both.events().copied().collect();
+        events.sort_unstable();
+        assert_eq!(events, ["close", "lock", "open", "unlock"]);
+    }
+
+    #[test]
+    fn callbacks_fire_on_both() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let (mut door, mut lock) = (door(), lock());
+        let l = log.clone();
+        door.on_transition(move |e| l.borrow_mut().push(("door", e)));
+        let l = log.clone();
+        lock.on_transition(move |e| l.borrow_mut().push(("lock", e)));
+        let mut both = Parallel::new(door, lock);
+
+        both.trigger(&"lock").unwrap();
+        both.trigger(&"unlock").unwrap();
+        assert!(both.trigger(&"close").is_err());
+
+        assert_eq!(
+            *log.borrow(),
+            [("door", "lock"), ("lock", "lock"), ("lock", "unlock")]
+        );
+    }
+
+    #[test]
+    fn guards_are_checked_before_firing() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut door = door();
+        let l = log.clone();
+        door.on_transition(move |e| l.borrow_mut().push(e));
+        let mut lock: Machine<_, _, bool> =
+            Machine::with_context("unlocked", false);
+        lock.when_guarded("lock", "unlocked", "locked", |jammed| !jammed);
+        let mut both = Parallel::new(door, lock);
+
+        *both.right_mut().context_mut() = true;
+        let err = both.trigger(&"lock").unwrap_err();
+        assert_eq!(err, MachineError::GuardRejected);
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn shared_steps_are_atomic() {
+        let mut door: Machine<_, _, u32> = Machine::with_context("closed", 0);
+        door.when("lock", "closed", "closed");
+        door.on_transition_ctx(|_, (): &(), turns| *turns += 1);
+        door.record_coverage();
+        let mut lock = lock();
+        lock.try_on_enter_with("locked", |_, (): &()| Err("jammed".into()));
+        let mut both = Parallel::new(door, lock);
+
+        let err = both.trigger(&"lock").unwrap_err();
+        assert!(matches!(err.error(), MachineError::CallbackFailed(_)));
+        assert_eq!(both.state(), (&"closed", &"unlocked"));
+        assert_eq!(*both.left().context(), 0);
+        assert_eq!(both.left().coverage().unwrap().edges().count(), 0);
+    }
+
+    #[test]
+    fn shared_rejections_are_reported() {
+        struct Rejections(RefCell<Vec<(&'static str, MachineError)>>);
+
+        impl MachineObserver<&'static str, &'static str> for Rejections {
+            fn on_rejected(
+                &self,
+                _state: &&'static str,
+                event: &&'static str,
+                error: &MachineError,
+            ) {
+                self.0.borrow_mut().push((*event, error.clone()));
+            }
+        }
+
+        let (mut door, mut lock) = (door(), lock());
+        let on_door = Rc::new(Rejections(RefCell::new(Vec::new())));
+        let on_lock = Rc::new(Rejections(RefCell::new(Vec::new())));
+        door.observe(&on_door);
+        lock.observe(&on_lock);
+        let mut both = Parallel::new(door, lock);
+
+        both.trigger(&"lock").unwrap();
+        both.trigger(&"open").unwrap_err();
+        both.trigger(&"lock").unwrap_err();
+
+        assert!(on_door.0.borrow().is_empty());
+        assert_eq!(
+            *on_lock.0.borrow(),
+            [
+                ("open", MachineError::StateInvalid),
+                ("lock", MachineError::StateInvalid)
+            ]
+        );
+    }
+
+    #[test]
+    fn product_matches_runtime() {
+        let (door, lock) = (door(), lock());
+        let product = door.parallel(&lock);
+
+        for sequence in words(&EVENTS, 5) {
+            let mut runtime = Parallel::new(door.clone(), lock.clone());
+            let mut machine = product.clone();
+            for event in &sequence {
+                let expected = runtime.trigger(event).is_ok();
+                assert_eq!(machine.trigger(event).is_ok(), expected);
+                let (s, t) = runtime.state();
+                assert_eq!(*machine.state(), (*s, *t), "{sequence:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn product_accepts_when_both_accept() {
+        let product = door().parallel(&lock());
+
+        assert!(!product.accepts([] as [&str; 0]));
+        assert!(product.accepts(["lock"]));
+        assert!(!product.accepts(["lock", "unlock"]));
+        assert!(product.accepts(["open", "close", "lock"]));
+    }
+
+    #[test]
+    fn product_only_builds_reachable_pairs() {
+        let product = door().parallel(&lock());
+
+        let mut states: Vec<_> = product.states().copied().collect();
+        states.sort_unstable();
+        assert_eq!(
+            states,
+            [
+                ("closed", "locked"),
+                ("closed", "unlocked"),
+                ("open", "unlocked")
+            ]
+        );
+    }
+}
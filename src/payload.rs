@@ -0,0 +1,48 @@
+use core::any::Any;
+
+/// How a machine hands payloads of type `T` to its callbacks.
+///
+/// A [`Machine`] stores its callbacks as taking a `&P`, where `P` is the
+/// machine's payload type. Triggering with a `&T` requires `P: Payload<T>` to
+/// turn the `&T` into a `&P`, and registering a callback for `&T` requires it
+/// to turn the `&P` back into a `&T`.
+///
+/// Two implementations are provided:
+///
+/// - `dyn Any`, the default, accepts payloads of any `'static` type. Callbacks
+///   only run when the payload downcasts to the type they expect.
+/// - Every sized type `T` accepts exactly `T`, so a mismatched callback or
+///   payload is a compile error rather than a silently skipped callback.
+///
+/// [`Machine`]: crate::Machine
+pub trait Payload<T> {
+    /// View the payload as a `T`, if it is one.
+    fn cast(&self) -> Option<&T>;
+
+    /// View a `T` as a payload.
+    fn wrap(payload: &T) -> &Self;
+}
+
+impl<T: 'static> Payload<T> for dyn Any {
+    #[inline]
+    fn cast(&self) -> Option<&T> {
+        self.downcast_ref()
+    }
+
+    #[inline]
+    fn wrap(payload: &T) -> &Self {
+        payload
+    }
+}
+
+impl<T> Payload<T> for T {
+    #[inline]
+    fn cast(&self) -> Option<&T> {
+        Some(self)
+    }
+
+    #[inline]
+    fn wrap(payload: &T) -> &Self {
+        payload
+    }
+}
@@ -0,0 +1,127 @@
+//! [`proptest`] strategies generating event sequences.
+//!
+//! Available with the `proptest` feature.
+
+use alloc::vec::Vec;
+use core::{fmt::Debug, hash::Hash};
+
+use ::proptest::{collection, prelude::any, strategy::Strategy};
+
+use crate::{Machine, walk::Sequences};
+
+/// A strategy generating sequences of at most `max_len` events that are valid
+/// for `machine`, starting from its current state.
+///
+/// Sequences only follow the transitions of the machine: guards are not
+/// evaluated, and nothing runs on `machine` itself. A sequence stops early if
+/// it reaches a state without transitions.
+///
+/// Sequences shrink towards shorter ones and towards the smallest event at
+/// every step, and stay valid while shrinking, so a failing test reports a
+/// minimal valid trace.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::Machine;
+/// use proptest::{prelude::*, test_runner::TestRunner};
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+/// nano.when("turn knob", "unlocked", "locked");
+///
+/// let strategy = nanomachine::proptest::sequences(&nano, 20);
+/// TestRunner::default()
+///     .run(&strategy, |events| {
+///         let mut nano = nano.clone();
+///         for event in &events {
+///             prop_assert!(nano.trigger(event).is_ok());
+///         }
+///         Ok(())
+///     })
+///     .unwrap();
+/// ```
+pub fn sequences<S, E, C, P>(
+    machine: &Machine<S, E, C, P>,
+    max_len: usize,
+) -> impl Strategy<Value = Vec<E>> + use<S, E, C, P>
+where
+    S: Eq + Hash + Clone + Debug,
+    E: Eq + Hash + Clone + Ord + Debug,
+    P: ?Sized,
+{
+    let sequences = Sequences::new(machine);
+    collection::vec(any::<u64>(), 0..=max_len)
+        .prop_map(move |choices| sequences.replay(choices))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, vec::Vec};
+
+    use ::proptest::{
+        prop_assert,
+        strategy::{Strategy, ValueTree},
+        test_runner::{TestCaseError, TestError, TestRunner},
+    };
+
+    use super::sequences;
+    use crate::testing::turnstile;
+
+    #[test]
+    fn sequences_are_valid() {
+        let nano = turnstile();
+
+        TestRunner::default()
+            .run(&sequences(&nano, 30), |events| {
+                prop_assert!(events.len() <= 30);
+                let mut nano = nano.clone();
+                for event in &events {
+                    prop_assert!(nano.trigger(event).is_ok());
+                }
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn sequences_shrink_to_minimal_traces() {
+        let nano = turnstile();
+
+        // Fails as soon as the knob is turned twice.
+        let result =
+            TestRunner::default().run(&sequences(&nano, 30), |events| {
+                let turns =
+                    events.iter().filter(|e| **e == "turn knob").count();
+                if turns >= 2 {
+                    return Err(TestCaseError::fail(format!("{events:?}")));
+                }
+                Ok(())
+            });
+
+        let Err(TestError::Fail(_, events)) = result else {
+            panic!("expected a failure, got {result:?}");
+        };
+        assert_eq!(
+            events,
+            ["insert coin", "turn knob", "insert coin", "turn knob"]
+        );
+    }
+
+    #[test]
+    fn sequences_stay_valid_while_shrinking() {
+        let nano = turnstile();
+        let strategy = sequences(&nano, 30);
+        let mut runner = TestRunner::deterministic();
+
+        let mut tree = strategy.new_tree(&mut runner).unwrap();
+        let mut seen: Vec<Vec<&str>> = Vec::new();
+        while tree.simplify() {
+            seen.push(tree.current());
+        }
+        for events in seen {
+            let mut nano = nano.clone();
+            assert!(events.iter().all(|event| nano.trigger(event).is_ok()));
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use alloc::{vec, vec::Vec};
+
+use crate::Machine;
+
+/// Every sequence over `alphabet` of length at most `len`, shortest first.
+pub(crate) fn words<T: Clone>(alphabet: &[T], len: usize) -> Vec<Vec<T>> {
+    let mut all = vec![Vec::new()];
+    let mut last = vec![Vec::new()];
+    for _ in 0..len {
+        let mut next = Vec::new();
+        for word in &last {
+            for c in alphabet {
+                let mut longer: Vec<T> = word.clone();
+                longer.push(c.clone());
+                next.push(longer);
+            }
+        }
+        all.extend(next.iter().cloned());
+        last = next;
+    }
+    all
+}
+
+/// A turnstile: a coin unlocks it, turning the knob locks it again, and
+/// kicking it while locked does nothing.
+pub(crate) fn turnstile() -> Machine<&'static str, &'static str> {
+    turnstile_with_context(())
+}
+
+/// A [`turnstile`] owning `context`.
+pub(crate) fn turnstile_with_context<C>(
+    context: C,
+) -> Machine<&'static str, &'static str, C> {
+    let mut nano = Machine::with_context("locked", context);
+    nano.when("insert coin", "locked", "unlocked");
+    nano.when("turn knob", "unlocked", "locked");
+    nano.when("kick", "locked", "locked");
+    nano
+}
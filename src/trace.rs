@@ -0,0 +1,413 @@
+use core::fmt::{self, Debug, Display, Formatter};
+
+use crate::{MachineError, TriggerError};
+
+/// The target of every span, event and log record emitted by a machine.
+const TARGET: &str = "nanomachine";
+
+/// A function formatting a `T` in what a machine reports.
+type Fmt<T> = fn(&T, &mut Formatter<'_>) -> fmt::Result;
+
+/// How a machine formats its states and events for `tracing` and `log`.
+///
+/// Machines don't require `Debug` states and events, so by default they are
+/// left out of what is reported. The `Debug` formatters are captured by
+/// [`Definition::instrument`](crate::Definition::instrument), which requires
+/// them.
+pub(crate) struct Tracer<S, E> {
+    state: Option<Fmt<S>>,
+    event: Option<Fmt<E>>,
+}
+
+impl<S, E> Clone for Tracer<S, E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S, E> Copy for Tracer<S, E> {}
+
+impl<S, E> Default for Tracer<S, E> {
+    fn default() -> Self {
+        Tracer { state: None, event: None }
+    }
+}
+
+impl<S: Debug, E: Debug> Tracer<S, E> {
+    pub(crate) fn debug() -> Self {
+        Tracer {
+            state: Some(<S as Debug>::fmt),
+            event: Some(<E as Debug>::fmt),
+        }
+    }
+}
+
+impl<S, E> Tracer<S, E> {
+    /// Open the span callbacks run in while triggering `event` from `from`.
+    ///
+    /// Without the `tracing` feature, there is no span.
+    #[cfg_attr(
+        not(feature = "tracing"),
+        allow(unused_variables, clippy::unused_self)
+    )]
+    pub(crate) fn span(&self, from: &S, event: &E) -> Span {
+        Span(
+            #[cfg(feature = "tracing")]
+            tracing::debug_span!(
+                target: TARGET,
+                "trigger",
+                from = self.state(from).map(tracing::field::display),
+                event = self.event(event).map(tracing::field::display),
+            ),
+        )
+    }
+
+    /// Report that triggering `event` failed with `err`, without running
+    /// anything.
+    pub(crate) fn reject(&self, event: &E, err: &TriggerError<S, E>) {
+        self.record(err.state(), err.state(), event, Some(err.error()));
+    }
+
+    /// Emit the outcome of triggering `event` from `from`, which left the
+    /// machine in `to`, failing with `error` if given.
+    pub(crate) fn record(
+        &self,
+        from: &S,
+        to: &S,
+        event: &E,
+        error: Option<&MachineError>,
+    ) {
+        let from = self.state(from);
+        let to = self.state(to);
+        let event = self.event(event);
+        let name = error.map(MachineError::name);
+
+        macro_rules! emit {
+            ($level:ident, $outcome:literal) => {{
+                #[cfg(feature = "tracing")]
+                tracing::$level!(
+                    target: TARGET,
+                    from = from.as_ref().map(tracing::field::display),
+                    to = to.as_ref().map(tracing::field::display),
+                    event = event.as_ref().map(tracing::field::display),
+                    outcome = $outcome,
+                    error = name,
+                    "trigger",
+                );
+                #[cfg(feature = "log")]
+                log::$level!(
+                    target: TARGET,
+                    "trigger{}{}{} outcome={}{}",
+                    Field("from", from.as_ref()),
+                    Field("to", to.as_ref()),
+                    Field("event", event.as_ref()),
+                    $outcome,
+                    Field("error", name),
+                );
+            }};
+        }
+        // The caller gets the error back, so neither outcome is reported
+        // above `info`.
+        match error {
+            None => emit!(debug, "ok"),
+            Some(MachineError::CallbackFailed(_)) => emit!(info, "failed"),
+            Some(_) => emit!(debug, "rejected"),
+        }
+    }
+
+    /// `state` formatted for reporting, if the machine is instrumented.
+    fn state<'a>(&self, state: &'a S) -> Option<Show<'a, S>> {
+        self.state.map(|fmt| Show(state, fmt))
+    }
+
+    /// `event` formatted for reporting, if the machine is instrumented.
+    fn event<'a>(&self, event: &'a E) -> Option<Show<'a, E>> {
+        self.event.map(|fmt| Show(event, fmt))
+    }
+}
+
+/// The span of a trigger, if the `tracing` feature is on.
+pub(crate) struct Span(#[cfg(feature = "tracing")] tracing::Span);
+
+impl Span {
+    /// Run `f` inside the span.
+    #[cfg_attr(not(feature = "tracing"), allow(clippy::unused_self))]
+    pub(crate) fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "tracing")]
+        return self.0.in_scope(f);
+        #[cfg(not(feature = "tracing"))]
+        f()
+    }
+}
+
+/// Displays a value with the formatter a [`Tracer`] picked for it.
+struct Show<'a, T>(&'a T, Fmt<T>);
+
+impl<T> Display for Show<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.1)(self.0, f)
+    }
+}
+
+/// Displays a field of a log record, if it has a value.
+#[cfg_attr(not(feature = "log"), allow(dead_code))]
+struct Field<T>(&'static str, Option<T>);
+
+impl<T: Display> Display for Field<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.1 {
+            Some(value) => write!(f, " {}={value}", self.0),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::{format, string::String, vec::Vec};
+    use core::cell::RefCell;
+    use std::thread_local;
+
+    use crate::Machine;
+
+    thread_local! {
+        /// What the current test emitted, one line per record.
+        static SEEN: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn push(line: String) {
+        SEEN.with(|seen| seen.borrow_mut().push(line));
+    }
+
+    /// Drain what was emitted, keeping the lines pushed by `source` without
+    /// the tag naming it.
+    fn take(source: &str) -> Vec<String> {
+        SEEN.with(RefCell::take)
+            .iter()
+            .filter_map(|line| line.strip_prefix(source)?.strip_prefix(' '))
+            .map(String::from)
+            .collect()
+    }
+
+    fn order() -> Machine<&'static str, &'static str> {
+        let mut m = Machine::new("created");
+        m.when("pay", "created", "paid");
+        m.when_guarded("ship", "paid", "shipped", |()| false);
+        m.try_on_enter_with("refunded", |_, (): &()| Err("down".into()));
+        m.when("refund", "paid", "refunded");
+        m
+    }
+
+    /// Install a global `tracing` subscriber writing to [`SEEN`].
+    #[cfg(feature = "tracing")]
+    fn subscribe() {
+        use core::fmt::{Debug, Write};
+
+        use tracing::{
+            Event, Id, Metadata, Subscriber,
+            field::{Field, Visit},
+            span::{Attributes, Record},
+            subscriber::Interest,
+        };
+
+        struct Line(String);
+
+        impl Visit for Line {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                let _ = write!(self.0, " {}={value:?}", field.name());
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                let _ = write!(self.0, " {}={value}", field.name());
+            }
+        }
+
+        struct Capture;
+
+        impl Subscriber for Capture {
+            fn register_callsite(&self, _: &Metadata<'_>) -> Interest {
+                Interest::always()
+            }
+
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut line =
+                    Line(format!("tracing span {}", span.metadata().name()));
+                span.record(&mut line);
+                push(line.0);
+                Id::from_u64(1)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let metadata = event.metadata();
+                let mut line = Line(format!(
+                    "tracing {} {}",
+                    metadata.level(),
+                    metadata.target()
+                ));
+                event.record(&mut line);
+                push(line.0);
+            }
+
+            fn enter(&self, _: &Id) {
+                push("tracing enter".into());
+            }
+
+            fn exit(&self, _: &Id) {
+                push("tracing exit".into());
+            }
+        }
+
+        let _ = tracing::subscriber::set_global_default(Capture);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_events_and_spans() {
+        subscribe();
+        let mut m = order();
+        m.on_enter("paid", |_| push("tracing callback".into()));
+        m.trigger(&"pay").unwrap();
+        assert_eq!(
+            take("tracing"),
+            [
+                "span trigger",
+                "enter",
+                "callback",
+                "exit",
+                "DEBUG nanomachine message=trigger outcome=ok",
+            ]
+        );
+
+        let mut m = order();
+        m.on_enter("paid", |_| push("tracing callback".into()));
+        m.instrument();
+        m.trigger(&"pay").unwrap();
+        m.trigger(&"ship").unwrap_err();
+        m.trigger(&"refund").unwrap_err();
+        m.trigger(&"cancel").unwrap_err();
+
+        let events: Vec<String> = take("tracing")
+            .into_iter()
+            .filter(|line| !matches!(&line[..], "enter" | "exit"))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "span trigger from=\"created\" event=\"pay\"",
+                "callback",
+                "DEBUG nanomachine message=trigger from=\"created\" \
+                 to=\"paid\" event=\"pay\" outcome=ok",
+                "span trigger from=\"paid\" event=\"ship\"",
+                "DEBUG nanomachine message=trigger from=\"paid\" to=\"paid\" \
+                 event=\"ship\" outcome=rejected error=GuardRejected",
+                "span trigger from=\"paid\" event=\"refund\"",
+                "INFO nanomachine message=trigger from=\"paid\" \
+                 to=\"refunded\" event=\"refund\" outcome=failed \
+                 error=CallbackFailed",
+                "span trigger from=\"refunded\" event=\"cancel\"",
+                "DEBUG nanomachine message=trigger from=\"refunded\" \
+                 to=\"refunded\" event=\"cancel\" outcome=rejected \
+                 error=EventInvalid",
+            ]
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn callbacks_run_inside_the_span() {
+        subscribe();
+        let mut m = order();
+        m.on_enter("paid", |_| push("tracing callback".into()));
+        m.instrument();
+        m.trigger(&"pay").unwrap();
+        assert_eq!(take("tracing")[1..4], ["enter", "callback", "exit"]);
+
+        let mut compiled = m.compile();
+        compiled.trigger(&"refund").unwrap_err();
+        assert_eq!(
+            take("tracing"),
+            [
+                "span trigger from=\"paid\" event=\"refund\"",
+                "enter",
+                "exit",
+                "INFO nanomachine message=trigger from=\"paid\" \
+                 to=\"refunded\" event=\"refund\" outcome=failed \
+                 error=CallbackFailed",
+            ]
+        );
+    }
+
+    /// Install a global logger writing to [`SEEN`].
+    #[cfg(feature = "log")]
+    fn logger() {
+        struct Capture;
+
+        impl log::Log for Capture {
+            fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record<'_>) {
+                push(format!(
+                    "log {} {} {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                ));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static CAPTURE: Capture = Capture;
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn log_records() {
+        logger();
+        let mut m = order();
+        m.trigger(&"pay").unwrap();
+        assert_eq!(take("log"), ["DEBUG nanomachine trigger outcome=ok"]);
+
+        let mut m = order();
+        m.instrument();
+        m.trigger(&"pay").unwrap();
+        m.trigger(&"ship").unwrap_err();
+        m.trigger(&"refund").unwrap_err();
+        m.trigger_all(&["cancel"]).unwrap_err();
+        assert_eq!(
+            take("log"),
+            [
+                "DEBUG nanomachine trigger from=\"created\" to=\"paid\" \
+                 event=\"pay\" outcome=ok",
+                "DEBUG nanomachine trigger from=\"paid\" to=\"paid\" \
+                 event=\"ship\" outcome=rejected error=GuardRejected",
+                "INFO nanomachine trigger from=\"paid\" to=\"refunded\" \
+                 event=\"refund\" outcome=failed error=CallbackFailed",
+                "DEBUG nanomachine trigger from=\"refunded\" \
+                 to=\"refunded\" event=\"cancel\" outcome=rejected \
+                 error=EventInvalid",
+            ]
+        );
+
+        let mut compiled = order().compile();
+        compiled.trigger(&"cancel").unwrap_err();
+        assert_eq!(
+            take("log"),
+            ["DEBUG nanomachine trigger outcome=rejected error=EventInvalid"]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    borrow::Borrow,
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    hash::Hash,
+};
+
+use hashbrown::HashMap;
+
+use crate::{Machine, Payload, TriggerError};
+
+/// A small, seeded pseudo-random number generator.
+///
+/// It implements `SplitMix64`, which is fast and good enough to pick events,
+/// but not suitable for anything that needs unpredictable numbers.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a seed. The same seed always yields the same
+    /// numbers.
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// The next number, uniformly distributed over all `u64`s.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_4d1c_e4e5_b9b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bound` is zero.
+    pub fn below(&mut self, bound: usize) -> usize {
+        assert!(bound > 0, "the bound must be positive");
+        pick(self.next_u64(), bound)
+    }
+}
+
+/// Map `choice` to an index in `0..len`.
+pub(crate) fn pick(choice: u64, len: usize) -> usize {
+    // `len` fits in a `u64` and the result is below `len`, so neither
+    // conversion can fail.
+    let len = u64::try_from(len).unwrap_or(u64::MAX);
+    usize::try_from(choice % len).unwrap_or_default()
+}
+
+/// Drives a [`Machine`] through random sequences of valid events.
+///
+/// At every step the walker picks one of the events the machine can take from
+/// its current state, i.e. one of its
+/// [`triggerable_events`](Machine::triggerable_events) whose guard allows it,
+/// and triggers it, running every callback along the way. Events are picked in
+/// a deterministic way from the walker's seed, so a walk that makes a
+/// callback panic or fail can be replayed by using the same seed on the same
+/// machine.
+///
+/// # Examples
+///
+/// ```rust
+/// use nanomachine::{Machine, Walker};
+///
+/// let mut nano = Machine::new("locked");
+/// nano.when("insert coin", "locked", "unlocked");
+/// nano.when("turn knob", "unlocked", "locked");
+/// nano.on_enter("unlocked", |_| { /* Must not panic. */ });
+///
+/// let trace = Walker::new(42).walk(&mut nano, 100).unwrap();
+/// assert_eq!(trace.len(), 100);
+/// assert_eq!(trace[0], "insert coin");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Walker {
+    rng: Rng,
+}
+
+impl Walker {
+    /// Create a walker from a seed.
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Walker { rng: Rng::new(seed) }
+    }
+
+    /// The generator the walker picks events with.
+    #[inline]
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Trigger up to `steps` random events on `machine`, without any payload.
+    ///
+    /// Returns the events that were triggered, which may be fewer than
+    /// `steps` if the machine reaches a state where no event can be taken.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WalkError`] if a fallible callback fails, carrying the
+    /// events triggered up to and including the failing one.
+    pub fn walk<S, E, C, P>(
+        &mut self,
+        machine: &mut Machine<S, E, C, P>,
+        steps: usize,
+    ) -> Result<Vec<E>, WalkError<S, E>>
+    where
+        S: Eq + Hash + Clone,
+        E: Eq + Hash + Clone + Ord,
+        P: ?Sized + Payload<()>,
+    {
+        self.walk_impl(machine, steps, |_, _| P::wrap(&()))
+    }
+
+    /// Trigger up to `steps` random events on `machine`, each with a payload
+    /// built by `payload` from the event and the walker's generator.
+    ///
+    /// See [`Walker::walk`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Walker::walk`].
+    pub fn walk_with<S, E, C, P, G>(
+        &mut self,
+        machine: &mut Machine<S, E, C, P>,
+        steps: usize,
+        mut payload: G,
+    ) -> Result<Vec<E>, WalkError<S, E>>
+    where
+        S: Eq + Hash + Clone,
+        E: Eq + Hash + Clone + Ord,
+        P: ?Sized,
+        G: FnMut(&E, &mut Rng) -> Box<P>,
+    {
+        self.walk_impl(machine, steps, |event, rng| payload(event, rng))
+    }
+
+    fn walk_impl<S, E, C, P, G, R>(
+        &mut self,
+        machine: &mut Machine<S, E, C, P>,
+        steps: usize,
+        mut payload: G,
+    ) -> Result<Vec<E>, WalkError<S, E>>
+    where
+        S: Eq + Hash + Clone,
+        E: Eq + Hash + Clone + Ord,
+        P: ?Sized,
+        G: FnMut(&E, &mut Rng) -> R,
+        R: Borrow<P>,
+    {
+        let mut trace = Vec::new();
+        for _ in 0..steps {
+            let mut events: Vec<&E> = machine
+                .triggerable_events()
+                .filter(|event| machine.can_trigger(event))
+                .collect();
+            if events.is_empty() {
+                break;
+            }
+            // Sort so that the walk only depends on the seed.
+            events.sort_unstable();
+            let event = events[self.rng.below(events.len())].clone();
+
+            trace.push(event.clone());
+            let payload = payload(&event, &mut self.rng);
+            if let Err(error) = machine.fire(&event, payload.borrow()) {
+                return Err(WalkError { trace, error });
+            }
+        }
+        Ok(trace)
+    }
+}
+
+/// An error returned when a callback fails during a [`Walker`] walk.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WalkError<S, E> {
+    trace: Vec<E>,
+    error: TriggerError<S, E>,
+}
+
+impl<S, E> WalkError<S, E> {
+    /// The events triggered during the walk, ending with the one that failed.
+    #[inline]
+    #[must_use]
+    pub fn trace(&self) -> &[E] {
+        &self.trace
+    }
+
+    /// The error returned by the failing trigger.
+    #[inline]
+    #[must_use]
+    pub fn error(&self) -> &TriggerError<S, E> {
+        &self.error
+    }
+}
+
+impl<S: Debug, E: Debug> Display for WalkError<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} after {:?}", self.error, self.trace)
+    }
+}
+
+impl<S: Debug, E: Debug> Error for WalkError<S, E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// The transition structure of a machine, used to turn a sequence of choices
+/// into a sequence of valid events.
+///
+/// Guards, actions and callbacks are ignored, so the events are valid for the
+/// machine's definition.
+#[cfg_attr(
+    not(any(feature = "proptest", feature = "arbitrary")),
+    allow(dead_code)
+)]
+#[derive(Debug, Clone)]
+pub(crate) struct Sequences<S, E> {
+    start: S,
+    /// The events leaving each state along with their targets, sorted by
+    /// event.
+    outgoing: HashMap<S, Vec<(E, S)>>,
+}
+
+#[cfg_attr(
+    not(any(feature = "proptest", feature = "arbitrary")),
+    allow(dead_code)
+)]
+impl<S, E> Sequences<S, E>
+where
+    S: Eq + Hash + Clone,
+    E: Eq + Hash + Clone + Ord,
+{
+    pub(crate) fn new<C, P: ?Sized>(machine: &Machine<S, E, C, P>) -> Self {
+        let mut outgoing: HashMap<S, Vec<(E, S)>> = HashMap::new();
+        for (event, from, to) in machine.transitions() {
+            outgoing
+                .entry(from.clone())
+                .or_default()
+                .push((event.clone(), to.clone()));
+        }
+        for events in outgoing.values_mut() {
+            events.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        Sequences { start: machine.state.clone(), outgoing }
+    }
+
+    /// The events picked by `choices`, stopping early at a state without
+    /// transitions.
+    pub(crate) fn replay<I>(&self, choices: I) -> Vec<E>
+    where
+        I: IntoIterator<Item = u64>,
+    {
+        let mut state = &self.start;
+        let mut events = Vec::new();
+        for choice in choices {
+            let Some(outgoing) = self.outgoing.get(state) else {
+                break;
+            };
+            let (event, target) = &outgoing[pick(choice, outgoing.len())];
+            events.push(event.clone());
+            state = target;
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{
+        boxed::Box,
+        rc::Rc,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+    use core::{any::Any, cell::RefCell};
+
+    use super::{Rng, Sequences, Walker};
+    use crate::{Machine, MachineError, testing::turnstile};
+
+    /// Whether `trace` is a valid sequence of events for [`turnstile`].
+    fn is_valid(trace: &[&'static str]) -> bool {
+        let mut nano = turnstile();
+        trace.iter().all(|event| nano.trigger(event).is_ok())
+    }
+
+    #[test]
+    fn rng_is_seeded() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+
+        let a: Vec<_> = (0..8).map(|_| a.next_u64()).collect();
+        let b: Vec<_> = (0..8).map(|_| b.next_u64()).collect();
+        let c: Vec<_> = (0..8).map(|_| c.next_u64()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn rng_below() {
+        let mut rng = Rng::new(1);
+        let mut seen = [false; 5];
+        for _ in 0..100 {
+            seen[rng.below(5)] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+    }
+
+    #[test]
+    fn walks_valid_events() {
+        let mut nano = turnstile();
+
+        let trace = Walker::new(3).walk(&mut nano, 50).unwrap();
+        assert_eq!(trace.len(), 50);
+        assert!(is_valid(&trace));
+        assert!(trace.contains(&"kick"));
+        assert!(trace.contains(&"turn knob"));
+    }
+
+    #[test]
+    fn walks_are_reproducible() {
+        let first = Walker::new(11).walk(&mut turnstile(), 30).unwrap();
+        let second = Walker::new(11).walk(&mut turnstile(), 30).unwrap();
+        let other = Walker::new(12).walk(&mut turnstile(), 30).unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn walks_stop_at_dead_ends() {
+        let mut nano = Machine::new(0);
+        nano.when('a', 0, 1);
+        nano.when('b', 1, 2);
+
+        let trace = Walker::new(0).walk(&mut nano, 10).unwrap();
+        assert_eq!(trace, ['a', 'b']);
+        assert_eq!(*nano.state(), 2);
+    }
+
+    #[test]
+    fn walks_respect_guards() {
+        let mut nano = Machine::with_context(0, false);
+        nano.when('a', 0, 0);
+        nano.when_guarded('b', 0, 1, |open| *open);
+
+        let trace = Walker::new(5).walk(&mut nano, 20).unwrap();
+        assert!(trace.iter().all(|e| *e == 'a'));
+
+        *nano.context_mut() = true;
+        let trace = Walker::new(5).walk(&mut nano, 20).unwrap();
+        assert!(trace.contains(&'b'));
+    }
+
+    #[test]
+    fn walks_run_callbacks() {
+        let entered = Rc::new(RefCell::new(0));
+        let mut nano = turnstile();
+        let e = entered.clone();
+        nano.on_enter("unlocked", move |_| *e.borrow_mut() += 1);
+
+        let trace = Walker::new(9).walk(&mut nano, 40).unwrap();
+        let coins = trace.iter().filter(|e| **e == "insert coin").count();
+        assert_eq!(*entered.borrow(), coins);
+    }
+
+    #[test]
+    fn walks_with_payloads() {
+        let paid = Rc::new(RefCell::new(Vec::new()));
+        let mut nano = turnstile();
+        let p = paid.clone();
+        nano.on_enter_with("unlocked", move |_, cents: &u32| {
+            p.borrow_mut().push(*cents);
+        });
+
+        let mut walker = Walker::new(2);
+        let trace = walker
+            .walk_with(&mut nano, 40, |event, rng| -> Box<dyn Any> {
+                if *event == "insert coin" {
+                    Box::new(u32::try_from(rng.below(100)).unwrap())
+                } else {
+                    Box::new(String::from("no payload"))
+                }
+            })
+            .unwrap();
+
+        let coins = trace.iter().filter(|e| **e == "insert coin").count();
+        assert_eq!(paid.borrow().len(), coins);
+        assert!(paid.borrow().iter().all(|cents| *cents < 100));
+    }
+
+    #[test]
+    fn walks_with_typed_payloads() {
+        let mut nano = Machine::<_, _, (), u8>::typed(0);
+        nano.when('a', 0, 0);
+        nano.try_on_transition_with(|_, n: &u8| {
+            if *n == 7 { Err("seven".into()) } else { Ok(()) }
+        });
+
+        let err = Walker::new(1)
+            .walk_with(&mut nano, 1000, |_, rng| {
+                Box::new(u8::try_from(rng.below(10)).unwrap())
+            })
+            .unwrap_err();
+
+        assert!(matches!(err.error().error(), MachineError::CallbackFailed(_)));
+        assert_eq!(err.trace().last(), Some(&'a'));
+        assert!(err.to_string().contains("after"));
+    }
+
+    #[test]
+    fn sequences_replay_valid_events() {
+        let sequences = Sequences::new(&turnstile());
+
+        for seed in 0..20 {
+            let mut rng = Rng::new(seed);
+            let choices: Vec<u64> = (0..10).map(|_| rng.next_u64()).collect();
+            let trace = sequences.replay(choices);
+            assert_eq!(trace.len(), 10);
+            assert!(is_valid(&trace));
+        }
+        assert_eq!(
+            sequences.replay(vec![0, 0, 0]),
+            ["insert coin", "turn knob", "insert coin"]
+        );
+    }
+}